@@ -1,80 +1,467 @@
-use crate::cli::LdkUserInfo;
+use crate::channel_policy::InboundChannelPolicy;
+use crate::cli::{ChainSource, FeeSource, LdkUserInfo, WalletKind};
+use crate::fee_policy::{self, FeePolicy};
+use crate::rgs::{self, RgsSource};
+use crate::rpc;
 use bitcoin::network::constants::Network;
+use bitcoin::secp256k1::PublicKey;
 use lightning::ln::msgs::SocketAddress;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+/// Settings gathered from the config file and the command line, keyed by option name. Most
+/// options take a single value, but some (e.g. `announced-listen-addr`) may be repeated.
+type Settings = HashMap<String, Vec<String>>;
+
+/// The name of the config file we look for in the storage directory if `--config` isn't given.
+const DEFAULT_CONFIG_FILE_NAME: &str = "ldk.conf";
+
+/// Every option which may be set in the config file or as a `--<option>=<value>` flag.
+const CONFIG_KEYS: &[&str] = &[
+	"config",
+	"bitcoind-rpc-host",
+	"bitcoind-rpc-port",
+	"bitcoind-rpc-username",
+	"bitcoind-rpc-password",
+	"storage-dir",
+	"peer-listening-port",
+	"network",
+	"node-name",
+	"announced-listen-addr",
+	"i-understand-mainnet-risk",
+	"keys-seed-backup-path",
+	"restore-from-mnemonic",
+	"verify-mnemonic",
+	"encrypt-keys-seed",
+	"seed-passphrase-fd",
+	"daemon",
+	"rpc-bind",
+	"rpc-socket",
+	"rgs-source",
+	"rgs-sync-interval-secs",
+	"chain-source",
+	"esplora-url",
+	"electrum-url",
+	"bitcoind-zmq-hashblock",
+	"bitcoind-rest",
+	"fee-source",
+	"fee-estimates-file",
+	"onchain-wallet",
+	"inbound-min-channel-sats",
+	"inbound-max-channel-sats",
+	"inbound-allow-peer",
+	"inbound-deny-peer",
+	"inbound-require-anchors",
+	"inbound-max-pending-per-peer",
+	"inbound-reject-private",
+	"trusted-peer",
+];
+
+/// Options which may be given more than once, all other options must be given at most once per
+/// source.
+const MULTI_VALUE_KEYS: &[&str] =
+	&["announced-listen-addr", "inbound-allow-peer", "inbound-deny-peer", "trusted-peer"];
+
+fn print_usage() {
+	println!("ldk-tutorial-node can be started with a config file and/or command line flags:");
+	println!("  `cargo run -- --storage-dir=<ldk_storage_directory_path> [--config=<config_file_path>] [--<option>=<value>]*`");
+	println!("or with the legacy positional arguments:");
+	println!("  `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*]`");
+	println!("Unless `--config` is given, options are also read from `<ldk_storage_directory_path>/{}` if it exists.", DEFAULT_CONFIG_FILE_NAME);
+	println!("Available options: {}", CONFIG_KEYS.join(", "));
+	println!(
+		"Fee policy options: fee.<target>.<setting>, where <target> is one of {} and <setting> one of {}",
+		fee_policy::CONFIRMATION_TARGETS.iter().map(|(_, name)| *name).collect::<Vec<_>>().join(", "),
+		fee_policy::POLICY_SETTINGS.join(", ")
+	);
+}
+
 pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
-	if env::args().len() < 3 {
-		println!("ldk-tutorial-node requires at least 2 arguments: `cargo run [<bitcoind-rpc-username>:<bitcoind-rpc-password>@]<bitcoind-rpc-host>:<bitcoind-rpc-port> ldk_storage_directory_path [<ldk-incoming-peer-listening-port>] [bitcoin-network] [announced-node-name announced-listen-addr*]`");
+	let args: Vec<String> = env::args().skip(1).collect();
+	if args.is_empty() {
+		print_usage();
+		return Err(());
+	}
+	let settings = collect_settings(&args)?;
+	parse_user_info(&settings)
+}
+
+/// Merges the config file, the positional arguments and the `--<option>=<value>` flags, in
+/// increasing order of precedence.
+fn collect_settings(args: &[String]) -> Result<Settings, ()> {
+	let mut positional_args = Vec::new();
+	let mut flag_settings = Settings::new();
+	for arg in args {
+		match arg.strip_prefix("--") {
+			Some(flag) => {
+				// A bare `--<option>` is shorthand for `--<option>=true`.
+				let (key, value) = flag.split_once('=').unwrap_or((flag, "true"));
+				insert_setting(&mut flag_settings, key, value)
+					.map_err(|e| println!("ERROR: invalid flag `{}`: {}", arg, e))?;
+			}
+			None => positional_args.push(arg.as_str()),
+		}
+	}
+	let positional_settings = parse_positional_args(&positional_args)?;
+
+	let config_path = match single_value(&flag_settings, "config")? {
+		Some(path) => Some(PathBuf::from(path)),
+		None => single_value(&flag_settings, "storage-dir")?
+			.or(single_value(&positional_settings, "storage-dir")?)
+			.map(|dir| Path::new(dir).join(DEFAULT_CONFIG_FILE_NAME))
+			.filter(|path| path.exists()),
+	};
+	let mut settings = match config_path {
+		Some(path) => read_config_file(&path)?,
+		None => Settings::new(),
+	};
+	settings.extend(positional_settings);
+	settings.extend(flag_settings);
+	Ok(settings)
+}
+
+fn insert_setting(settings: &mut Settings, key: &str, value: &str) -> Result<(), String> {
+	if !CONFIG_KEYS.contains(&key) && !fee_policy::is_config_key(key) {
+		return Err(format!("unknown option `{}`", key));
+	}
+	let values = settings.entry(key.to_string()).or_default();
+	if !values.is_empty() && !MULTI_VALUE_KEYS.contains(&key) {
+		return Err(format!("option `{}` given more than once", key));
+	}
+	values.push(value.to_string());
+	Ok(())
+}
+
+fn read_config_file(path: &Path) -> Result<Settings, ()> {
+	let contents = fs::read_to_string(path).map_err(|e| {
+		println!("ERROR: unable to read config file {}: {}", path.display(), e);
+	})?;
+	parse_config(&contents).map_err(|e| println!("ERROR: in config file {}: {}", path.display(), e))
+}
+
+/// Parses `<option> = <value>` lines, ignoring blank lines and lines starting with `#` or `;`.
+fn parse_config(contents: &str) -> Result<Settings, String> {
+	let mut settings = Settings::new();
+	for (idx, line) in contents.lines().enumerate() {
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+			continue;
+		}
+		let (key, value) = match line.split_once('=') {
+			Some((key, value)) => (key.trim(), value.trim()),
+			None => return Err(format!("line {}: expected `<option> = <value>`", idx + 1)),
+		};
+		if key == "config" {
+			return Err(format!(
+				"line {}: `config` may only be given on the command line",
+				idx + 1
+			));
+		}
+		insert_setting(&mut settings, key, value)
+			.map_err(|e| format!("line {}: {}", idx + 1, e))?;
+	}
+	Ok(settings)
+}
+
+/// Maps the legacy positional argument list onto named options.
+fn parse_positional_args(args: &[&str]) -> Result<Settings, ()> {
+	let mut settings = Settings::new();
+	if args.is_empty() {
+		return Ok(settings);
+	}
+	if args.len() < 2 {
+		println!("ERROR: positional arguments require at least the bitcoind RPC info and the LDK storage directory path");
+		print_usage();
 		return Err(());
 	}
-	let bitcoind_rpc_info = env::args().skip(1).next().unwrap();
-	let bitcoind_rpc_info_parts: Vec<&str> = bitcoind_rpc_info.rsplitn(2, "@").collect();
+	let mut set = |key: &str, value: &str| {
+		settings.entry(key.to_string()).or_default().push(value.to_string())
+	};
 
-	// Parse rpc auth after getting network for default .cookie location
-	let bitcoind_rpc_path: Vec<&str> = bitcoind_rpc_info_parts[0].split(":").collect();
+	let bitcoind_rpc_info_parts: Vec<&str> = args[0].rsplitn(2, '@').collect();
+	let bitcoind_rpc_path: Vec<&str> = bitcoind_rpc_info_parts[0].split(':').collect();
 	if bitcoind_rpc_path.len() != 2 {
 		println!("ERROR: bad bitcoind RPC path provided");
 		return Err(());
 	}
-	let bitcoind_rpc_host = bitcoind_rpc_path[0].to_string();
-	let bitcoind_rpc_port = bitcoind_rpc_path[1].parse::<u16>().unwrap();
+	set("bitcoind-rpc-host", bitcoind_rpc_path[0]);
+	set("bitcoind-rpc-port", bitcoind_rpc_path[1]);
+	if bitcoind_rpc_info_parts.len() == 2 {
+		let (rpc_username, rpc_password) = parse_rpc_auth(bitcoind_rpc_info_parts[1])?;
+		set("bitcoind-rpc-username", &rpc_username);
+		set("bitcoind-rpc-password", &rpc_password);
+	}
+	set("storage-dir", args[1]);
 
-	let ldk_storage_dir_path = env::args().skip(2).next().unwrap();
+	// The peer listening port is optional, so anything which isn't a port is taken to be the
+	// network.
+	let mut remaining = args[2..].iter();
+	let mut next = remaining.next();
+	if let Some(port) = next.filter(|p| p.parse::<u16>().is_ok()) {
+		set("peer-listening-port", port);
+		next = remaining.next();
+	}
+	if let Some(network) = next {
+		set("network", network);
+	}
+	if let Some(node_name) = remaining.next() {
+		set("node-name", node_name);
+	}
+	for addr in remaining {
+		set("announced-listen-addr", addr);
+	}
+	Ok(settings)
+}
+
+fn single_value<'a>(settings: &'a Settings, key: &str) -> Result<Option<&'a str>, ()> {
+	match settings.get(key).map(|values| values.as_slice()) {
+		None | Some([]) => Ok(None),
+		Some([value]) => Ok(Some(value.as_str())),
+		Some(_) => {
+			println!("ERROR: option `{}` given more than once", key);
+			Err(())
+		}
+	}
+}
+
+fn parse_value<T: FromStr>(settings: &Settings, key: &str) -> Result<Option<T>, ()> {
+	match single_value(settings, key)? {
+		Some(value) => match value.parse() {
+			Ok(v) => Ok(Some(v)),
+			Err(_) => {
+				println!("ERROR: invalid value `{}` for option `{}`", value, key);
+				Err(())
+			}
+		},
+		None => Ok(None),
+	}
+}
 
-	let mut ldk_peer_port_set = true;
-	let ldk_peer_listening_port: u16 = match env::args().skip(3).next().map(|p| p.parse()) {
-		Some(Ok(p)) => p,
-		Some(Err(_)) => {
-			ldk_peer_port_set = false;
-			9735
+fn parse_network(network: &str) -> Result<Network, ()> {
+	match network {
+		"testnet" => Ok(Network::Testnet),
+		"regtest" => Ok(Network::Regtest),
+		"signet" => Ok(Network::Signet),
+		"bitcoin" | "mainnet" => Ok(Network::Bitcoin),
+		net => {
+			println!(
+				"ERROR: unsupported network `{}`. Options are: `regtest`, `testnet`, `signet` and `mainnet`",
+				net
+			);
+			Err(())
 		}
+	}
+}
+
+fn parse_bool(settings: &Settings, key: &str) -> Result<bool, ()> {
+	match single_value(settings, key)? {
+		None | Some("false") => Ok(false),
+		Some("true") => Ok(true),
+		Some(value) => {
+			println!(
+				"ERROR: invalid value `{}` for option `{}`, expected `true` or `false`",
+				value, key
+			);
+			Err(())
+		}
+	}
+}
+
+fn parse_user_info(settings: &Settings) -> Result<LdkUserInfo, ()> {
+	let ldk_storage_dir_path = match single_value(settings, "storage-dir")? {
+		Some(dir) => dir.to_string(),
 		None => {
-			ldk_peer_port_set = false;
-			9735
+			println!("ERROR: the LDK storage directory must be set with `storage-dir`");
+			print_usage();
+			return Err(());
 		}
 	};
 
-	let mut arg_idx = match ldk_peer_port_set {
-		true => 4,
-		false => 3,
+	let network = match single_value(settings, "network")? {
+		Some(network) => parse_network(network)?,
+		None => Network::Testnet,
 	};
-	let network: Network = match env::args().skip(arg_idx).next().as_ref().map(String::as_str) {
-		Some("testnet") => Network::Testnet,
-		Some("regtest") => Network::Regtest,
-		Some("signet") => Network::Signet,
-		Some(net) => {
-			panic!("Unsupported network provided. Options are: `regtest`, `testnet`, and `signet`. Got {}", net);
+
+	let keys_seed_backup_path = single_value(settings, "keys-seed-backup-path")?.map(String::from);
+	if network == Network::Bitcoin {
+		if !parse_bool(settings, "i-understand-mainnet-risk")? {
+			println!("ERROR: this is sample code which has not been audited and may lose funds. To run it on mainnet anyway, set `i-understand-mainnet-risk`");
+			return Err(());
+		}
+		if keys_seed_backup_path.is_none() {
+			println!("ERROR: running on mainnet requires `keys-seed-backup-path`, ideally pointing to a separate disk");
+			return Err(());
+		}
+	}
+
+	let esplora_url = single_value(settings, "esplora-url")?;
+	let electrum_url = single_value(settings, "electrum-url")?;
+	let chain_source = match (single_value(settings, "chain-source")?, esplora_url, electrum_url) {
+		(None, None, None) | (Some("bitcoind"), None, None) => ChainSource::Bitcoind,
+		(Some("esplora"), Some(url), None) => {
+			if !url.starts_with("http://") && !url.starts_with("https://") {
+				println!("ERROR: `esplora-url` must be an http:// or https:// URL");
+				return Err(());
+			}
+			ChainSource::Esplora { url: url.trim_end_matches('/').to_string() }
+		}
+		(Some("electrum"), None, Some(url)) => {
+			if !url.starts_with("tcp://") && !url.starts_with("ssl://") {
+				println!("ERROR: `electrum-url` must be a tcp:// or ssl:// URL");
+				return Err(());
+			}
+			ChainSource::Electrum { url: url.to_string() }
+		}
+		(Some("esplora"), None, _) => {
+			println!("ERROR: `chain-source=esplora` requires `esplora-url`");
+			return Err(());
+		}
+		(Some("electrum"), _, None) => {
+			println!("ERROR: `chain-source=electrum` requires `electrum-url`");
+			return Err(());
+		}
+		(None, _, _) | (Some("bitcoind" | "esplora" | "electrum"), _, _) => {
+			println!(
+				"ERROR: `esplora-url` and `electrum-url` require `chain-source=esplora` and `chain-source=electrum` respectively"
+			);
+			return Err(());
+		}
+		(Some(source), _, _) => {
+			println!(
+				"ERROR: invalid value `{}` for option `chain-source`, expected `bitcoind`, `esplora` or `electrum`",
+				source
+			);
+			return Err(());
 		}
-		None => Network::Testnet,
 	};
 
-	let (bitcoind_rpc_username, bitcoind_rpc_password) = if bitcoind_rpc_info_parts.len() == 1 {
-		get_rpc_auth_from_env_vars()
-			.or(get_rpc_auth_from_env_file(None))
-			.or(get_rpc_auth_from_cookie(None, Some(network), None))
-			.or({
-				println!("ERROR: unable to get bitcoind RPC username and password");
-				print_rpc_auth_help();
-				Err(())
-			})?
-	} else if bitcoind_rpc_info_parts.len() == 2 {
-		parse_rpc_auth(bitcoind_rpc_info_parts[1])?
-	} else {
-		println!("ERROR: bad bitcoind RPC URL provided");
+	let bitcoind_rpc_host =
+		single_value(settings, "bitcoind-rpc-host")?.unwrap_or("127.0.0.1").to_string();
+	let bitcoind_rpc_port = parse_value(settings, "bitcoind-rpc-port")?.unwrap_or(match network {
+		Network::Bitcoin => 8332,
+		Network::Regtest => 18443,
+		Network::Signet => 38332,
+		Network::Testnet | _ => 18332,
+	});
+
+	let (bitcoind_rpc_username, bitcoind_rpc_password) =
+		match (
+			single_value(settings, "bitcoind-rpc-username")?,
+			single_value(settings, "bitcoind-rpc-password")?,
+		) {
+			(Some(username), Some(password)) => (username.to_string(), password.to_string()),
+			// Esplora and Electrum replace bitcoind entirely, so we don't need its credentials.
+			(None, None) if chain_source != ChainSource::Bitcoind => (String::new(), String::new()),
+			(None, None) => get_rpc_auth_from_env_vars()
+				.or_else(|_| get_rpc_auth_from_env_file(None))
+				.or_else(|_| get_rpc_auth_from_cookie(None, Some(network), None))
+				.map_err(|_| {
+					println!("ERROR: unable to get bitcoind RPC username and password");
+					print_rpc_auth_help();
+				})?,
+			_ => {
+				println!("ERROR: `bitcoind-rpc-username` and `bitcoind-rpc-password` must be given together");
+				return Err(());
+			}
+		};
+
+	let restore_from_mnemonic = parse_bool(settings, "restore-from-mnemonic")?;
+	let verify_mnemonic = parse_bool(settings, "verify-mnemonic")?;
+	let encrypt_keys_seed = parse_bool(settings, "encrypt-keys-seed")?;
+	let seed_passphrase_fd = parse_value(settings, "seed-passphrase-fd")?;
+
+	let daemon = parse_bool(settings, "daemon")?;
+	let mut rpc_bind: Option<SocketAddr> = parse_value(settings, "rpc-bind")?;
+	let rpc_socket = single_value(settings, "rpc-socket")?.map(PathBuf::from);
+	if rpc_bind.is_some() && rpc_socket.is_some() {
+		println!("ERROR: only one of `rpc-bind` and `rpc-socket` may be set");
+		return Err(());
+	}
+	if cfg!(not(unix)) && rpc_socket.is_some() {
+		println!("ERROR: `rpc-socket` is only supported on unix platforms");
+		return Err(());
+	}
+	if daemon && rpc_bind.is_none() && rpc_socket.is_none() {
+		// Without the prompt the control API is the only way to drive the node.
+		rpc_bind = Some(rpc::DEFAULT_RPC_BIND.parse().unwrap());
+	}
+
+	let rgs_source = match single_value(settings, "rgs-source")? {
+		Some(source) => match RgsSource::parse(source) {
+			Ok(source) => Some(source),
+			Err(e) => {
+				println!("ERROR: invalid `rgs-source`: {}", e);
+				return Err(());
+			}
+		},
+		None => None,
+	};
+	let rgs_sync_interval_secs =
+		parse_value(settings, "rgs-sync-interval-secs")?.unwrap_or(rgs::DEFAULT_SYNC_INTERVAL_SECS);
+	if rgs_sync_interval_secs == 0 {
+		println!("ERROR: `rgs-sync-interval-secs` must be greater than 0");
 		return Err(());
+	}
+
+	let bitcoind_zmq_hashblock = match single_value(settings, "bitcoind-zmq-hashblock")? {
+		Some(endpoint) if !endpoint.starts_with("tcp://") => {
+			println!("ERROR: `bitcoind-zmq-hashblock` must be a tcp:// endpoint");
+			return Err(());
+		}
+		endpoint => endpoint.map(|endpoint| endpoint.to_string()),
 	};
 
-	let ldk_announced_node_name = match env::args().skip(arg_idx + 1).next().as_ref() {
+	let fee_policy = parse_fee_policy(settings)?;
+	let fee_estimates_file = single_value(settings, "fee-estimates-file")?.map(PathBuf::from);
+	let fee_source = match (single_value(settings, "fee-source")?, fee_estimates_file) {
+		(None, None) | (Some("chain"), None) => FeeSource::Chain,
+		(Some("static"), estimates_file) => FeeSource::Static { estimates_file },
+		(None, Some(_)) | (Some("chain"), Some(_)) => {
+			println!("ERROR: `fee-estimates-file` requires `fee-source=static`");
+			return Err(());
+		}
+		(Some(source), _) => {
+			println!(
+				"ERROR: invalid value `{}` for option `fee-source`, expected `chain` or `static`",
+				source
+			);
+			return Err(());
+		}
+	};
+
+	// Without bitcoind, the internal wallet is the only one we have. It finds its coins by scanning
+	// blocks from bitcoind, or from the history of its scripts which Esplora and Electrum index.
+	let onchain_wallet = match single_value(settings, "onchain-wallet")? {
+		None if chain_source == ChainSource::Bitcoind => WalletKind::Bitcoind,
+		Some("bitcoind") if chain_source == ChainSource::Bitcoind => WalletKind::Bitcoind,
+		None | Some("internal") => WalletKind::Internal,
+		Some("bitcoind") => {
+			println!("ERROR: `onchain-wallet=bitcoind` requires `chain-source=bitcoind`, use `onchain-wallet=internal` instead");
+			return Err(());
+		}
+		Some(wallet) => {
+			println!(
+				"ERROR: invalid value `{}` for option `onchain-wallet`, expected `bitcoind` or `internal`",
+				wallet
+			);
+			return Err(());
+		}
+	};
+
+	let inbound_channel_policy = parse_inbound_channel_policy(settings)?;
+
+	let ldk_peer_listening_port = parse_value(settings, "peer-listening-port")?.unwrap_or(9735);
+
+	let ldk_announced_node_name = match single_value(settings, "node-name")? {
 		Some(s) => {
 			if s.len() > 32 {
-				panic!("Node Alias can not be longer than 32 bytes");
+				println!("ERROR: node-name can not be longer than 32 bytes");
+				return Err(());
 			}
-			arg_idx += 1;
 			let mut bytes = [0; 32];
 			bytes[..s.len()].copy_from_slice(s.as_bytes());
 			bytes
@@ -83,16 +470,16 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 	};
 
 	let mut ldk_announced_listen_addr = Vec::new();
-	loop {
-		match env::args().skip(arg_idx + 1).next().as_ref() {
-			Some(s) => match SocketAddress::from_str(s) {
-				Ok(sa) => {
-					ldk_announced_listen_addr.push(sa);
-					arg_idx += 1;
-				}
-				Err(_) => panic!("Failed to parse announced-listen-addr into a socket address"),
-			},
-			None => break,
+	for addr in settings.get("announced-listen-addr").into_iter().flatten() {
+		match SocketAddress::from_str(addr) {
+			Ok(sa) => ldk_announced_listen_addr.push(sa),
+			Err(_) => {
+				println!(
+					"ERROR: failed to parse announced-listen-addr `{}` into a socket address",
+					addr
+				);
+				return Err(());
+			}
 		}
 	}
 
@@ -106,9 +493,78 @@ pub(crate) fn parse_startup_args() -> Result<LdkUserInfo, ()> {
 		ldk_announced_listen_addr,
 		ldk_announced_node_name,
 		network,
+		keys_seed_backup_path,
+		restore_from_mnemonic,
+		verify_mnemonic,
+		encrypt_keys_seed,
+		seed_passphrase_fd,
+		daemon,
+		rpc_bind,
+		rpc_socket,
+		rgs_source,
+		rgs_sync_interval_secs,
+		chain_source,
+		bitcoind_zmq_hashblock,
+		bitcoind_rest: parse_bool(settings, "bitcoind-rest")?,
+		fee_policy,
+		fee_source,
+		onchain_wallet,
+		inbound_channel_policy,
 	})
 }
 
+/// Collects the `fee.<target>.<setting>` options into a [`FeePolicy`].
+fn parse_fee_policy(settings: &Settings) -> Result<FeePolicy, ()> {
+	let mut policy_settings = Vec::new();
+	for key in settings.keys().filter(|key| fee_policy::is_config_key(key)) {
+		let mut parts = key.splitn(3, '.').skip(1);
+		let (name, setting) = (parts.next().unwrap(), parts.next().unwrap());
+		if let Some(value) = single_value(settings, key)? {
+			policy_settings.push((name, setting, value));
+		}
+	}
+	FeePolicy::from_settings(policy_settings).map_err(|e| println!("ERROR: {}", e))
+}
+
+fn parse_node_ids(settings: &Settings, key: &str) -> Result<HashSet<PublicKey>, ()> {
+	let mut node_ids = HashSet::new();
+	for node_id in settings.get(key).into_iter().flatten() {
+		match PublicKey::from_str(node_id) {
+			Ok(node_id) => node_ids.insert(node_id),
+			Err(_) => {
+				println!("ERROR: invalid node id `{}` for option `{}`", node_id, key);
+				return Err(());
+			}
+		};
+	}
+	Ok(node_ids)
+}
+
+/// Collects the `inbound-*` and `trusted-peer` options into an [`InboundChannelPolicy`].
+fn parse_inbound_channel_policy(settings: &Settings) -> Result<InboundChannelPolicy, ()> {
+	let policy = InboundChannelPolicy {
+		min_channel_sats: parse_value(settings, "inbound-min-channel-sats")?,
+		max_channel_sats: parse_value(settings, "inbound-max-channel-sats")?,
+		allowed_peers: parse_node_ids(settings, "inbound-allow-peer")?,
+		denied_peers: parse_node_ids(settings, "inbound-deny-peer")?,
+		require_anchors: parse_bool(settings, "inbound-require-anchors")?,
+		max_pending_channels_per_peer: parse_value(settings, "inbound-max-pending-per-peer")?,
+		reject_private: parse_bool(settings, "inbound-reject-private")?,
+		trusted_peers: parse_node_ids(settings, "trusted-peer")?,
+	};
+	if let (Some(min), Some(max)) = (policy.min_channel_sats, policy.max_channel_sats) {
+		if min > max {
+			println!("ERROR: `inbound-min-channel-sats` is larger than `inbound-max-channel-sats`");
+			return Err(());
+		}
+	}
+	if let Some(node_id) = policy.trusted_peers.intersection(&policy.denied_peers).next() {
+		println!("ERROR: peer {} is both trusted and denied with `inbound-deny-peer`", node_id);
+		return Err(());
+	}
+	Ok(policy)
+}
+
 // Default datadir relative to home directory
 #[cfg(target_os = "windows")]
 const DEFAULT_BITCOIN_DATADIR: &str = "AppData/Roaming/Bitcoin";
@@ -362,3 +818,321 @@ mod rpc_auth_tests {
 		assert_eq!(password, EXPECTED_PASSWORD);
 	}
 }
+
+#[cfg(test)]
+mod config_tests {
+	use super::*;
+	use lightning::chain::chaininterface::ConfirmationTarget;
+
+	const TEST_CONFIG_FILE: &str = "test_data/test_ldk.conf";
+	const TEST_CONFIG_FILE_BAD: &str = "test_data/test_ldk_bad.conf";
+
+	fn args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|a| a.to_string()).collect()
+	}
+
+	#[test]
+	fn test_read_config_file_success() {
+		let settings = read_config_file(Path::new(TEST_CONFIG_FILE)).unwrap();
+		assert_eq!(single_value(&settings, "bitcoind-rpc-host").unwrap(), Some("10.0.0.1"));
+		assert_eq!(single_value(&settings, "network").unwrap(), Some("regtest"));
+		assert_eq!(single_value(&settings, "peer-listening-port").unwrap(), None);
+		assert_eq!(settings.get("announced-listen-addr").unwrap().len(), 2);
+	}
+
+	#[test]
+	fn test_read_config_file_fail() {
+		assert!(read_config_file(Path::new(TEST_CONFIG_FILE_BAD)).is_err());
+		assert!(read_config_file(Path::new("nonexistent_file")).is_err());
+		assert!(parse_config("not-an-option = 1").is_err());
+		assert!(parse_config("network").is_err());
+		assert!(parse_config("config = other.conf").is_err());
+	}
+
+	#[test]
+	fn test_flags_override_config_file() {
+		let settings = collect_settings(&args(&[
+			"--config=test_data/test_ldk.conf",
+			"--storage-dir=test_data",
+			"--network=signet",
+		]))
+		.unwrap();
+		let user_info = parse_user_info(&settings).unwrap();
+		assert_eq!(user_info.network, Network::Signet);
+		assert_eq!(user_info.bitcoind_rpc_host, "10.0.0.1");
+		assert_eq!(user_info.bitcoind_rpc_port, 18443);
+		assert_eq!(user_info.bitcoind_rpc_username, "testuser");
+		assert_eq!(user_info.ldk_storage_dir_path, "test_data");
+		assert_eq!(user_info.ldk_peer_listening_port, 9735);
+		assert_eq!(&user_info.ldk_announced_node_name[..9], b"test-node");
+		assert_eq!(user_info.ldk_announced_listen_addr.len(), 2);
+	}
+
+	#[test]
+	fn test_positional_args() {
+		let settings = collect_settings(&args(&[
+			"testuser:testpassword@localhost:18443",
+			"test_data",
+			"9000",
+			"regtest",
+			"alias",
+			"127.0.0.1:9735",
+		]))
+		.unwrap();
+		let user_info = parse_user_info(&settings).unwrap();
+		assert_eq!(user_info.bitcoind_rpc_host, "localhost");
+		assert_eq!(user_info.bitcoind_rpc_password, "testpassword");
+		assert_eq!(user_info.ldk_peer_listening_port, 9000);
+		assert_eq!(user_info.network, Network::Regtest);
+		assert_eq!(user_info.ldk_announced_listen_addr.len(), 1);
+
+		// The peer listening port may be omitted
+		let settings = collect_settings(&args(&[
+			"testuser:testpassword@localhost:18443",
+			"test_data",
+			"signet",
+		]))
+		.unwrap();
+		let user_info = parse_user_info(&settings).unwrap();
+		assert_eq!(user_info.ldk_peer_listening_port, 9735);
+		assert_eq!(user_info.network, Network::Signet);
+	}
+
+	#[test]
+	fn test_invalid_settings() {
+		let base =
+			["--storage-dir=test_data", "--bitcoind-rpc-username=u", "--bitcoind-rpc-password=p"];
+		let invalid_flags = [
+			"--network=mainnet",
+			"--network=testnet4",
+			"--peer-listening-port=notaport",
+			"--bitcoind-rpc-port=70000",
+			"--node-name=this-node-name-is-longer-than-32-bytes",
+			"--announced-listen-addr=notanaddress",
+			"--chain-source=bitcoin-core",
+			"--chain-source=esplora",
+			"--chain-source=electrum",
+			"--electrum-url=tcp://localhost:50001",
+			"--bitcoind-zmq-hashblock=127.0.0.1:28332",
+			"--esplora-url=https://blockstream.info/api",
+		];
+		for flag in invalid_flags.iter() {
+			let mut flags = base.to_vec();
+			flags.push(flag);
+			let settings = collect_settings(&args(&flags)).unwrap();
+			assert!(parse_user_info(&settings).is_err(), "{} should be rejected", flag);
+		}
+
+		assert!(collect_settings(&args(&["--not-an-option=1"])).is_err());
+		assert!(collect_settings(&args(&["--network=regtest", "--network=signet"])).is_err());
+		assert!(collect_settings(&args(&["--config=nonexistent_file"])).is_err());
+		assert!(collect_settings(&args(&["localhost:18443"])).is_err());
+		let settings = collect_settings(&args(&["--network=regtest"])).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+	}
+
+	#[test]
+	fn test_chain_source() {
+		let base =
+			["--storage-dir=test_data", "--bitcoind-rpc-username=u", "--bitcoind-rpc-password=p"];
+		let settings = collect_settings(&args(&base)).unwrap();
+		assert_eq!(parse_user_info(&settings).unwrap().chain_source, ChainSource::Bitcoind);
+
+		let mut flags = base.to_vec();
+		flags.push("--chain-source=esplora");
+		flags.push("--esplora-url=https://blockstream.info/testnet/api/");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(
+			parse_user_info(&settings).unwrap().chain_source,
+			ChainSource::Esplora { url: "https://blockstream.info/testnet/api".to_string() }
+		);
+
+		flags.pop();
+		flags.push("--esplora-url=blockstream.info/testnet/api");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+
+		let mut flags = base.to_vec();
+		flags.push("--chain-source=electrum");
+		flags.push("--electrum-url=ssl://electrum.example.com:50002");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(
+			parse_user_info(&settings).unwrap().chain_source,
+			ChainSource::Electrum { url: "ssl://electrum.example.com:50002".to_string() }
+		);
+
+		flags.push("--esplora-url=https://blockstream.info/testnet/api");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+
+		// Without bitcoind we don't need its credentials.
+		let settings = collect_settings(&args(&[
+			"--storage-dir=test_data",
+			"--chain-source=electrum",
+			"--electrum-url=ssl://electrum.example.com:50002",
+		]))
+		.unwrap();
+		assert!(parse_user_info(&settings).is_ok());
+	}
+
+	#[test]
+	fn test_fee_policy() {
+		let settings = collect_settings(&args(&[
+			"--config=test_data/test_ldk.conf",
+			"--storage-dir=test_data",
+			"--fee.on-chain-sweep.static=8000",
+		]))
+		.unwrap();
+		let fee_policy = parse_user_info(&settings).unwrap().fee_policy;
+		let on_chain_sweep = fee_policy.get(&ConfirmationTarget::OnChainSweep);
+		assert_eq!(on_chain_sweep.static_feerate, Some(8000));
+		let non_anchor_channel_fee = fee_policy.get(&ConfirmationTarget::NonAnchorChannelFee);
+		assert_eq!(non_anchor_channel_fee.conf_target, 12);
+		assert_eq!(non_anchor_channel_fee.multiplier, 1.25);
+
+		let base =
+			["--storage-dir=test_data", "--bitcoind-rpc-username=u", "--bitcoind-rpc-password=p"];
+		let mut flags = base.to_vec();
+		flags.push("--fee.on-chain-sweep.multiplier=-1");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+		assert!(collect_settings(&args(&["--fee.on-chain-sweep.speed=1"])).is_err());
+
+		let mut flags = base.to_vec();
+		flags.push("--fee-source=static");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(
+			parse_user_info(&settings).unwrap().fee_source,
+			FeeSource::Static { estimates_file: None }
+		);
+		flags.push("--fee-estimates-file=test_data/fee_estimates.json");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(
+			parse_user_info(&settings).unwrap().fee_source,
+			FeeSource::Static {
+				estimates_file: Some(PathBuf::from("test_data/fee_estimates.json"))
+			}
+		);
+		for invalid in ["--fee-source=fixed", "--fee-estimates-file=test_data/fee_estimates.json"] {
+			let mut flags = base.to_vec();
+			flags.push(invalid);
+			let settings = collect_settings(&args(&flags)).unwrap();
+			assert!(parse_user_info(&settings).is_err(), "{} should be rejected", invalid);
+		}
+	}
+
+	#[test]
+	fn test_onchain_wallet() {
+		let base =
+			["--storage-dir=test_data", "--bitcoind-rpc-username=u", "--bitcoind-rpc-password=p"];
+		let settings = collect_settings(&args(&base)).unwrap();
+		assert_eq!(parse_user_info(&settings).unwrap().onchain_wallet, WalletKind::Bitcoind);
+
+		let mut flags = base.to_vec();
+		flags.push("--onchain-wallet=internal");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(parse_user_info(&settings).unwrap().onchain_wallet, WalletKind::Internal);
+
+		flags.push("--chain-source=esplora");
+		flags.push("--esplora-url=https://blockstream.info/testnet/api");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(parse_user_info(&settings).unwrap().onchain_wallet, WalletKind::Internal);
+
+		// Without bitcoind, the internal wallet is the default and the only choice.
+		let mut flags = base.to_vec();
+		flags.push("--chain-source=esplora");
+		flags.push("--esplora-url=https://blockstream.info/testnet/api");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert_eq!(parse_user_info(&settings).unwrap().onchain_wallet, WalletKind::Internal);
+		flags.push("--onchain-wallet=bitcoind");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+
+		let mut flags = base.to_vec();
+		flags.push("--onchain-wallet=bdk");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+	}
+
+	#[test]
+	fn test_inbound_channel_policy() {
+		let node_a = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+		let node_b = "02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5";
+		let base =
+			["--storage-dir=test_data", "--bitcoind-rpc-username=u", "--bitcoind-rpc-password=p"];
+		let settings = collect_settings(&args(&base)).unwrap();
+		assert_eq!(
+			parse_user_info(&settings).unwrap().inbound_channel_policy,
+			InboundChannelPolicy::default()
+		);
+
+		let mut flags = base.to_vec();
+		let (allow_a, allow_b, trust_b) = (
+			format!("--inbound-allow-peer={}", node_a),
+			format!("--inbound-allow-peer={}", node_b),
+			format!("--trusted-peer={}", node_b),
+		);
+		flags.extend_from_slice(&[
+			"--inbound-min-channel-sats=20000",
+			"--inbound-max-pending-per-peer=2",
+			"--inbound-require-anchors",
+			&allow_a,
+			&allow_b,
+			&trust_b,
+		]);
+		let settings = collect_settings(&args(&flags)).unwrap();
+		let policy = parse_user_info(&settings).unwrap().inbound_channel_policy;
+		assert_eq!(policy.min_channel_sats, Some(20000));
+		assert_eq!(policy.max_channel_sats, None);
+		assert_eq!(policy.max_pending_channels_per_peer, Some(2));
+		assert!(policy.require_anchors);
+		assert!(!policy.reject_private);
+		assert_eq!(policy.allowed_peers.len(), 2);
+		assert!(policy.trusted_peers.contains(&PublicKey::from_str(node_b).unwrap()));
+
+		let deny_b = format!("--inbound-deny-peer={}", node_b);
+		for invalid in [
+			"--inbound-allow-peer=02aa",
+			"--inbound-max-channel-sats=10000",
+			"--inbound-reject-private=maybe",
+			&deny_b,
+		] {
+			let mut invalid_flags = flags.clone();
+			invalid_flags.push(invalid);
+			let settings = collect_settings(&args(&invalid_flags)).unwrap();
+			assert!(parse_user_info(&settings).is_err(), "{} should be rejected", invalid);
+		}
+	}
+
+	#[test]
+	fn test_mainnet_requires_opt_in() {
+		let base = [
+			"--storage-dir=test_data",
+			"--bitcoind-rpc-username=u",
+			"--bitcoind-rpc-password=p",
+			"--network=mainnet",
+		];
+		let mut flags = base.to_vec();
+		flags.push("--keys-seed-backup-path=/backup/keys_seed");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+
+		flags.push("--i-understand-mainnet-risk=yes");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+
+		flags.pop();
+		flags.push("--i-understand-mainnet-risk");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		let user_info = parse_user_info(&settings).unwrap();
+		assert_eq!(user_info.network, Network::Bitcoin);
+		assert_eq!(user_info.bitcoind_rpc_port, 8332);
+		assert_eq!(user_info.keys_seed_backup_path.as_deref(), Some("/backup/keys_seed"));
+
+		// A seed backup is required on mainnet
+		let mut flags = base.to_vec();
+		flags.push("--i-understand-mainnet-risk");
+		let settings = collect_settings(&args(&flags)).unwrap();
+		assert!(parse_user_info(&settings).is_err());
+	}
+}
@@ -0,0 +1,292 @@
+//! `ldk-cli` sends a single command to a running ldk-sample node over its JSON-RPC control API and
+//! prints the result, much like `bitcoin-cli` does for bitcoind.
+//!
+//! The node must have been started with `daemon`, `rpc-bind` or `rpc-socket`. Authentication uses
+//! the cookie the node writes to `<storage-dir>/.ldk/.cookie` on startup.
+
+use serde_json::{json, Value};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process;
+
+const DEFAULT_RPC_CONNECT: &str = "127.0.0.1:9736";
+
+/// Exit codes, so scripts can tell a failed command from a node which couldn't be reached.
+const EXIT_COMMAND_FAILED: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_CONNECTION_FAILED: i32 = 3;
+
+/// The commands the node understands, as listed by its `help` command, and their aliases.
+const COMMANDS: &[&str] = &[
+	"help",
+	"openchannel",
+	"openchannels",
+	"closechannel",
+	"forceclosechannel",
+	"updatechannelpolicy",
+	"listchannels",
+	"connectpeer",
+	"disconnectpeer",
+	"listpeers",
+	"sendpayment",
+	"keysend",
+	"listpayments",
+	"getinvoice",
+	"getoffer",
+	"signmessage",
+	"sendonionmessage",
+	"nodeinfo",
+	"feerates",
+	"newaddress",
+	"onchainbalance",
+	"getbalance",
+	"listunspent",
+	"sendonchain",
+	"sendtoaddress",
+	"abandontransaction",
+	"setoutput",
+	"stop",
+];
+
+struct Options {
+	storage_dir: String,
+	rpc_connect: Option<String>,
+	rpc_socket: Option<String>,
+	cookie_file: Option<String>,
+	json: bool,
+	command: Vec<String>,
+}
+
+fn print_usage() {
+	eprintln!("Usage: ldk-cli [--storage-dir=<ldk_storage_directory_path>] [--rpc-connect=<host:port>|--rpc-socket=<path>] [--cookie-file=<path>] [--json] <command> [<arguments>]*");
+	eprintln!(
+		"  --storage-dir defaults to the current directory and is used to find the cookie file"
+	);
+	eprintln!("  --rpc-connect defaults to {}", DEFAULT_RPC_CONNECT);
+	eprintln!("Commands: {}", COMMANDS.join(", "));
+	eprintln!("Run `ldk-cli help` for the arguments each command takes.");
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+	let mut options = Options {
+		storage_dir: ".".to_string(),
+		rpc_connect: None,
+		rpc_socket: None,
+		cookie_file: None,
+		json: false,
+		command: Vec::new(),
+	};
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		if !arg.starts_with("--") {
+			options.command.push(arg.clone());
+			// Everything after the command name belongs to the command, including its own flags.
+			options.command.extend(args.by_ref().cloned());
+			break;
+		}
+		let (key, value) = match arg[2..].split_once('=') {
+			Some((key, value)) => (key, Some(value.to_string())),
+			None => (&arg[2..], None),
+		};
+		match (key, value) {
+			("json", None) => options.json = true,
+			("storage-dir", Some(value)) => options.storage_dir = value,
+			("rpc-connect", Some(value)) => options.rpc_connect = Some(value),
+			("rpc-socket", Some(value)) => options.rpc_socket = Some(value),
+			("cookie-file", Some(value)) => options.cookie_file = Some(value),
+			_ => return Err(format!("invalid option `{}`", arg)),
+		}
+	}
+	if options.rpc_connect.is_some() && options.rpc_socket.is_some() {
+		return Err("only one of `--rpc-connect` and `--rpc-socket` may be given".to_string());
+	}
+	match options.command.first() {
+		None => return Err("no command given".to_string()),
+		Some(command) if !COMMANDS.contains(&command.as_str()) => {
+			return Err(format!("unknown command `{}`", command));
+		}
+		Some(_) => {}
+	}
+	Ok(options)
+}
+
+/// Builds the JSON-RPC request for a command line, passing every argument through as a string.
+fn build_request(id: u64, command: &[String]) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "method": command[0], "params": &command[1..] })
+}
+
+/// The two halves of a connection to the node.
+struct Connection {
+	reader: Box<dyn BufRead>,
+	writer: Box<dyn Write>,
+}
+
+fn connect(options: &Options) -> Result<Connection, String> {
+	if let Some(path) = &options.rpc_socket {
+		#[cfg(unix)]
+		{
+			let connect_err = |e| format!("couldn't connect to {}: {}", path, e);
+			let stream = std::os::unix::net::UnixStream::connect(path).map_err(connect_err)?;
+			let writer = stream.try_clone().map_err(connect_err)?;
+			return Ok(Connection {
+				reader: Box::new(BufReader::new(stream)),
+				writer: Box::new(writer),
+			});
+		}
+		#[cfg(not(unix))]
+		return Err(format!("`--rpc-socket={}` is only supported on unix platforms", path));
+	}
+	let addr = options.rpc_connect.as_deref().unwrap_or(DEFAULT_RPC_CONNECT);
+	let connect_err = |e| format!("couldn't connect to {}: {}", addr, e);
+	let stream = TcpStream::connect(addr).map_err(connect_err)?;
+	let writer = stream.try_clone().map_err(connect_err)?;
+	Ok(Connection { reader: Box::new(BufReader::new(stream)), writer: Box::new(writer) })
+}
+
+fn call(conn: &mut Connection, request: &Value) -> Result<Value, String> {
+	let mut line = request.to_string();
+	line.push('\n');
+	conn.writer.write_all(line.as_bytes()).map_err(|e| format!("couldn't send request: {}", e))?;
+	let mut response = String::new();
+	match conn.reader.read_line(&mut response) {
+		Ok(0) => Err("the node closed the connection".to_string()),
+		Ok(_) => serde_json::from_str(&response).map_err(|e| format!("invalid response: {}", e)),
+		Err(e) => Err(format!("couldn't read response: {}", e)),
+	}
+}
+
+fn main() {
+	let args: Vec<String> = env::args().skip(1).collect();
+	let options = match parse_options(&args) {
+		Ok(options) => options,
+		Err(e) => {
+			eprintln!("ERROR: {}", e);
+			print_usage();
+			process::exit(EXIT_USAGE);
+		}
+	};
+
+	let cookie_path = match &options.cookie_file {
+		Some(path) => path.clone(),
+		None => format!("{}/.ldk/.cookie", options.storage_dir),
+	};
+	let cookie = match fs::read_to_string(&cookie_path) {
+		Ok(cookie) => cookie.trim().to_string(),
+		Err(e) => {
+			eprintln!("ERROR: couldn't read the cookie file {}: {}", cookie_path, e);
+			eprintln!("Is the node running with the control API enabled?");
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	};
+
+	let mut conn = match connect(&options) {
+		Ok(conn) => conn,
+		Err(e) => {
+			eprintln!("ERROR: {}", e);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	};
+	let auth = json!({ "jsonrpc": "2.0", "id": 0, "method": "auth", "params": [cookie] });
+	match call(&mut conn, &auth) {
+		Ok(response) if response.get("error").is_none() => {}
+		Ok(_) => {
+			eprintln!("ERROR: the node rejected the cookie in {}", cookie_path);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+		Err(e) => {
+			eprintln!("ERROR: {}", e);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	}
+
+	if options.json {
+		// Have the listing commands produce JSON, which we then embed in our output as is.
+		let setoutput = vec!["setoutput".to_string(), "json".to_string()];
+		if let Err(e) = call(&mut conn, &build_request(1, &setoutput)) {
+			eprintln!("ERROR: {}", e);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	}
+
+	let response = match call(&mut conn, &build_request(2, &options.command)) {
+		Ok(response) => response,
+		Err(e) => {
+			eprintln!("ERROR: {}", e);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	};
+	match (response.get("result"), response.get("error")) {
+		(Some(result), None) => {
+			if options.json {
+				let result = match result.as_str().map(serde_json::from_str::<Value>) {
+					Some(Ok(value)) => value,
+					_ => result.clone(),
+				};
+				println!("{}", serde_json::to_string_pretty(&json!({ "result": result })).unwrap());
+			} else if let Some(text) = result.as_str() {
+				print!("{}", text);
+			} else {
+				println!("{}", result);
+			}
+		}
+		(_, Some(error)) => {
+			if options.json {
+				println!("{}", serde_json::to_string_pretty(&json!({ "error": error })).unwrap());
+			} else {
+				let message = error.get("message").and_then(|m| m.as_str()).unwrap_or("");
+				eprintln!("{}", message);
+			}
+			process::exit(EXIT_COMMAND_FAILED);
+		}
+		(None, None) => {
+			eprintln!("ERROR: invalid response: {}", response);
+			process::exit(EXIT_CONNECTION_FAILED);
+		}
+	}
+}
+
+#[cfg(test)]
+mod ldk_cli_tests {
+	use super::*;
+
+	fn args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|s| s.to_string()).collect()
+	}
+
+	#[test]
+	fn test_parse_options() {
+		let options = parse_options(&args(&[
+			"--storage-dir=/tmp/node",
+			"--json",
+			"openchannel",
+			"02aa@127.0.0.1:9735",
+			"10000",
+			"--public",
+		]))
+		.unwrap();
+		assert_eq!(options.storage_dir, "/tmp/node");
+		assert!(options.json);
+		assert_eq!(
+			options.command,
+			args(&["openchannel", "02aa@127.0.0.1:9735", "10000", "--public"])
+		);
+
+		assert!(parse_options(&args(&[])).is_err());
+		assert!(parse_options(&args(&["--json"])).is_err());
+		assert!(parse_options(&args(&["notacommand"])).is_err());
+		assert!(parse_options(&args(&["getbalance"])).is_ok());
+		assert!(parse_options(&args(&["--bogus=1", "nodeinfo"])).is_err());
+		assert!(parse_options(&args(&["--rpc-connect=a:1", "--rpc-socket=b", "nodeinfo"])).is_err());
+	}
+
+	#[test]
+	fn test_build_request() {
+		let request = build_request(1, &args(&["getinvoice", "1000", "60"]));
+		assert_eq!(
+			request,
+			json!({ "jsonrpc": "2.0", "id": 1, "method": "getinvoice", "params": ["1000", "60"] })
+		);
+	}
+}
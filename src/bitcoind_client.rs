@@ -1,9 +1,12 @@
 use crate::convert::{
-	BlockchainInfo, FeeResponse, FundedTx, ListUnspentResponse, MempoolMinFeeResponse, NewAddress,
-	RawTx, SignedTx,
+	BlockchainInfo, FeeResponse, FundedTx, ListUnspentResponse, LockUnspentResponse,
+	MempoolMinFeeResponse, NewAddress, NullResponse, RawTx, SignedTx,
 };
 use crate::disk::FilesystemLogger;
+use crate::fee_policy::{EstimateMode, FeePolicy};
+use crate::health::{self, ChainSourceHealth};
 use crate::hex_utils;
+use crate::zmq;
 use base64;
 use bitcoin::address::{Address, Payload, WitnessVersion};
 use bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
@@ -20,17 +23,23 @@ use lightning::events::bump_transaction::{Utxo, WalletSource};
 use lightning::log_error;
 use lightning::util::logger::Logger;
 use lightning_block_sync::http::HttpEndpoint;
-use lightning_block_sync::rpc::RpcClient;
+use lightning_block_sync::http::JsonResponse;
+use lightning_block_sync::rest::RestClient;
+use lightning_block_sync::rpc::{RpcClient, RpcError};
 use lightning_block_sync::{AsyncBlockSourceResult, BlockData, BlockHeaderData, BlockSource};
 use serde_json;
 use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::watch;
 
 pub struct BitcoindClient {
 	pub(crate) bitcoind_rpc_client: Arc<RpcClient>,
+	/// If set, headers and blocks are fetched via bitcoind's REST interface rather than RPC.
+	rest_client: Option<Arc<RestClient>>,
 	network: Network,
 	host: String,
 	port: u16,
@@ -39,33 +48,131 @@ pub struct BitcoindClient {
 	fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>,
 	handle: tokio::runtime::Handle,
 	logger: Arc<FilesystemLogger>,
+	health: Arc<ChainSourceHealth>,
 }
 
 impl BlockSource for BitcoindClient {
 	fn get_header<'a>(
 		&'a self, header_hash: &'a BlockHash, height_hint: Option<u32>,
 	) -> AsyncBlockSourceResult<'a, BlockHeaderData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_header(header_hash, height_hint).await })
+		Box::pin(async move {
+			match &self.rest_client {
+				Some(rest_client) => rest_client.get_header(header_hash, height_hint).await,
+				None => self.bitcoind_rpc_client.get_header(header_hash, height_hint).await,
+			}
+		})
 	}
 
 	fn get_block<'a>(
 		&'a self, header_hash: &'a BlockHash,
 	) -> AsyncBlockSourceResult<'a, BlockData> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_block(header_hash).await })
+		Box::pin(async move {
+			match &self.rest_client {
+				Some(rest_client) => rest_client.get_block(header_hash).await,
+				None => self.bitcoind_rpc_client.get_block(header_hash).await,
+			}
+		})
 	}
 
 	fn get_best_block<'a>(&'a self) -> AsyncBlockSourceResult<(BlockHash, Option<u32>)> {
-		Box::pin(async move { self.bitcoind_rpc_client.get_best_block().await })
+		Box::pin(async move {
+			match &self.rest_client {
+				Some(rest_client) => rest_client.get_best_block().await,
+				None => self.bitcoind_rpc_client.get_best_block().await,
+			}
+		})
 	}
 }
 
 /// The minimum feerate we are allowed to send, as specify by LDK.
-const MIN_FEERATE: u32 = 253;
+pub(crate) const MIN_FEERATE: u32 = 253;
+
+/// How many times we retry an RPC call which failed because bitcoind was unreachable or still
+/// starting up before giving up on it.
+const RPC_RETRIES: usize = 5;
+
+/// bitcoind's `RPC_IN_WARMUP` error code, returned while it's still loading on startup.
+const RPC_IN_WARMUP: i64 = -28;
+
+/// Whether an RPC call which failed with `e` may succeed if retried, i.e. bitcoind is unreachable
+/// or still starting up, rather than having rejected the call itself.
+fn is_transient(e: &std::io::Error) -> bool {
+	match e.kind() {
+		std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput => false,
+		std::io::ErrorKind::Other => match e.get_ref().and_then(|e| e.downcast_ref::<RpcError>()) {
+			Some(rpc_error) => rpc_error.code == RPC_IN_WARMUP,
+			None => false,
+		},
+		_ => true,
+	}
+}
+
+/// Calls an RPC method, retrying with exponential backoff while bitcoind is unreachable, and
+/// records the outcome in `health`. Only for calls which are safe to repeat, as a call which timed
+/// out may still have been processed by bitcoind.
+async fn call_with_retry<T>(
+	rpc_client: &RpcClient, health: &ChainSourceHealth, method: &str, params: &[serde_json::Value],
+) -> std::io::Result<T>
+where
+	JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+{
+	let mut backoff = health::INITIAL_BACKOFF;
+	let mut attempt = 0;
+	loop {
+		match rpc_client.call_method::<T>(method, params).await {
+			Ok(res) => {
+				health.record_success();
+				return Ok(res);
+			}
+			Err(e) if is_transient(&e) => {
+				health.record_failure(&e);
+				attempt += 1;
+				if attempt > RPC_RETRIES {
+					return Err(e);
+				}
+				tokio::time::sleep(backoff).await;
+				backoff = health::next_backoff(backoff);
+			}
+			// bitcoind answered, it just didn't like the call.
+			Err(e) => {
+				health.record_success();
+				return Err(e);
+			}
+		}
+	}
+}
+
+/// Calls an RPC method once, recording the outcome in `health`. For calls which change bitcoind's
+/// state, e.g. locking the inputs of a funded transaction, where retrying one which timed out after
+/// bitcoind processed it would do so twice.
+async fn call_once<T>(
+	rpc_client: &RpcClient, health: &ChainSourceHealth, method: &str, params: &[serde_json::Value],
+) -> std::io::Result<T>
+where
+	JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+{
+	match rpc_client.call_method::<T>(method, params).await {
+		Ok(res) => {
+			health.record_success();
+			Ok(res)
+		}
+		Err(e) if is_transient(&e) => {
+			health.record_failure(&e);
+			Err(e)
+		}
+		// bitcoind answered, it just didn't like the call.
+		Err(e) => {
+			health.record_success();
+			Err(e)
+		}
+	}
+}
 
 impl BitcoindClient {
 	pub(crate) async fn new(
 		host: String, port: u16, rpc_user: String, rpc_password: String, network: Network,
 		handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+		block_notifications: Option<watch::Receiver<()>>, fee_policy: Arc<FeePolicy>,
 	) -> std::io::Result<Self> {
 		let http_endpoint = HttpEndpoint::for_host(host.clone()).with_port(port);
 		let rpc_credentials =
@@ -78,22 +185,16 @@ impl BitcoindClient {
 				std::io::Error::new(std::io::ErrorKind::PermissionDenied,
 				"Failed to make initial call to bitcoind - please check your RPC user/password and access settings")
 			})?;
-		let mut fees: HashMap<ConfirmationTarget, AtomicU32> = HashMap::new();
-		fees.insert(ConfirmationTarget::OnChainSweep, AtomicU32::new(5000));
-		fees.insert(
-			ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
-			AtomicU32::new(MIN_FEERATE),
-		);
-		fees.insert(
-			ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
-			AtomicU32::new(MIN_FEERATE),
-		);
-		fees.insert(ConfirmationTarget::AnchorChannelFee, AtomicU32::new(MIN_FEERATE));
-		fees.insert(ConfirmationTarget::NonAnchorChannelFee, AtomicU32::new(2000));
-		fees.insert(ConfirmationTarget::ChannelCloseMinimum, AtomicU32::new(MIN_FEERATE));
+		// Until we have estimates, use the policy's static feerates and fallbacks.
+		let fees: HashMap<ConfirmationTarget, AtomicU32> = fee_policy
+			.feerates(|_| None)
+			.into_iter()
+			.map(|(target, rate)| (target, AtomicU32::new(rate)))
+			.collect();
 
 		let client = Self {
 			bitcoind_rpc_client: Arc::new(bitcoind_rpc_client),
+			rest_client: None,
 			host,
 			port,
 			rpc_user,
@@ -102,104 +203,101 @@ impl BitcoindClient {
 			fees: Arc::new(fees),
 			handle: handle.clone(),
 			logger,
+			health: Arc::new(ChainSourceHealth::new()),
 		};
 		BitcoindClient::poll_for_fee_estimates(
 			client.fees.clone(),
+			fee_policy,
 			client.bitcoind_rpc_client.clone(),
 			handle,
+			block_notifications,
+			Arc::clone(&client.health),
+			Arc::clone(&client.logger),
 		);
 		Ok(client)
 	}
 
 	fn poll_for_fee_estimates(
-		fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>, rpc_client: Arc<RpcClient>,
-		handle: tokio::runtime::Handle,
+		fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>, fee_policy: Arc<FeePolicy>,
+		rpc_client: Arc<RpcClient>, handle: tokio::runtime::Handle,
+		mut block_notifications: Option<watch::Receiver<()>>, health: Arc<ChainSourceHealth>,
+		logger: Arc<FilesystemLogger>,
 	) {
+		// Fee estimates mostly change when a block comes in, so with block notifications we refresh
+		// them then and otherwise only rarely.
+		let poll_interval = match block_notifications {
+			Some(_) => Duration::from_secs(600),
+			None => Duration::from_secs(60),
+		};
 		handle.spawn(async move {
 			loop {
-				let mempoolmin_estimate = {
-					let resp = rpc_client
-						.call_method::<MempoolMinFeeResponse>("getmempoolinfo", &vec![])
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => MIN_FEERATE,
-					}
-				};
-				let background_estimate = {
-					let background_conf_target = serde_json::json!(144);
-					let background_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![background_conf_target, background_estimate_mode],
-						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => MIN_FEERATE,
-					}
-				};
-
-				let normal_estimate = {
-					let normal_conf_target = serde_json::json!(18);
-					let normal_estimate_mode = serde_json::json!("ECONOMICAL");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![normal_conf_target, normal_estimate_mode],
-						)
-						.await
-						.unwrap();
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 2000,
-					}
-				};
-
-				let high_prio_estimate = {
-					let high_prio_conf_target = serde_json::json!(6);
-					let high_prio_estimate_mode = serde_json::json!("CONSERVATIVE");
-					let resp = rpc_client
-						.call_method::<FeeResponse>(
-							"estimatesmartfee",
-							&vec![high_prio_conf_target, high_prio_estimate_mode],
-						)
-						.await
-						.unwrap();
-
-					match resp.feerate_sat_per_kw {
-						Some(feerate) => std::cmp::max(feerate, MIN_FEERATE),
-						None => 5000,
+				match BitcoindClient::fetch_fee_estimates(&rpc_client, &fee_policy, &health).await {
+					Ok(estimates) => {
+						let feerates = fee_policy.feerates(|target_policy| {
+							estimates[&(target_policy.conf_target, target_policy.estimate_mode)]
+						});
+						for (target, rate) in feerates {
+							fees.get(&target).unwrap().store(rate, Ordering::Release);
+						}
 					}
-				};
-
-				fees.get(&ConfirmationTarget::OnChainSweep)
-					.unwrap()
-					.store(high_prio_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::MinAllowedAnchorChannelRemoteFee)
-					.unwrap()
-					.store(mempoolmin_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee)
-					.unwrap()
-					.store(background_estimate - 250, Ordering::Release);
-				fees.get(&ConfirmationTarget::AnchorChannelFee)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::NonAnchorChannelFee)
-					.unwrap()
-					.store(normal_estimate, Ordering::Release);
-				fees.get(&ConfirmationTarget::ChannelCloseMinimum)
-					.unwrap()
-					.store(background_estimate, Ordering::Release);
-
-				tokio::time::sleep(Duration::from_secs(60)).await;
+					// Keep using the previous estimates until bitcoind is back.
+					Err(e) => log_error!(logger, "Failed to update fee estimates: {}", e),
+				}
+
+				zmq::wait_for_block(&mut block_notifications, poll_interval).await;
 			}
 		});
 	}
 
+	/// Fetches the estimates (in sat/KW) the fee policy needs, keyed by conf target and estimate
+	/// mode. An estimate is `None` if bitcoind doesn't have one, e.g. on regtest.
+	async fn fetch_fee_estimates(
+		rpc_client: &RpcClient, fee_policy: &FeePolicy, health: &ChainSourceHealth,
+	) -> std::io::Result<HashMap<(u16, EstimateMode), Option<u32>>> {
+		let mut estimates = HashMap::new();
+		for (conf_target, estimate_mode) in fee_policy.required_estimates() {
+			let feerate = match estimate_mode.estimatesmartfee_mode() {
+				Some(mode) => {
+					let resp = call_with_retry::<FeeResponse>(
+						rpc_client,
+						health,
+						"estimatesmartfee",
+						&vec![serde_json::json!(conf_target), serde_json::json!(mode)],
+					)
+					.await?;
+					resp.feerate_sat_per_kw
+				}
+				None => {
+					let resp = call_with_retry::<MempoolMinFeeResponse>(
+						rpc_client,
+						health,
+						"getmempoolinfo",
+						&vec![],
+					)
+					.await?;
+					resp.feerate_sat_per_kw
+				}
+			};
+			estimates.insert((conf_target, estimate_mode), feerate);
+		}
+		Ok(estimates)
+	}
+
+	/// Switches fetching headers and blocks over to bitcoind's REST interface, which bitcoind serves
+	/// on its RPC port when started with `rest=1`. RPC is still used for everything else.
+	pub(crate) async fn enable_rest_block_source(&mut self) -> std::io::Result<()> {
+		let endpoint = HttpEndpoint::for_host(self.host.clone())
+			.with_port(self.port)
+			.with_path("/rest".to_string());
+		let rest_client = RestClient::new(endpoint)?;
+		rest_client.get_best_block().await.map_err(|_| {
+			std::io::Error::new(std::io::ErrorKind::Other,
+			"Failed to make initial call to bitcoind's REST interface - please check that bitcoind is running with `rest=1`")
+		})?;
+		self.rest_client = Some(Arc::new(rest_client));
+		Ok(())
+	}
+
 	pub fn get_new_rpc_client(&self) -> std::io::Result<RpcClient> {
 		let http_endpoint = HttpEndpoint::for_host(self.host.clone()).with_port(self.port);
 		let rpc_credentials =
@@ -207,76 +305,128 @@ impl BitcoindClient {
 		RpcClient::new(&rpc_credentials, http_endpoint)
 	}
 
-	pub async fn create_raw_transaction(&self, outputs: Vec<HashMap<String, f64>>) -> RawTx {
+	pub(crate) fn network(&self) -> Network {
+		self.network
+	}
+
+	/// The reachability of bitcoind, as seen by our calls to it.
+	pub(crate) fn health(&self) -> Arc<ChainSourceHealth> {
+		Arc::clone(&self.health)
+	}
+
+	/// Calls a method which is safe to repeat, retrying while bitcoind is unreachable.
+	async fn call_method<T>(&self, method: &str, params: &[serde_json::Value]) -> std::io::Result<T>
+	where
+		JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+	{
+		call_with_retry(&self.bitcoind_rpc_client, &self.health, method, params).await
+	}
+
+	/// Calls a method which changes bitcoind's state, without retrying.
+	async fn call_method_once<T>(
+		&self, method: &str, params: &[serde_json::Value],
+	) -> std::io::Result<T>
+	where
+		JsonResponse: TryFrom<Vec<u8>, Error = std::io::Error> + TryInto<T, Error = std::io::Error>,
+	{
+		call_once(&self.bitcoind_rpc_client, &self.health, method, params).await
+	}
+
+	pub async fn create_raw_transaction(
+		&self, outputs: Vec<HashMap<String, f64>>,
+	) -> std::io::Result<RawTx> {
 		let outputs_json = serde_json::json!(outputs);
-		self.bitcoind_rpc_client
-			.call_method::<RawTx>(
-				"createrawtransaction",
-				&vec![serde_json::json!([]), outputs_json],
-			)
-			.await
-			.unwrap()
+		self.call_method::<RawTx>(
+			"createrawtransaction",
+			&vec![serde_json::json!([]), outputs_json],
+		)
+		.await
 	}
 
-	pub async fn fund_raw_transaction(&self, raw_tx: RawTx) -> FundedTx {
+	pub async fn fund_raw_transaction(
+		&self, raw_tx: RawTx, feerate_sat_per_kw: u32,
+	) -> std::io::Result<FundedTx> {
 		let raw_tx_json = serde_json::json!(raw_tx.0);
 		let options = serde_json::json!({
 			// LDK gives us feerates in satoshis per KW but Bitcoin Core here expects fees
 			// denominated in satoshis per vB. First we need to multiply by 4 to convert weight
 			// units to virtual bytes, then divide by 1000 to convert KvB to vB.
-			"fee_rate": self
-				.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee) as f64 / 250.0,
+			"fee_rate": feerate_sat_per_kw as f64 / 250.0,
 			// While users could "cancel" a channel open by RBF-bumping and paying back to
 			// themselves, we don't allow it here as its easy to have users accidentally RBF bump
 			// and pay to the channel funding address, which results in loss of funds. Real
 			// LDK-based applications should enable RBF bumping and RBF bump either to a local
 			// change address or to a new channel output negotiated with the same node.
 			"replaceable": false,
+			// Lock the inputs bitcoind picks, so they aren't also picked for another transaction
+			// before this one is broadcast. See `unlock_unspent` for giving them back.
+			"lockUnspents": true,
 		});
-		self.bitcoind_rpc_client
-			.call_method("fundrawtransaction", &[raw_tx_json, options])
-			.await
-			.unwrap()
+		self.call_method_once("fundrawtransaction", &[raw_tx_json, options]).await
+	}
+
+	/// Unlocks outputs locked by `fund_raw_transaction`, e.g. once the transaction spending them
+	/// won't be broadcast after all.
+	pub async fn unlock_unspent(&self, outpoints: &[OutPoint]) -> std::io::Result<()> {
+		let outpoints_json: Vec<_> = outpoints
+			.iter()
+			.map(
+				|outpoint| serde_json::json!({"txid": outpoint.txid.to_string(), "vout": outpoint.vout}),
+			)
+			.collect();
+		let params = [serde_json::json!(true), serde_json::json!(outpoints_json)];
+		let res = self.call_method::<LockUnspentResponse>("lockunspent", &params).await?;
+		if !res.0 {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				"bitcoind failed to unlock the outputs",
+			));
+		}
+		Ok(())
 	}
 
-	pub async fn send_raw_transaction(&self, raw_tx: RawTx) {
+	/// Marks a wallet transaction which isn't in the mempool as abandoned, making its inputs
+	/// spendable again.
+	pub async fn abandon_transaction(&self, txid: &Txid) -> std::io::Result<()> {
+		let txid_json = serde_json::json!(txid.to_string());
+		self.call_method_once::<NullResponse>("abandontransaction", &[txid_json]).await?;
+		Ok(())
+	}
+
+	pub async fn send_raw_transaction(&self, raw_tx: RawTx) -> std::io::Result<Txid> {
 		let raw_tx_json = serde_json::json!(raw_tx.0);
-		self.bitcoind_rpc_client
-			.call_method::<Txid>("sendrawtransaction", &[raw_tx_json])
-			.await
-			.unwrap();
+		self.call_method_once::<Txid>("sendrawtransaction", &[raw_tx_json]).await
 	}
 
-	pub async fn sign_raw_transaction_with_wallet(&self, tx_hex: String) -> SignedTx {
+	pub async fn sign_raw_transaction_with_wallet(
+		&self, tx_hex: String,
+	) -> std::io::Result<SignedTx> {
 		let tx_hex_json = serde_json::json!(tx_hex);
-		self.bitcoind_rpc_client
-			.call_method("signrawtransactionwithwallet", &vec![tx_hex_json])
-			.await
-			.unwrap()
+		self.call_method("signrawtransactionwithwallet", &vec![tx_hex_json]).await
 	}
 
-	pub async fn get_new_address(&self) -> Address {
+	pub async fn get_new_address(&self) -> std::io::Result<Address> {
 		let addr_args = vec![serde_json::json!("LDK output address")];
-		let addr = self
-			.bitcoind_rpc_client
-			.call_method::<NewAddress>("getnewaddress", &addr_args)
-			.await
-			.unwrap();
-		Address::from_str(addr.0.as_str()).unwrap().require_network(self.network).unwrap()
+		let addr = self.call_method_once::<NewAddress>("getnewaddress", &addr_args).await?;
+		Address::from_str(addr.0.as_str())
+			.ok()
+			.and_then(|addr| addr.require_network(self.network).ok())
+			.ok_or_else(|| {
+				std::io::Error::new(
+					std::io::ErrorKind::InvalidData,
+					format!("bitcoind returned an invalid address: {}", addr.0),
+				)
+			})
 	}
 
-	pub async fn get_blockchain_info(&self) -> BlockchainInfo {
-		self.bitcoind_rpc_client
-			.call_method::<BlockchainInfo>("getblockchaininfo", &vec![])
-			.await
-			.unwrap()
+	pub async fn get_blockchain_info(&self) -> std::io::Result<BlockchainInfo> {
+		self.call_method::<BlockchainInfo>("getblockchaininfo", &vec![]).await
 	}
 
-	pub async fn list_unspent(&self) -> ListUnspentResponse {
-		self.bitcoind_rpc_client
-			.call_method::<ListUnspentResponse>("listunspent", &vec![])
-			.await
-			.unwrap()
+	/// Lists the wallet's unspent outputs with at least `min_conf` confirmations.
+	pub async fn list_unspent(&self, min_conf: u32) -> std::io::Result<ListUnspentResponse> {
+		let min_conf_json = serde_json::json!(min_conf);
+		self.call_method::<ListUnspentResponse>("listunspent", &[min_conf_json]).await
 	}
 }
 
@@ -292,19 +442,25 @@ impl BroadcasterInterface for BitcoindClient {
 		// `submitpackage` once it becomes available.
 		for tx in txs {
 			let bitcoind_rpc_client = Arc::clone(&self.bitcoind_rpc_client);
+			let health = Arc::clone(&self.health);
 			let tx_serialized = encode::serialize_hex(tx);
 			let tx_json = serde_json::json!(tx_serialized);
 			let logger = Arc::clone(&self.logger);
 			self.handle.spawn(async move {
 				// This may error due to RL calling `broadcast_transactions` with the same transaction
-				// multiple times, but the error is safe to ignore.
-				match bitcoind_rpc_client
-					.call_method::<Txid>("sendrawtransaction", &vec![tx_json])
-					.await
+				// multiple times, but the error is safe to ignore. For the same reason, retrying a
+				// broadcast bitcoind already processed is harmless.
+				match call_with_retry::<Txid>(
+					&bitcoind_rpc_client,
+					&health,
+					"sendrawtransaction",
+					&vec![tx_json],
+				)
+				.await
 					{
 						Ok(_) => {}
 						Err(e) => {
-							let err_str = e.get_ref().unwrap().to_string();
+							let err_str = e.to_string();
 							log_error!(logger,
 									   "Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransaction: {}",
 									   err_str,
@@ -320,8 +476,10 @@ impl BroadcasterInterface for BitcoindClient {
 impl WalletSource for BitcoindClient {
 	fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
 		let utxos = tokio::task::block_in_place(move || {
-			self.handle.block_on(async move { self.list_unspent().await }).0
-		});
+			self.handle.block_on(async move { self.list_unspent(1).await })
+		})
+		.map_err(|e| log_error!(self.logger, "Failed to list unspent outputs: {}", e))?
+		.0;
 		Ok(utxos
 			.into_iter()
 			.filter_map(|utxo| {
@@ -353,8 +511,10 @@ impl WalletSource for BitcoindClient {
 
 	fn get_change_script(&self) -> Result<ScriptBuf, ()> {
 		tokio::task::block_in_place(move || {
-			Ok(self.handle.block_on(async move { self.get_new_address().await.script_pubkey() }))
+			self.handle.block_on(async move { self.get_new_address().await })
 		})
+		.map(|addr| addr.script_pubkey())
+		.map_err(|e| log_error!(self.logger, "Failed to get a change address: {}", e))
 	}
 
 	fn sign_psbt(&self, tx: PartiallySignedTransaction) -> Result<Transaction, ()> {
@@ -363,8 +523,26 @@ impl WalletSource for BitcoindClient {
 		let tx_hex = hex_utils::hex_str(&tx_bytes);
 		let signed_tx = tokio::task::block_in_place(move || {
 			self.handle.block_on(async move { self.sign_raw_transaction_with_wallet(tx_hex).await })
-		});
+		})
+		.map_err(|e| log_error!(self.logger, "Failed to sign a transaction: {}", e))?;
 		let signed_tx_bytes = hex_utils::to_vec(&signed_tx.hex).ok_or(())?;
 		Transaction::consensus_decode(&mut signed_tx_bytes.as_slice()).map_err(|_| ())
 	}
 }
+
+#[cfg(test)]
+mod bitcoind_client_tests {
+	use super::*;
+
+	#[test]
+	fn test_is_transient() {
+		let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+		assert!(is_transient(&refused));
+		let warmup = RpcError { code: RPC_IN_WARMUP, message: "Loading block index".to_string() };
+		assert!(is_transient(&std::io::Error::new(std::io::ErrorKind::Other, warmup)));
+
+		let rejected = RpcError { code: -26, message: "txn-mempool-conflict".to_string() };
+		assert!(!is_transient(&std::io::Error::new(std::io::ErrorKind::Other, rejected)));
+		assert!(!is_transient(&std::io::Error::from(std::io::ErrorKind::InvalidData)));
+	}
+}
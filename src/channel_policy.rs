@@ -0,0 +1,152 @@
+//! Which inbound channels we accept. With `manually_accept_inbound_channels` set, LDK asks us
+//! about every channel a peer wants to open with an `OpenChannelRequest` event, which we check
+//! against the `inbound-*` and `trusted-peer` options.
+
+use bitcoin::secp256k1::PublicKey;
+use lightning::ln::features::ChannelTypeFeatures;
+use std::collections::HashSet;
+
+/// The rules inbound channels have to meet. The default accepts every channel.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct InboundChannelPolicy {
+	pub(crate) min_channel_sats: Option<u64>,
+	pub(crate) max_channel_sats: Option<u64>,
+	/// If not empty, only these peers (and trusted peers) may open channels with us.
+	pub(crate) allowed_peers: HashSet<PublicKey>,
+	pub(crate) denied_peers: HashSet<PublicKey>,
+	pub(crate) require_anchors: bool,
+	/// How many channels a peer may have with us which aren't ready yet, not counting the one it
+	/// is asking to open.
+	pub(crate) max_pending_channels_per_peer: Option<usize>,
+	/// Enforced by LDK rather than by [`InboundChannelPolicy::check`], as the announcement
+	/// preference of an inbound channel isn't part of the `OpenChannelRequest` event.
+	pub(crate) reject_private: bool,
+	/// Peers we trust not to double-spend a channel's funding transaction, whose channels we accept
+	/// without waiting for it to confirm.
+	pub(crate) trusted_peers: HashSet<PublicKey>,
+}
+
+/// How to answer an `OpenChannelRequest`.
+#[derive(Debug, PartialEq)]
+pub(crate) enum InboundChannelDecision {
+	Accept,
+	/// Accept the channel and use it before its funding transaction confirms.
+	AcceptZeroConf,
+	Reject(String),
+}
+
+impl InboundChannelPolicy {
+	/// Checks a channel `counterparty_node_id` wants to open with us, while it already has
+	/// `pending_channels` channels with us which aren't ready yet.
+	pub(crate) fn check(
+		&self, counterparty_node_id: &PublicKey, funding_satoshis: u64,
+		channel_type: &ChannelTypeFeatures, pending_channels: usize,
+	) -> InboundChannelDecision {
+		let trusted = self.trusted_peers.contains(counterparty_node_id);
+		if self.denied_peers.contains(counterparty_node_id) {
+			return InboundChannelDecision::Reject("peer is denied".to_string());
+		}
+		if !self.allowed_peers.is_empty()
+			&& !trusted
+			&& !self.allowed_peers.contains(counterparty_node_id)
+		{
+			return InboundChannelDecision::Reject("peer is not allowed".to_string());
+		}
+		if let Some(min_channel_sats) = self.min_channel_sats {
+			if funding_satoshis < min_channel_sats {
+				return InboundChannelDecision::Reject(format!(
+					"channel of {} sats is smaller than the minimum of {} sats",
+					funding_satoshis, min_channel_sats
+				));
+			}
+		}
+		if let Some(max_channel_sats) = self.max_channel_sats {
+			if funding_satoshis > max_channel_sats {
+				return InboundChannelDecision::Reject(format!(
+					"channel of {} sats is larger than the maximum of {} sats",
+					funding_satoshis, max_channel_sats
+				));
+			}
+		}
+		if self.require_anchors && !channel_type.supports_anchors_zero_fee_htlc_tx() {
+			return InboundChannelDecision::Reject(
+				"channel doesn't use anchor outputs".to_string(),
+			);
+		}
+		if let Some(max_pending) = self.max_pending_channels_per_peer {
+			if pending_channels >= max_pending {
+				return InboundChannelDecision::Reject(format!(
+					"peer already has {} pending channels",
+					pending_channels
+				));
+			}
+		}
+		if trusted {
+			InboundChannelDecision::AcceptZeroConf
+		} else if channel_type.requires_zero_conf() {
+			InboundChannelDecision::Reject("zero-conf channel from an untrusted peer".to_string())
+		} else {
+			InboundChannelDecision::Accept
+		}
+	}
+}
+
+#[cfg(test)]
+mod channel_policy_tests {
+	use super::*;
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+	fn node(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn is_rejected(decision: InboundChannelDecision) -> bool {
+		matches!(decision, InboundChannelDecision::Reject(_))
+	}
+
+	#[test]
+	fn test_default_policy() {
+		let policy = InboundChannelPolicy::default();
+		let channel_type = ChannelTypeFeatures::empty();
+		assert_eq!(
+			policy.check(&node(1), 1_000, &channel_type, 100),
+			InboundChannelDecision::Accept
+		);
+
+		let mut zero_conf = ChannelTypeFeatures::empty();
+		zero_conf.set_zero_conf_required();
+		assert!(is_rejected(policy.check(&node(1), 1_000, &zero_conf, 0)));
+	}
+
+	#[test]
+	fn test_policy_rules() {
+		let policy = InboundChannelPolicy {
+			min_channel_sats: Some(20_000),
+			max_channel_sats: Some(1_000_000),
+			allowed_peers: vec![node(1), node(2)].into_iter().collect(),
+			denied_peers: vec![node(2)].into_iter().collect(),
+			require_anchors: true,
+			max_pending_channels_per_peer: Some(2),
+			reject_private: false,
+			trusted_peers: vec![node(3)].into_iter().collect(),
+		};
+		let mut anchors = ChannelTypeFeatures::empty();
+		anchors.set_anchors_zero_fee_htlc_tx_required();
+
+		assert_eq!(policy.check(&node(1), 20_000, &anchors, 1), InboundChannelDecision::Accept);
+		// Denying a peer takes precedence over allowing it.
+		assert!(is_rejected(policy.check(&node(2), 20_000, &anchors, 0)));
+		assert!(is_rejected(policy.check(&node(4), 20_000, &anchors, 0)));
+		assert!(is_rejected(policy.check(&node(1), 19_999, &anchors, 0)));
+		assert!(is_rejected(policy.check(&node(1), 1_000_001, &anchors, 0)));
+		assert!(is_rejected(policy.check(&node(1), 20_000, &ChannelTypeFeatures::empty(), 0)));
+		assert!(is_rejected(policy.check(&node(1), 20_000, &anchors, 2)));
+
+		// Trusted peers don't need to be allowed, but still have to follow the other rules.
+		assert_eq!(
+			policy.check(&node(3), 20_000, &anchors, 0),
+			InboundChannelDecision::AcceptZeroConf
+		);
+		assert!(is_rejected(policy.check(&node(3), 10_000, &anchors, 0)));
+	}
+}
@@ -1,15 +1,29 @@
+use crate::bitcoind_client::MIN_FEERATE;
+use crate::channel_policy::InboundChannelPolicy;
 use crate::disk::{self, INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME};
+use crate::fee_policy::{self, FeePolicy};
+use crate::health::ChainSourceHealth;
 use crate::hex_utils;
+use crate::rgs::RgsSource;
+use crate::seed;
+use crate::wallet::{FundingBatches, WalletBackend};
 use crate::{
-	ChannelManager, HTLCStatus, InboundPaymentInfoStorage, MillisatAmount, NetworkGraph,
-	OnionMessenger, OutboundPaymentInfoStorage, PaymentInfo, PeerManager,
+	ChannelManager, DynFeeEstimator, HTLCStatus, InboundPaymentInfoStorage, MillisatAmount,
+	NetworkGraph, OnionMessenger, OutboundPaymentInfoStorage, PaymentInfo, PeerManager,
 };
+use bitcoin::address::Address;
+use bitcoin::amount::Amount;
+use bitcoin::hash_types::Txid;
 use bitcoin::hashes::sha256::Hash as Sha256;
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
 use bitcoin::secp256k1::PublicKey;
-use lightning::ln::channelmanager::{PaymentId, RecipientOnionFields, Retry};
+use lightning::chain::chaininterface::ConfirmationTarget;
+use lightning::ln::channelmanager::{
+	PaymentId, RecipientOnionFields, Retry, BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA,
+};
 use lightning::ln::msgs::SocketAddress;
+use lightning::ln::script::ShutdownScript;
 use lightning::ln::{ChannelId, PaymentHash, PaymentPreimage};
 use lightning::offers::offer::{self, Offer};
 use lightning::onion_message::messenger::Destination;
@@ -17,19 +31,26 @@ use lightning::onion_message::packet::OnionMessageContents;
 use lightning::routing::gossip::NodeId;
 use lightning::routing::router::{PaymentParameters, RouteParameters};
 use lightning::sign::{EntropySource, KeysManager};
-use lightning::util::config::{ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig};
+use lightning::util::config::{
+	ChannelConfigUpdate, ChannelHandshakeConfig, ChannelHandshakeLimits, UserConfig,
+};
+use lightning::util::errors::APIError;
+use lightning::util::logger::Logger;
 use lightning::util::persist::KVStore;
 use lightning::util::ser::{Writeable, Writer};
+use lightning::{log_error, log_info};
 use lightning_invoice::payment::payment_parameters_from_invoice;
 use lightning_invoice::payment::payment_parameters_from_zero_amount_invoice;
 use lightning_invoice::{utils, Bolt11Invoice, Currency};
 use lightning_persister::fs_store::FilesystemStore;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
 use std::env;
 use std::io;
 use std::io::Write;
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::path::Path;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -43,6 +64,52 @@ pub(crate) struct LdkUserInfo {
 	pub(crate) ldk_announced_listen_addr: Vec<SocketAddress>,
 	pub(crate) ldk_announced_node_name: [u8; 32],
 	pub(crate) network: Network,
+	pub(crate) keys_seed_backup_path: Option<String>,
+	pub(crate) restore_from_mnemonic: bool,
+	pub(crate) verify_mnemonic: bool,
+	pub(crate) encrypt_keys_seed: bool,
+	pub(crate) seed_passphrase_fd: Option<i32>,
+	pub(crate) daemon: bool,
+	pub(crate) rpc_bind: Option<SocketAddr>,
+	pub(crate) rpc_socket: Option<PathBuf>,
+	pub(crate) rgs_source: Option<RgsSource>,
+	pub(crate) rgs_sync_interval_secs: u64,
+	pub(crate) chain_source: ChainSource,
+	pub(crate) bitcoind_zmq_hashblock: Option<String>,
+	pub(crate) bitcoind_rest: bool,
+	pub(crate) fee_policy: FeePolicy,
+	pub(crate) fee_source: FeeSource,
+	pub(crate) onchain_wallet: WalletKind,
+	pub(crate) inbound_channel_policy: InboundChannelPolicy,
+}
+
+/// Where we get chain data, fee estimates and transaction broadcasting from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum ChainSource {
+	/// bitcoind's RPC interface, polling it for new blocks.
+	Bitcoind,
+	/// An Esplora HTTP(S) API at the given base URL.
+	Esplora { url: String },
+	/// An Electrum server, e.g. `tcp://localhost:50001` or `ssl://electrum.example.com:50002`.
+	Electrum { url: String },
+}
+
+/// Where the feerates we give LDK come from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum FeeSource {
+	/// The chain source's fee estimates, adjusted by our fee policy.
+	Chain,
+	/// Fixed feerates from our fee policy and, if set, a JSON file which may be edited while we run.
+	Static { estimates_file: Option<PathBuf> },
+}
+
+/// Which on-chain wallet funds our channels and receives our swept funds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WalletKind {
+	/// bitcoind's wallet, which must be loaded.
+	Bitcoind,
+	/// Our own wallet, derived from the keys seed.
+	Internal,
 }
 
 #[derive(Debug)]
@@ -63,19 +130,49 @@ impl Writeable for UserOnionMessageContents {
 	}
 }
 
-pub(crate) fn poll_for_user_input(
-	peer_manager: Arc<PeerManager>, channel_manager: Arc<ChannelManager>,
-	keys_manager: Arc<KeysManager>, network_graph: Arc<NetworkGraph>,
-	onion_messenger: Arc<OnionMessenger>, inbound_payments: Arc<Mutex<InboundPaymentInfoStorage>>,
-	outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>, ldk_data_dir: String,
-	network: Network, logger: Arc<disk::FilesystemLogger>, fs_store: Arc<FilesystemStore>,
-) {
+/// Everything the CLI commands act on, shared by the interactive prompt and the JSON-RPC server.
+#[derive(Clone)]
+pub(crate) struct NodeContext {
+	pub(crate) peer_manager: Arc<PeerManager>,
+	pub(crate) channel_manager: Arc<ChannelManager>,
+	pub(crate) keys_manager: Arc<KeysManager>,
+	pub(crate) network_graph: Arc<NetworkGraph>,
+	pub(crate) onion_messenger: Arc<OnionMessenger>,
+	pub(crate) inbound_payments: Arc<Mutex<InboundPaymentInfoStorage>>,
+	pub(crate) outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>,
+	pub(crate) ldk_data_dir: String,
+	pub(crate) keys_seed_backup_path: Option<PathBuf>,
+	pub(crate) network: Network,
+	pub(crate) logger: Arc<disk::FilesystemLogger>,
+	pub(crate) fs_store: Arc<FilesystemStore>,
+	/// The default output format of the listing commands, changed with `setoutput`.
+	pub(crate) output_format: Arc<Mutex<OutputFormat>>,
+	/// Whether the chain source we sync from is currently reachable.
+	pub(crate) chain_health: Arc<ChainSourceHealth>,
+	pub(crate) fee_estimator: Arc<DynFeeEstimator>,
+	pub(crate) fee_policy: Arc<FeePolicy>,
+	pub(crate) wallet: Arc<WalletBackend>,
+	/// Channels opened by `openchannels` which are waiting to be funded together.
+	pub(crate) funding_batches: Arc<FundingBatches>,
+}
+
+/// How the listing commands (`listchannels`, `listpayments`, `listpeers`, `nodeinfo`, `feerates`,
+/// `onchainbalance` and `listunspent`) print their results.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+	/// Human-readable, loosely JSON-like text.
+	Text,
+	/// Pretty-printed JSON with stable field names, for scripts.
+	Json,
+}
+
+pub(crate) fn poll_for_user_input(ctx: NodeContext) {
 	println!(
 		"LDK startup successful. Enter \"help\" to view available commands. Press Ctrl-D to quit."
 	);
 	println!("LDK logs are available at <your-supplied-ldk-data-dir-path>/.ldk/logs");
-	println!("Local Node ID is {}.", channel_manager.get_our_node_id());
-	'read_command: loop {
+	println!("Local Node ID is {}.", ctx.channel_manager.get_our_node_id());
+	loop {
 		print!("> ");
 		io::stdout().flush().unwrap(); // Without flushing, the `>` doesn't print
 		let mut line = String::new();
@@ -88,614 +185,1261 @@ pub(crate) fn poll_for_user_input(
 			break;
 		}
 
-		let mut words = line.split_whitespace();
-		if let Some(word) = words.next() {
-			match word {
-				"help" => help(),
-				"openchannel" => {
-					let peer_pubkey_and_ip_addr = words.next();
-					let channel_value_sat = words.next();
-					if peer_pubkey_and_ip_addr.is_none() || channel_value_sat.is_none() {
-						println!("ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public] [--with-anchors]");
-						continue;
-					}
-					let peer_pubkey_and_ip_addr = peer_pubkey_and_ip_addr.unwrap();
-					let (pubkey, peer_addr) =
-						match parse_peer_info(peer_pubkey_and_ip_addr.to_string()) {
-							Ok(info) => info,
-							Err(e) => {
-								println!("{:?}", e.into_inner().unwrap());
-								continue;
-							}
-						};
+		match line.split_whitespace().next() {
+			Some("quit") | Some("exit") => break,
+			Some(_) => {
+				let _ = handle_command(&ctx, &line, &mut io::stdout(), true);
+			}
+			None => {}
+		}
+	}
+}
 
-					let chan_amt_sat: Result<u64, _> = channel_value_sat.unwrap().parse();
-					if chan_amt_sat.is_err() {
-						println!("ERROR: channel amount must be a number");
-						continue;
-					}
-
-					if tokio::runtime::Handle::current()
-						.block_on(connect_peer_if_necessary(
-							pubkey,
-							peer_addr,
-							peer_manager.clone(),
-						))
-						.is_err()
-					{
-						continue;
-					};
-
-					let (mut announce_channel, mut with_anchors) = (false, false);
-					while let Some(word) = words.next() {
-						match word {
-							"--public" | "--public=true" => announce_channel = true,
-							"--public=false" => announce_channel = false,
-							"--with-anchors" | "--with-anchors=true" => with_anchors = true,
-							"--with-anchors=false" => with_anchors = false,
-							_ => {
-								println!("ERROR: invalid boolean flag format. Valid formats: `--option`, `--option=true` `--option=false`");
-								continue;
-							}
-						}
-					}
+/// Runs a single command line, writing its results to `out`. Commands which need confirmation
+/// from the user only prompt for it if `interactive` is set.
+///
+/// Returns `Err` if the command failed, in which case an error message has been written to `out`.
+pub(crate) fn handle_command(
+	ctx: &NodeContext, line: &str, out: &mut dyn Write, interactive: bool,
+) -> Result<(), ()> {
+	let NodeContext {
+		peer_manager,
+		channel_manager,
+		keys_manager,
+		network_graph,
+		onion_messenger,
+		inbound_payments,
+		outbound_payments,
+		ldk_data_dir,
+		keys_seed_backup_path,
+		network,
+		logger,
+		fs_store,
+		output_format: _,
+		chain_health,
+		fee_estimator,
+		fee_policy,
+		wallet,
+		funding_batches,
+	} = ctx;
+	let mut words = line.split_whitespace();
+	match words.next() {
+		Some("help") => help(out),
+		Some("openchannel") => {
+			let peer_pubkey_and_ip_addr = words.next();
+			let channel_value_sat = words.next();
+			if peer_pubkey_and_ip_addr.is_none() || channel_value_sat.is_none() {
+				writeln!(out, "ERROR: openchannel has 2 required arguments: `openchannel pubkey@host:port channel_amt_satoshis` [--public] [--with-anchors] [--zeroconf=false] [--<option>=<value>]...").unwrap();
+				return Err(());
+			}
+			let peer_pubkey_and_ip_addr = peer_pubkey_and_ip_addr.unwrap();
+			let (pubkey, peer_addr) = match parse_peer_info(peer_pubkey_and_ip_addr.to_string()) {
+				Ok(info) => info,
+				Err(e) => {
+					writeln!(out, "{:?}", e.into_inner().unwrap()).unwrap();
+					return Err(());
+				}
+			};
 
-					if open_channel(
-						pubkey,
-						chan_amt_sat.unwrap(),
-						announce_channel,
-						with_anchors,
-						channel_manager.clone(),
-					)
-					.is_ok()
-					{
-						let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
-						let _ = disk::persist_channel_peer(
-							Path::new(&peer_data_path),
-							peer_pubkey_and_ip_addr,
-						);
-					}
-				}
-				"sendpayment" => {
-					let invoice_str = words.next();
-					if invoice_str.is_none() {
-						println!("ERROR: sendpayment requires an invoice: `sendpayment <invoice>`");
-						continue;
-					}
-
-					let mut user_provided_amt: Option<u64> = None;
-					if let Some(amt_msat_str) = words.next() {
-						match amt_msat_str.parse() {
-							Ok(amt) => user_provided_amt = Some(amt),
-							Err(e) => {
-								println!("ERROR: couldn't parse amount_msat: {}", e);
-								continue;
+			let chan_amt_sat: u64 = match channel_value_sat.unwrap().parse() {
+				Ok(amt) => amt,
+				Err(_) => {
+					writeln!(out, "ERROR: channel amount must be a number").unwrap();
+					return Err(());
+				}
+			};
+
+			// Check the options before connecting, so a typo doesn't leave us connected to the peer
+			// without a channel.
+			let options = parse_open_channel_options(words, chan_amt_sat, out)?;
+
+			if tokio::runtime::Handle::current()
+				.block_on(connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone()))
+				.is_err()
+			{
+				writeln!(out, "ERROR: failed to connect to peer").unwrap();
+				return Err(());
+			};
+
+			open_channel(pubkey, chan_amt_sat, &options, channel_manager.clone(), out)?;
+			let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
+			let _ = disk::persist_channel_peer(Path::new(&peer_data_path), peer_pubkey_and_ip_addr);
+		}
+		Some("openchannels") => {
+			let usage = "`openchannels pubkey@host:port channel_amt_satoshis [pubkey@host:port channel_amt_satoshis]...` [--public] [--with-anchors]";
+			let (mut channels, mut announce_channel, mut with_anchors) = (Vec::new(), false, false);
+			while let Some(word) = words.next() {
+				match word {
+					"--public" | "--public=true" => announce_channel = true,
+					"--public=false" => announce_channel = false,
+					"--with-anchors" | "--with-anchors=true" => with_anchors = true,
+					"--with-anchors=false" => with_anchors = false,
+					_ if word.starts_with("--") => {
+						writeln!(out, "ERROR: invalid boolean flag format. Valid formats: `--option`, `--option=true` `--option=false`").unwrap();
+						return Err(());
+					}
+					peer_pubkey_and_ip_addr => {
+						let (pubkey, peer_addr) =
+							match parse_peer_info(peer_pubkey_and_ip_addr.to_string()) {
+								Ok(info) => info,
+								Err(e) => {
+									writeln!(out, "{:?}", e.into_inner().unwrap()).unwrap();
+									return Err(());
+								}
+							};
+						let chan_amt_sat: u64 = match words.next().map(|amt| amt.parse()) {
+							Some(Ok(amt)) => amt,
+							Some(Err(_)) => {
+								writeln!(out, "ERROR: channel amount must be a number").unwrap();
+								return Err(());
+							}
+							None => {
+								writeln!(out, "ERROR: each peer needs a channel amount: {}", usage)
+									.unwrap();
+								return Err(());
 							}
 						};
+						channels.push((peer_pubkey_and_ip_addr, pubkey, peer_addr, chan_amt_sat));
 					}
+				}
+			}
+			if channels.is_empty() {
+				writeln!(out, "ERROR: openchannels requires at least one peer: {}", usage).unwrap();
+				return Err(());
+			}
 
-					if let Ok(offer) = Offer::from_str(invoice_str.unwrap()) {
-						let offer_hash = Sha256::hash(invoice_str.unwrap().as_bytes());
-						let payment_id = PaymentId(*offer_hash.as_ref());
+			// Connect to every peer before opening any channel, so an unreachable peer doesn't
+			// leave the rest of the batch half-open.
+			for (_, pubkey, peer_addr, _) in channels.iter() {
+				if tokio::runtime::Handle::current()
+					.block_on(connect_peer_if_necessary(*pubkey, *peer_addr, peer_manager.clone()))
+					.is_err()
+				{
+					writeln!(out, "ERROR: failed to connect to peer {}", pubkey).unwrap();
+					return Err(());
+				}
+			}
 
-						let amt_msat = match (offer.amount(), user_provided_amt) {
-							(Some(offer::Amount::Bitcoin { amount_msats }), _) => *amount_msats,
-							(_, Some(amt)) => amt,
-							(amt, _) => {
-								println!("ERROR: Cannot process non-Bitcoin-denominated offer value {:?}", amt);
-								continue;
-							}
-						};
-						if user_provided_amt.is_some() && user_provided_amt != Some(amt_msat) {
-							println!("Amount didn't match offer of {}msat", amt_msat);
-							continue;
-						}
+			let channels_to_open = channels
+				.iter()
+				.map(|(_, pubkey, _, chan_amt_sat)| (*pubkey, *chan_amt_sat))
+				.collect::<Vec<_>>();
+			open_channels(
+				&channels_to_open,
+				announce_channel,
+				with_anchors,
+				channel_manager,
+				keys_manager,
+				funding_batches,
+				out,
+			)?;
+			let peer_data_path = format!("{}/channel_peer_data", ldk_data_dir.clone());
+			for (peer_pubkey_and_ip_addr, ..) in channels {
+				let _ =
+					disk::persist_channel_peer(Path::new(&peer_data_path), peer_pubkey_and_ip_addr);
+			}
+		}
+		Some("sendpayment") => {
+			let invoice_str = words.next();
+			if invoice_str.is_none() {
+				writeln!(out, "ERROR: sendpayment requires an invoice: `sendpayment <invoice>`")
+					.unwrap();
+				return Err(());
+			}
 
-						while user_provided_amt.is_none() {
-							print!("Paying offer for {} msat. Continue (Y/N)? >", amt_msat);
-							io::stdout().flush().unwrap();
+			let mut user_provided_amt: Option<u64> = None;
+			if let Some(amt_msat_str) = words.next() {
+				match amt_msat_str.parse() {
+					Ok(amt) => user_provided_amt = Some(amt),
+					Err(e) => {
+						writeln!(out, "ERROR: couldn't parse amount_msat: {}", e).unwrap();
+						return Err(());
+					}
+				};
+			}
 
-							if let Err(e) = io::stdin().read_line(&mut line) {
-								println!("ERROR: {}", e);
-								break 'read_command;
-							}
+			if let Ok(offer) = Offer::from_str(invoice_str.unwrap()) {
+				let offer_hash = Sha256::hash(invoice_str.unwrap().as_bytes());
+				let payment_id = PaymentId(*offer_hash.as_ref());
 
-							if line.len() == 0 {
-								// We hit EOF / Ctrl-D
-								break 'read_command;
-							}
+				let amt_msat = match (offer.amount(), user_provided_amt) {
+					(Some(offer::Amount::Bitcoin { amount_msats }), _) => *amount_msats,
+					(_, Some(amt)) => amt,
+					(amt, _) => {
+						writeln!(
+							out,
+							"ERROR: Cannot process non-Bitcoin-denominated offer value {:?}",
+							amt
+						)
+						.unwrap();
+						return Err(());
+					}
+				};
+				if user_provided_amt.is_some() && user_provided_amt != Some(amt_msat) {
+					writeln!(out, "Amount didn't match offer of {}msat", amt_msat).unwrap();
+					return Err(());
+				}
 
-							if line.starts_with("Y") {
-								break;
-							}
-							if line.starts_with("N") {
-								continue 'read_command;
-							}
-						}
+				// Only a human at the prompt needs to confirm the amount, other callers already
+				// asked us to pay.
+				while interactive && user_provided_amt.is_none() {
+					print!("Paying offer for {} msat. Continue (Y/N)? >", amt_msat);
+					io::stdout().flush().unwrap();
 
-						outbound_payments.lock().unwrap().payments.insert(
-							payment_id,
-							PaymentInfo {
-								preimage: None,
-								secret: None,
-								status: HTLCStatus::Pending,
-								amt_msat: MillisatAmount(Some(amt_msat)),
-							},
-						);
-						fs_store
-							.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode())
-							.unwrap();
+					let mut line = String::new();
+					if let Err(e) = io::stdin().read_line(&mut line) {
+						writeln!(out, "ERROR: {}", e).unwrap();
+						return Err(());
+					}
 
-						let retry = Retry::Timeout(Duration::from_secs(10));
-						let amt = Some(amt_msat);
-						let pay = channel_manager
-							.pay_for_offer(&offer, None, amt, None, payment_id, retry, None);
-						if pay.is_err() {
-							println!("ERROR: Failed to pay: {:?}", pay);
-						}
-					} else {
-						match Bolt11Invoice::from_str(invoice_str.unwrap()) {
-							Ok(invoice) => send_payment(
-								&channel_manager,
-								&invoice,
-								user_provided_amt,
-								&mut outbound_payments.lock().unwrap(),
-								Arc::clone(&fs_store),
-							),
-							Err(e) => {
-								println!("ERROR: invalid invoice: {:?}", e);
-							}
-						}
+					if line.len() == 0 {
+						// We hit EOF / Ctrl-D
+						return Err(());
+					}
+
+					if line.starts_with("Y") {
+						break;
+					}
+					if line.starts_with("N") {
+						return Err(());
 					}
 				}
-				"keysend" => {
-					let dest_pubkey = match words.next() {
-						Some(dest) => match hex_utils::to_compressed_pubkey(dest) {
-							Some(pk) => pk,
-							None => {
-								println!("ERROR: couldn't parse destination pubkey");
-								continue;
-							}
-						},
-						None => {
-							println!("ERROR: keysend requires a destination pubkey: `keysend <dest_pubkey> <amt_msat>`");
-							continue;
-						}
-					};
-					let amt_msat_str = match words.next() {
-						Some(amt) => amt,
-						None => {
-							println!("ERROR: keysend requires an amount in millisatoshis: `keysend <dest_pubkey> <amt_msat>`");
-							continue;
-						}
-					};
-					let amt_msat: u64 = match amt_msat_str.parse() {
-						Ok(amt) => amt,
-						Err(e) => {
-							println!("ERROR: couldn't parse amount_msat: {}", e);
-							continue;
-						}
-					};
-					keysend(
-						&channel_manager,
-						dest_pubkey,
-						amt_msat,
-						&*keys_manager,
+
+				outbound_payments.lock().unwrap().payments.insert(
+					payment_id,
+					PaymentInfo {
+						preimage: None,
+						secret: None,
+						status: HTLCStatus::Pending,
+						amt_msat: MillisatAmount(Some(amt_msat)),
+					},
+				);
+				fs_store
+					.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode())
+					.unwrap();
+
+				let retry = Retry::Timeout(Duration::from_secs(10));
+				let amt = Some(amt_msat);
+				let pay =
+					channel_manager.pay_for_offer(&offer, None, amt, None, payment_id, retry, None);
+				if pay.is_err() {
+					writeln!(out, "ERROR: Failed to pay: {:?}", pay).unwrap();
+					return Err(());
+				}
+			} else {
+				match Bolt11Invoice::from_str(invoice_str.unwrap()) {
+					Ok(invoice) => send_payment(
+						channel_manager,
+						&invoice,
+						user_provided_amt,
 						&mut outbound_payments.lock().unwrap(),
-						Arc::clone(&fs_store),
-					);
+						Arc::clone(fs_store),
+						out,
+					)?,
+					Err(e) => {
+						writeln!(out, "ERROR: invalid invoice: {:?}", e).unwrap();
+						return Err(());
+					}
 				}
-				"getoffer" => {
-					let offer_builder = channel_manager.create_offer_builder(String::new());
-					if let Err(e) = offer_builder {
-						println!("ERROR: Failed to initiate offer building: {:?}", e);
-						continue;
+			}
+		}
+		Some("keysend") => {
+			let dest_pubkey = match words.next() {
+				Some(dest) => match hex_utils::to_compressed_pubkey(dest) {
+					Some(pk) => pk,
+					None => {
+						writeln!(out, "ERROR: couldn't parse destination pubkey").unwrap();
+						return Err(());
 					}
+				},
+				None => {
+					writeln!(out, "ERROR: keysend requires a destination pubkey: `keysend <dest_pubkey> <amt_msat>`").unwrap();
+					return Err(());
+				}
+			};
+			let amt_msat_str = match words.next() {
+				Some(amt) => amt,
+				None => {
+					writeln!(out, "ERROR: keysend requires an amount in millisatoshis: `keysend <dest_pubkey> <amt_msat>`").unwrap();
+					return Err(());
+				}
+			};
+			let amt_msat: u64 = match amt_msat_str.parse() {
+				Ok(amt) => amt,
+				Err(e) => {
+					writeln!(out, "ERROR: couldn't parse amount_msat: {}", e).unwrap();
+					return Err(());
+				}
+			};
+			keysend(
+				channel_manager,
+				dest_pubkey,
+				amt_msat,
+				&**keys_manager,
+				&mut outbound_payments.lock().unwrap(),
+				Arc::clone(fs_store),
+				out,
+			)?;
+		}
+		Some("getoffer") => {
+			let offer_builder = channel_manager.create_offer_builder(String::new());
+			if let Err(e) = offer_builder {
+				writeln!(out, "ERROR: Failed to initiate offer building: {:?}", e).unwrap();
+				return Err(());
+			}
 
-					let amt_str = words.next();
-					let offer = if amt_str.is_some() {
-						let amt_msat: Result<u64, _> = amt_str.unwrap().parse();
-						if amt_msat.is_err() {
-							println!("ERROR: getoffer provided payment amount was not a number");
-							continue;
-						}
-						offer_builder.unwrap().amount_msats(amt_msat.unwrap()).build()
-					} else {
-						offer_builder.unwrap().build()
-					};
-
-					if offer.is_err() {
-						println!("ERROR: Failed to build offer: {:?}", offer.unwrap_err());
-					} else {
-						// Note that unlike BOLT11 invoice creation we don't bother to add a
-						// pending inbound payment here, as offers can be reused and don't
-						// correspond with individual payments.
-						println!("{}", offer.unwrap());
-					}
-				}
-				"getinvoice" => {
-					let amt_str = words.next();
-					if amt_str.is_none() {
-						println!("ERROR: getinvoice requires an amount in millisatoshis");
-						continue;
-					}
-
-					let amt_msat: Result<u64, _> = amt_str.unwrap().parse();
-					if amt_msat.is_err() {
-						println!("ERROR: getinvoice provided payment amount was not a number");
-						continue;
-					}
-
-					let expiry_secs_str = words.next();
-					if expiry_secs_str.is_none() {
-						println!("ERROR: getinvoice requires an expiry in seconds");
-						continue;
-					}
-
-					let expiry_secs: Result<u32, _> = expiry_secs_str.unwrap().parse();
-					if expiry_secs.is_err() {
-						println!("ERROR: getinvoice provided expiry was not a number");
-						continue;
-					}
-
-					let mut inbound_payments = inbound_payments.lock().unwrap();
-					get_invoice(
-						amt_msat.unwrap(),
-						&mut inbound_payments,
-						&channel_manager,
-						Arc::clone(&keys_manager),
-						network,
-						expiry_secs.unwrap(),
-						Arc::clone(&logger),
-					);
-					fs_store
-						.write("", "", INBOUND_PAYMENTS_FNAME, &inbound_payments.encode())
+			let amt_str = words.next();
+			let offer = if amt_str.is_some() {
+				let amt_msat: Result<u64, _> = amt_str.unwrap().parse();
+				if amt_msat.is_err() {
+					writeln!(out, "ERROR: getoffer provided payment amount was not a number")
 						.unwrap();
+					return Err(());
 				}
-				"connectpeer" => {
-					let peer_pubkey_and_ip_addr = words.next();
-					if peer_pubkey_and_ip_addr.is_none() {
-						println!("ERROR: connectpeer requires peer connection info: `connectpeer pubkey@host:port`");
-						continue;
-					}
-					let (pubkey, peer_addr) =
-						match parse_peer_info(peer_pubkey_and_ip_addr.unwrap().to_string()) {
-							Ok(info) => info,
-							Err(e) => {
-								println!("{:?}", e.into_inner().unwrap());
-								continue;
-							}
-						};
-					if tokio::runtime::Handle::current()
-						.block_on(connect_peer_if_necessary(
-							pubkey,
-							peer_addr,
-							peer_manager.clone(),
-						))
-						.is_ok()
-					{
-						println!("SUCCESS: connected to peer {}", pubkey);
-					}
-				}
-				"disconnectpeer" => {
-					let peer_pubkey = words.next();
-					if peer_pubkey.is_none() {
-						println!("ERROR: disconnectpeer requires peer public key: `disconnectpeer <peer_pubkey>`");
-						continue;
-					}
-
-					let peer_pubkey =
-						match bitcoin::secp256k1::PublicKey::from_str(peer_pubkey.unwrap()) {
-							Ok(pubkey) => pubkey,
-							Err(e) => {
-								println!("ERROR: {}", e.to_string());
-								continue;
-							}
-						};
+				offer_builder.unwrap().amount_msats(amt_msat.unwrap()).build()
+			} else {
+				offer_builder.unwrap().build()
+			};
+
+			if offer.is_err() {
+				writeln!(out, "ERROR: Failed to build offer: {:?}", offer.unwrap_err()).unwrap();
+				return Err(());
+			} else {
+				// Note that unlike BOLT11 invoice creation we don't bother to add a
+				// pending inbound payment here, as offers can be reused and don't
+				// correspond with individual payments.
+				writeln!(out, "{}", offer.unwrap()).unwrap();
+			}
+		}
+		Some("getinvoice") => {
+			let amt_str = words.next();
+			if amt_str.is_none() {
+				writeln!(out, "ERROR: getinvoice requires an amount in millisatoshis").unwrap();
+				return Err(());
+			}
+
+			let amt_msat: Result<u64, _> = amt_str.unwrap().parse();
+			if amt_msat.is_err() {
+				writeln!(out, "ERROR: getinvoice provided payment amount was not a number")
+					.unwrap();
+				return Err(());
+			}
+
+			let expiry_secs_str = words.next();
+			if expiry_secs_str.is_none() {
+				writeln!(out, "ERROR: getinvoice requires an expiry in seconds").unwrap();
+				return Err(());
+			}
 
-					if do_disconnect_peer(
-						peer_pubkey,
-						peer_manager.clone(),
-						channel_manager.clone(),
+			let expiry_secs: Result<u32, _> = expiry_secs_str.unwrap().parse();
+			if expiry_secs.is_err() {
+				writeln!(out, "ERROR: getinvoice provided expiry was not a number").unwrap();
+				return Err(());
+			}
+
+			let mut inbound_payments = inbound_payments.lock().unwrap();
+			get_invoice(
+				amt_msat.unwrap(),
+				&mut inbound_payments,
+				channel_manager,
+				Arc::clone(keys_manager),
+				*network,
+				expiry_secs.unwrap(),
+				Arc::clone(logger),
+				out,
+			)?;
+			fs_store.write("", "", INBOUND_PAYMENTS_FNAME, &inbound_payments.encode()).unwrap();
+		}
+		Some("connectpeer") => {
+			let peer_pubkey_and_ip_addr = words.next();
+			if peer_pubkey_and_ip_addr.is_none() {
+				writeln!(out, "ERROR: connectpeer requires peer connection info: `connectpeer pubkey@host:port`").unwrap();
+				return Err(());
+			}
+			let (pubkey, peer_addr) =
+				match parse_peer_info(peer_pubkey_and_ip_addr.unwrap().to_string()) {
+					Ok(info) => info,
+					Err(e) => {
+						writeln!(out, "{:?}", e.into_inner().unwrap()).unwrap();
+						return Err(());
+					}
+				};
+			if tokio::runtime::Handle::current()
+				.block_on(connect_peer_if_necessary(pubkey, peer_addr, peer_manager.clone()))
+				.is_ok()
+			{
+				writeln!(out, "SUCCESS: connected to peer {}", pubkey).unwrap();
+			} else {
+				writeln!(out, "ERROR: failed to connect to peer").unwrap();
+				return Err(());
+			}
+		}
+		Some("disconnectpeer") => {
+			let peer_pubkey = words.next();
+			if peer_pubkey.is_none() {
+				writeln!(out, "ERROR: disconnectpeer requires peer public key: `disconnectpeer <peer_pubkey>`").unwrap();
+				return Err(());
+			}
+
+			let peer_pubkey = match bitcoin::secp256k1::PublicKey::from_str(peer_pubkey.unwrap()) {
+				Ok(pubkey) => pubkey,
+				Err(e) => {
+					writeln!(out, "ERROR: {}", e.to_string()).unwrap();
+					return Err(());
+				}
+			};
+
+			do_disconnect_peer(peer_pubkey, peer_manager.clone(), channel_manager.clone(), out)?;
+			writeln!(out, "SUCCESS: disconnected from peer {}", peer_pubkey).unwrap();
+		}
+		Some("updatechannelpolicy") => {
+			let usage = "`updatechannelpolicy <channel_id|all> [--base-fee-msat=<msat>] [--fee-ppm=<ppm>] [--cltv-delta=<blocks>]`";
+			let channel_id = match words.next() {
+				Some("all") => None,
+				Some(channel_id_str) => match hex_utils::to_vec(channel_id_str) {
+					Some(channel_id_vec) if channel_id_vec.len() == 32 => {
+						let mut channel_id = [0; 32];
+						channel_id.copy_from_slice(&channel_id_vec);
+						Some(ChannelId(channel_id))
+					}
+					_ => {
+						writeln!(out, "ERROR: couldn't parse channel_id").unwrap();
+						return Err(());
+					}
+				},
+				None => {
+					writeln!(
+						out,
+						"ERROR: updatechannelpolicy requires a channel ID or `all`: {}",
+						usage
 					)
-					.is_ok()
-					{
-						println!("SUCCESS: disconnected from peer {}", peer_pubkey);
-					}
-				}
-				"listchannels" => list_channels(&channel_manager, &network_graph),
-				"listpayments" => list_payments(
-					&inbound_payments.lock().unwrap(),
-					&outbound_payments.lock().unwrap(),
-				),
-				"closechannel" => {
-					let channel_id_str = words.next();
-					if channel_id_str.is_none() {
-						println!("ERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey>`");
-						continue;
-					}
-					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
-					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
-						println!("ERROR: couldn't parse channel_id");
-						continue;
-					}
-					let mut channel_id = [0; 32];
-					channel_id.copy_from_slice(&channel_id_vec.unwrap());
-
-					let peer_pubkey_str = words.next();
-					if peer_pubkey_str.is_none() {
-						println!("ERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey>`");
-						continue;
-					}
-					let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
-						Some(peer_pubkey_vec) => peer_pubkey_vec,
-						None => {
-							println!("ERROR: couldn't parse peer_pubkey");
-							continue;
-						}
-					};
-					let peer_pubkey = match PublicKey::from_slice(&peer_pubkey_vec) {
-						Ok(peer_pubkey) => peer_pubkey,
-						Err(_) => {
-							println!("ERROR: couldn't parse peer_pubkey");
-							continue;
-						}
-					};
-
-					close_channel(channel_id, peer_pubkey, channel_manager.clone());
-				}
-				"forceclosechannel" => {
-					let channel_id_str = words.next();
-					if channel_id_str.is_none() {
-						println!("ERROR: forceclosechannel requires a channel ID: `forceclosechannel <channel_id> <peer_pubkey>`");
-						continue;
-					}
-					let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
-					if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
-						println!("ERROR: couldn't parse channel_id");
-						continue;
-					}
-					let mut channel_id = [0; 32];
-					channel_id.copy_from_slice(&channel_id_vec.unwrap());
-
-					let peer_pubkey_str = words.next();
-					if peer_pubkey_str.is_none() {
-						println!("ERROR: forceclosechannel requires a peer pubkey: `forceclosechannel <channel_id> <peer_pubkey>`");
-						continue;
-					}
-					let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
-						Some(peer_pubkey_vec) => peer_pubkey_vec,
-						None => {
-							println!("ERROR: couldn't parse peer_pubkey");
-							continue;
-						}
-					};
-					let peer_pubkey = match PublicKey::from_slice(&peer_pubkey_vec) {
-						Ok(peer_pubkey) => peer_pubkey,
-						Err(_) => {
-							println!("ERROR: couldn't parse peer_pubkey");
-							continue;
-						}
-					};
-
-					force_close_channel(channel_id, peer_pubkey, channel_manager.clone());
-				}
-				"nodeinfo" => node_info(&channel_manager, &peer_manager),
-				"listpeers" => list_peers(peer_manager.clone()),
-				"signmessage" => {
-					const MSG_STARTPOS: usize = "signmessage".len() + 1;
-					if line.trim().as_bytes().len() <= MSG_STARTPOS {
-						println!("ERROR: signmsg requires a message");
-						continue;
-					}
-					println!(
-						"{:?}",
-						lightning::util::message_signing::sign(
-							&line.trim().as_bytes()[MSG_STARTPOS..],
-							&keys_manager.get_node_secret_key()
-						)
-					);
+					.unwrap();
+					return Err(());
 				}
-				"sendonionmessage" => {
-					let path_pks_str = words.next();
-					if path_pks_str.is_none() {
-						println!(
-							"ERROR: sendonionmessage requires at least one node id for the path"
-						);
-						continue;
-					}
-					let mut intermediate_nodes = Vec::new();
-					let mut errored = false;
-					for pk_str in path_pks_str.unwrap().split(",") {
-						let node_pubkey_vec = match hex_utils::to_vec(pk_str) {
-							Some(peer_pubkey_vec) => peer_pubkey_vec,
-							None => {
-								println!("ERROR: couldn't parse peer_pubkey");
-								errored = true;
-								break;
-							}
-						};
-						let node_pubkey = match PublicKey::from_slice(&node_pubkey_vec) {
-							Ok(peer_pubkey) => peer_pubkey,
-							Err(_) => {
-								println!("ERROR: couldn't parse peer_pubkey");
-								errored = true;
-								break;
-							}
-						};
-						intermediate_nodes.push(node_pubkey);
+			};
+
+			let mut update = ChannelConfigUpdate::default();
+			for word in words {
+				let (flag, value) = match word.split_once('=') {
+					Some(flag_and_value) => flag_and_value,
+					None => {
+						writeln!(
+							out,
+							"ERROR: options must be given as `--option=<value>`: {}",
+							usage
+						)
+						.unwrap();
+						return Err(());
+					}
+				};
+				match flag {
+					"--base-fee-msat" => {
+						update.forwarding_fee_base_msat =
+							Some(parse_option_value(flag, value, out)?)
 					}
-					if errored {
-						continue;
+					"--fee-ppm" => {
+						update.forwarding_fee_proportional_millionths =
+							Some(parse_option_value(flag, value, out)?)
 					}
-					let tlv_type = match words.next().map(|ty_str| ty_str.parse()) {
-						Some(Ok(ty)) if ty >= 64 => ty,
-						_ => {
-							println!("Need an integral message type above 64");
-							continue;
-						}
-					};
-					let data = match words.next().map(|s| hex_utils::to_vec(s)) {
-						Some(Some(data)) => data,
-						_ => {
-							println!("Need a hex data string");
-							continue;
-						}
-					};
-					let destination = Destination::Node(intermediate_nodes.pop().unwrap());
-					match onion_messenger.send_onion_message(
-						UserOnionMessageContents { tlv_type, data },
-						destination,
-						None,
-					) {
-						Ok(success) => {
-							println!("SUCCESS: forwarded onion message to first hop {:?}", success)
+					"--cltv-delta" => {
+						let cltv_expiry_delta = parse_option_value(flag, value, out)?;
+						if cltv_expiry_delta < MIN_CLTV_EXPIRY_DELTA {
+							writeln!(
+								out,
+								"ERROR: the CLTV delta must be at least {} blocks",
+								MIN_CLTV_EXPIRY_DELTA
+							)
+							.unwrap();
+							return Err(());
 						}
-						Err(e) => println!("ERROR: failed to send onion message: {:?}", e),
+						update.cltv_expiry_delta = Some(cltv_expiry_delta);
+					}
+					"--max-htlc-msat" => {
+						// LDK derives the `htlc_maximum_msat` it announces from limits negotiated
+						// when the channel was opened.
+						writeln!(out, "ERROR: --max-htlc-msat isn't supported, LDK 0.0.121 can't change a channel's htlc_maximum_msat after it's opened").unwrap();
+						return Err(());
+					}
+					_ => {
+						writeln!(out, "ERROR: unknown option `{}`: {}", flag, usage).unwrap();
+						return Err(());
+					}
+				}
+			}
+			if update.forwarding_fee_base_msat.is_none()
+				&& update.forwarding_fee_proportional_millionths.is_none()
+				&& update.cltv_expiry_delta.is_none()
+			{
+				writeln!(out, "ERROR: nothing to update: {}", usage).unwrap();
+				return Err(());
+			}
+
+			update_channel_policy(channel_id, &update, channel_manager, out)?;
+		}
+		Some("listchannels") => {
+			let format = parse_output_format(ctx, words, out)?;
+			list_channels(channel_manager, network_graph, format, out)
+		}
+		Some("listpayments") => {
+			let format = parse_output_format(ctx, words, out)?;
+			list_payments(
+				&inbound_payments.lock().unwrap(),
+				&outbound_payments.lock().unwrap(),
+				format,
+				out,
+			)
+		}
+		Some("closechannel") => {
+			let channel_id_str = words.next();
+			if channel_id_str.is_none() {
+				writeln!(out, "ERROR: closechannel requires a channel ID: `closechannel <channel_id> <peer_pubkey>`").unwrap();
+				return Err(());
+			}
+			let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+			if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+				writeln!(out, "ERROR: couldn't parse channel_id").unwrap();
+				return Err(());
+			}
+			let mut channel_id = [0; 32];
+			channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+			let peer_pubkey_str = words.next();
+			if peer_pubkey_str.is_none() {
+				writeln!(out, "ERROR: closechannel requires a peer pubkey: `closechannel <channel_id> <peer_pubkey>`").unwrap();
+				return Err(());
+			}
+			let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
+				Some(peer_pubkey_vec) => peer_pubkey_vec,
+				None => {
+					writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+					return Err(());
+				}
+			};
+			let peer_pubkey = match PublicKey::from_slice(&peer_pubkey_vec) {
+				Ok(peer_pubkey) => peer_pubkey,
+				Err(_) => {
+					writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+					return Err(());
+				}
+			};
+
+			let (mut address, mut feerate_sat_per_kw) = (None, None);
+			while let Some(word) = words.next() {
+				let (flag, value) = match word.split_once('=') {
+					Some((flag, value)) => (flag, Some(value)),
+					None => (word, words.next()),
+				};
+				let value = match value {
+					Some(value) => value,
+					None => {
+						writeln!(out, "ERROR: `{}` requires a value", flag).unwrap();
+						return Err(());
+					}
+				};
+				match flag {
+					"--address" => address = Some(parse_address(value, *network, out)?),
+					"--feerate" => {
+						feerate_sat_per_kw = Some(parse_feerate_sat_per_vbyte(value, out)?)
+					}
+					_ => {
+						writeln!(out, "ERROR: unknown option `{}`: `closechannel <channel_id> <peer_pubkey> [--address <btc_addr>] [--feerate <sat/vB>]`", flag).unwrap();
+						return Err(());
+					}
+				}
+			}
+
+			close_channel(
+				channel_id,
+				peer_pubkey,
+				address.as_ref(),
+				feerate_sat_per_kw,
+				channel_manager.clone(),
+				out,
+			)?;
+		}
+		Some("forceclosechannel") => {
+			let channel_id_str = words.next();
+			if channel_id_str.is_none() {
+				writeln!(out, "ERROR: forceclosechannel requires a channel ID: `forceclosechannel <channel_id> <peer_pubkey>`").unwrap();
+				return Err(());
+			}
+			let channel_id_vec = hex_utils::to_vec(channel_id_str.unwrap());
+			if channel_id_vec.is_none() || channel_id_vec.as_ref().unwrap().len() != 32 {
+				writeln!(out, "ERROR: couldn't parse channel_id").unwrap();
+				return Err(());
+			}
+			let mut channel_id = [0; 32];
+			channel_id.copy_from_slice(&channel_id_vec.unwrap());
+
+			let peer_pubkey_str = words.next();
+			if peer_pubkey_str.is_none() {
+				writeln!(out, "ERROR: forceclosechannel requires a peer pubkey: `forceclosechannel <channel_id> <peer_pubkey>`").unwrap();
+				return Err(());
+			}
+			let peer_pubkey_vec = match hex_utils::to_vec(peer_pubkey_str.unwrap()) {
+				Some(peer_pubkey_vec) => peer_pubkey_vec,
+				None => {
+					writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+					return Err(());
+				}
+			};
+			let peer_pubkey = match PublicKey::from_slice(&peer_pubkey_vec) {
+				Ok(peer_pubkey) => peer_pubkey,
+				Err(_) => {
+					writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+					return Err(());
+				}
+			};
+
+			force_close_channel(channel_id, peer_pubkey, channel_manager.clone(), out)?;
+		}
+		Some("nodeinfo") => {
+			let format = parse_output_format(ctx, words, out)?;
+			node_info(channel_manager, peer_manager, chain_health, format, out)
+		}
+		Some("feerates") => {
+			let format = parse_output_format(ctx, words, out)?;
+			fee_rates(fee_estimator, fee_policy, format, out)
+		}
+		Some("newaddress") => {
+			match tokio::runtime::Handle::current().block_on(wallet.get_new_address()) {
+				Ok(address) => {
+					log_info!(logger, "Handed out on-chain address {}", address);
+					writeln!(out, "{}", address).unwrap()
+				}
+				Err(e) => {
+					writeln!(out, "ERROR: failed to get a new address: {}", e).unwrap();
+					return Err(());
+				}
+			}
+		}
+		// `getbalance` and `sendtoaddress` are kept as aliases, matching bitcoind's names.
+		Some("onchainbalance") | Some("getbalance") => {
+			let format = parse_output_format(ctx, words, out)?;
+			onchain_balance(wallet, format, out)?
+		}
+		Some("listunspent") => {
+			let format = parse_output_format(ctx, words, out)?;
+			list_unspent(wallet, format, out)?
+		}
+		Some("sendonchain") | Some("sendtoaddress") => {
+			let (address_str, amount_str) = match (words.next(), words.next()) {
+				(Some(address), Some(amount)) => (address, amount),
+				_ => {
+					writeln!(out, "ERROR: sendonchain has 2 required arguments: `sendonchain <address> <amount_sats> [<feerate_sat_per_vbyte>]`").unwrap();
+					return Err(());
+				}
+			};
+			let address = parse_address(address_str, *network, out)?;
+			let amount_sats = parse_send_amount_sats(amount_str, out)?;
+			// Without an explicit feerate, pay what we'd pay to fund a channel.
+			let feerate_sat_per_kw = match words.next() {
+				Some(feerate) => parse_feerate_sat_per_vbyte(feerate, out)?,
+				None => fee_estimator
+					.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee),
+			};
+			send_on_chain(wallet, &address, amount_sats, feerate_sat_per_kw, logger, out)?;
+		}
+		Some("abandontransaction") => {
+			let txid = match words.next().map(Txid::from_str) {
+				Some(Ok(txid)) => txid,
+				_ => {
+					writeln!(out, "ERROR: abandontransaction requires a transaction id: `abandontransaction <txid>`").unwrap();
+					return Err(());
+				}
+			};
+			abandon_transaction(wallet, &txid, channel_manager, logger, out)?;
+		}
+		Some("listpeers") => {
+			let format = parse_output_format(ctx, words, out)?;
+			list_peers(peer_manager.clone(), format, out)
+		}
+		Some("setoutput") => {
+			let format = match words.next() {
+				Some("json") => OutputFormat::Json,
+				Some("text") => OutputFormat::Text,
+				_ => {
+					writeln!(out, "ERROR: setoutput requires a format: `setoutput <json|text>`")
+						.unwrap();
+					return Err(());
+				}
+			};
+			*ctx.output_format.lock().unwrap() = format;
+			writeln!(out, "SUCCESS: listing commands now print {}", output_format_str(format))
+				.unwrap();
+		}
+		Some("signmessage") => {
+			let line = line.trim();
+			const MSG_STARTPOS: usize = "signmessage".len() + 1;
+			if line.as_bytes().len() <= MSG_STARTPOS {
+				writeln!(out, "ERROR: signmsg requires a message").unwrap();
+				return Err(());
+			}
+			writeln!(
+				out,
+				"{:?}",
+				lightning::util::message_signing::sign(
+					&line.as_bytes()[MSG_STARTPOS..],
+					&keys_manager.get_node_secret_key()
+				)
+			)
+			.unwrap();
+		}
+		Some("sendonionmessage") => {
+			let path_pks_str = words.next();
+			if path_pks_str.is_none() {
+				writeln!(out, "ERROR: sendonionmessage requires at least one node id for the path")
+					.unwrap();
+				return Err(());
+			}
+			let mut intermediate_nodes = Vec::new();
+			for pk_str in path_pks_str.unwrap().split(",") {
+				let node_pubkey_vec = match hex_utils::to_vec(pk_str) {
+					Some(peer_pubkey_vec) => peer_pubkey_vec,
+					None => {
+						writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+						return Err(());
+					}
+				};
+				let node_pubkey = match PublicKey::from_slice(&node_pubkey_vec) {
+					Ok(peer_pubkey) => peer_pubkey,
+					Err(_) => {
+						writeln!(out, "ERROR: couldn't parse peer_pubkey").unwrap();
+						return Err(());
 					}
+				};
+				intermediate_nodes.push(node_pubkey);
+			}
+			let tlv_type = match words.next().map(|ty_str| ty_str.parse()) {
+				Some(Ok(ty)) if ty >= 64 => ty,
+				_ => {
+					writeln!(out, "Need an integral message type above 64").unwrap();
+					return Err(());
+				}
+			};
+			let data = match words.next().map(|s| hex_utils::to_vec(s)) {
+				Some(Some(data)) => data,
+				_ => {
+					writeln!(out, "Need a hex data string").unwrap();
+					return Err(());
+				}
+			};
+			let destination = Destination::Node(intermediate_nodes.pop().unwrap());
+			match onion_messenger.send_onion_message(
+				UserOnionMessageContents { tlv_type, data },
+				destination,
+				None,
+			) {
+				Ok(success) => {
+					writeln!(out, "SUCCESS: forwarded onion message to first hop {:?}", success)
+						.unwrap()
 				}
-				"quit" | "exit" => break,
-				_ => println!("Unknown command. See `\"help\" for available commands."),
+				Err(e) => {
+					writeln!(out, "ERROR: failed to send onion message: {:?}", e).unwrap();
+					return Err(());
+				}
+			}
+		}
+		Some("changepassphrase") => {
+			if !interactive {
+				writeln!(out, "ERROR: changepassphrase prompts for the passphrases, so it is only available from the interactive prompt").unwrap();
+				return Err(());
 			}
+			let keys_seed_path = format!("{}/keys_seed", ldk_data_dir);
+			if seed::change_passphrase(Path::new(&keys_seed_path), keys_seed_backup_path.as_deref())
+				.is_err()
+			{
+				writeln!(out, "ERROR: failed to change the keys seed passphrase").unwrap();
+				return Err(());
+			}
+			writeln!(out, "SUCCESS: changed the keys seed passphrase").unwrap();
+		}
+		Some(_) => {
+			writeln!(out, "Unknown command. See `\"help\" for available commands.").unwrap();
+			return Err(());
 		}
+		None => {}
 	}
+	Ok(())
 }
 
-fn help() {
+fn help(out: &mut dyn Write) {
 	let package_version = env!("CARGO_PKG_VERSION");
 	let package_name = env!("CARGO_PKG_NAME");
-	println!("\nVERSION:");
-	println!("  {} v{}", package_name, package_version);
-	println!("\nUSAGE:");
-	println!("  Command [arguments]");
-	println!("\nCOMMANDS:");
-	println!("  help\tShows a list of commands.");
-	println!("  quit\tClose the application.");
-	println!("\n  Channels:");
-	println!("      openchannel pubkey@host:port <amt_satoshis> [--public] [--with-anchors]");
-	println!("      closechannel <channel_id> <peer_pubkey>");
-	println!("      forceclosechannel <channel_id> <peer_pubkey>");
-	println!("      listchannels");
-	println!("\n  Peers:");
-	println!("      connectpeer pubkey@host:port");
-	println!("      disconnectpeer <peer_pubkey>");
-	println!("      listpeers");
-	println!("\n  Payments:");
-	println!("      sendpayment <invoice|offer> [<amount_msat>]");
-	println!("      keysend <dest_pubkey> <amt_msats>");
-	println!("      listpayments");
-	println!("\n  Invoices:");
-	println!("      getinvoice <amt_msats> <expiry_secs>");
-	println!("      getoffer [<amt_msats>]");
-	println!("\n  Other:");
-	println!("      signmessage <message>");
-	println!(
+	writeln!(out, "\nVERSION:").unwrap();
+	writeln!(out, "  {} v{}", package_name, package_version).unwrap();
+	writeln!(out, "\nUSAGE:").unwrap();
+	writeln!(out, "  Command [arguments]").unwrap();
+	writeln!(out, "\nCOMMANDS:").unwrap();
+	writeln!(out, "  help\tShows a list of commands.").unwrap();
+	writeln!(out, "  quit\tClose the application.").unwrap();
+	writeln!(out, "\n  Channels:").unwrap();
+	writeln!(
+		out,
+		"      openchannel pubkey@host:port <amt_satoshis> [--public] [--with-anchors] [--zeroconf=false]"
+	)
+	.unwrap();
+	writeln!(out, "                  [--push-msat=<msat>] [--to-self-delay=<blocks>] [--max-htlc-in-flight=<percent>]").unwrap();
+	writeln!(out, "                  [--htlc-minimum=<msat>] [--fee-ppm=<ppm>] [--base-fee=<msat>] [--user-channel-id=<id>]").unwrap();
+	writeln!(out, "                  [--channel-reserve=<sats>]").unwrap();
+	writeln!(out, "                  The peer decides whether the channel is zero-conf, --zeroconf=false waits for it to confirm.").unwrap();
+	writeln!(out, "      openchannels pubkey@host:port <amt_satoshis> [pubkey@host:port <amt_satoshis>]... [--public] [--with-anchors]").unwrap();
+	writeln!(
+		out,
+		"      closechannel <channel_id> <peer_pubkey> [--address <btc_addr>] [--feerate <sat/vB>]"
+	)
+	.unwrap();
+	writeln!(out, "      updatechannelpolicy <channel_id|all> [--base-fee-msat=<msat>] [--fee-ppm=<ppm>] [--cltv-delta=<blocks>]").unwrap();
+	writeln!(out, "                  --max-htlc-msat is refused, LDK 0.0.121 can't change a channel's htlc_maximum_msat after it's opened.").unwrap();
+	writeln!(out, "      forceclosechannel <channel_id> <peer_pubkey>").unwrap();
+	writeln!(out, "      listchannels [--json]").unwrap();
+	writeln!(out, "\n  Peers:").unwrap();
+	writeln!(out, "      connectpeer pubkey@host:port").unwrap();
+	writeln!(out, "      disconnectpeer <peer_pubkey>").unwrap();
+	writeln!(out, "      listpeers [--json]").unwrap();
+	writeln!(out, "\n  Payments:").unwrap();
+	writeln!(out, "      sendpayment <invoice|offer> [<amount_msat>]").unwrap();
+	writeln!(out, "      keysend <dest_pubkey> <amt_msats>").unwrap();
+	writeln!(out, "      listpayments [--json]").unwrap();
+	writeln!(out, "\n  Invoices:").unwrap();
+	writeln!(out, "      getinvoice <amt_msats> <expiry_secs>").unwrap();
+	writeln!(out, "      getoffer [<amt_msats>]").unwrap();
+	writeln!(out, "\n  On-chain wallet:").unwrap();
+	writeln!(out, "      newaddress").unwrap();
+	writeln!(out, "      onchainbalance [--json]").unwrap();
+	writeln!(out, "      listunspent [--json]").unwrap();
+	writeln!(out, "      sendonchain <address> <amount_sats> [<feerate_sat_per_vbyte>]").unwrap();
+	writeln!(out, "      abandontransaction <txid>").unwrap();
+	writeln!(out, "\n  Other:").unwrap();
+	writeln!(out, "      signmessage <message>").unwrap();
+	writeln!(
+		out,
 		"      sendonionmessage <node_id_1,node_id_2,..,destination_node_id> <type> <hex_bytes>"
-	);
-	println!("      nodeinfo");
+	)
+	.unwrap();
+	writeln!(out, "      nodeinfo [--json]").unwrap();
+	writeln!(out, "      feerates [--json]").unwrap();
+	writeln!(out, "      setoutput <json|text>").unwrap();
+	writeln!(out, "      changepassphrase").unwrap();
 }
 
-fn node_info(channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>) {
-	println!("\t{{");
-	println!("\t\t node_pubkey: {}", channel_manager.get_our_node_id());
+fn node_info(
+	channel_manager: &Arc<ChannelManager>, peer_manager: &Arc<PeerManager>,
+	chain_health: &ChainSourceHealth, format: OutputFormat, out: &mut dyn Write,
+) {
 	let chans = channel_manager.list_channels();
-	println!("\t\t num_channels: {}", chans.len());
-	println!("\t\t num_usable_channels: {}", chans.iter().filter(|c| c.is_usable).count());
+	let num_usable_channels = chans.iter().filter(|c| c.is_usable).count();
 	let local_balance_msat = chans.iter().map(|c| c.balance_msat).sum::<u64>();
-	println!("\t\t local_balance_msat: {}", local_balance_msat);
-	println!("\t\t num_peers: {}", peer_manager.get_peer_node_ids().len());
-	println!("\t}},");
+	let num_peers = peer_manager.get_peer_node_ids().len();
+	if format == OutputFormat::Json {
+		let info = json!({
+			"node_pubkey": channel_manager.get_our_node_id().to_string(),
+			"num_channels": chans.len(),
+			"num_usable_channels": num_usable_channels,
+			"local_balance_msat": local_balance_msat,
+			"num_peers": num_peers,
+			"chain_source_healthy": chain_health.is_healthy(),
+			"chain_source_consecutive_failures": chain_health.consecutive_failures(),
+			"chain_source_last_error": chain_health.last_error(),
+			"chain_source_last_success_time": chain_health.last_success_time(),
+		});
+		return write_json(&info, out);
+	}
+	writeln!(out, "\t{{").unwrap();
+	writeln!(out, "\t\t node_pubkey: {}", channel_manager.get_our_node_id()).unwrap();
+	writeln!(out, "\t\t num_channels: {}", chans.len()).unwrap();
+	writeln!(out, "\t\t num_usable_channels: {}", num_usable_channels).unwrap();
+	writeln!(out, "\t\t local_balance_msat: {}", local_balance_msat).unwrap();
+	writeln!(out, "\t\t num_peers: {}", num_peers).unwrap();
+	writeln!(out, "\t\t chain_source_healthy: {}", chain_health.is_healthy()).unwrap();
+	if !chain_health.is_healthy() {
+		writeln!(
+			out,
+			"\t\t chain_source_last_error: {} ({} consecutive failures)",
+			chain_health.last_error().unwrap_or_default(),
+			chain_health.consecutive_failures()
+		)
+		.unwrap();
+	}
+	writeln!(out, "\t}},").unwrap();
 }
 
-fn list_peers(peer_manager: Arc<PeerManager>) {
-	println!("\t{{");
+/// Prints the feerates we currently give LDK for each confirmation target.
+fn fee_rates(
+	fee_estimator: &Arc<DynFeeEstimator>, fee_policy: &FeePolicy, format: OutputFormat,
+	out: &mut dyn Write,
+) {
+	let rates = fee_policy::CONFIRMATION_TARGETS.iter().map(|(target, name)| {
+		let is_static = fee_policy.get(target).static_feerate.is_some();
+		(*name, fee_estimator.get_est_sat_per_1000_weight(*target), is_static)
+	});
+	if format == OutputFormat::Json {
+		let rates: Vec<Value> = rates
+			.map(|(name, sat_per_kw, is_static)| {
+				json!({
+					"confirmation_target": name,
+					"sat_per_kw": sat_per_kw,
+					"sat_per_vbyte": sat_per_kw as f64 / 250.0,
+					"static": is_static,
+				})
+			})
+			.collect();
+		return write_json(&Value::Array(rates), out);
+	}
+	writeln!(out, "\t{{").unwrap();
+	for (name, sat_per_kw, is_static) in rates {
+		writeln!(
+			out,
+			"\t\t {}: {} sat/KW ({} sat/vB){}",
+			name,
+			sat_per_kw,
+			sat_per_kw as f64 / 250.0,
+			if is_static { " (static)" } else { "" }
+		)
+		.unwrap();
+	}
+	writeln!(out, "\t}},").unwrap();
+}
+
+fn onchain_balance(
+	wallet: &WalletBackend, format: OutputFormat, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let balance = match tokio::runtime::Handle::current().block_on(wallet.balance()) {
+		Ok(balance) => balance,
+		Err(e) => {
+			writeln!(out, "ERROR: failed to get the on-chain balance: {}", e).unwrap();
+			return Err(());
+		}
+	};
+	if format == OutputFormat::Json {
+		write_json(
+			&json!({
+				"confirmed_sats": balance.confirmed_sats,
+				"unconfirmed_sats": balance.unconfirmed_sats,
+			}),
+			out,
+		);
+		return Ok(());
+	}
+	writeln!(out, "\t{{").unwrap();
+	writeln!(out, "\t\t confirmed_sats: {},", balance.confirmed_sats).unwrap();
+	writeln!(out, "\t\t unconfirmed_sats: {},", balance.unconfirmed_sats).unwrap();
+	writeln!(out, "\t}},").unwrap();
+	Ok(())
+}
+
+fn list_unspent(
+	wallet: &WalletBackend, format: OutputFormat, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let utxos = match tokio::runtime::Handle::current().block_on(wallet.list_unspent()) {
+		Ok(utxos) => utxos,
+		Err(e) => {
+			writeln!(out, "ERROR: failed to list unspent outputs: {}", e).unwrap();
+			return Err(());
+		}
+	};
+	if format == OutputFormat::Json {
+		let utxos: Vec<Value> = utxos
+			.iter()
+			.map(|utxo| {
+				json!({
+					"outpoint": utxo.outpoint.to_string(),
+					"address": utxo.address.to_string(),
+					"amount_sats": utxo.amount_sats,
+					"confirmations": utxo.confirmations,
+				})
+			})
+			.collect();
+		write_json(&Value::Array(utxos), out);
+		return Ok(());
+	}
+	writeln!(out, "[").unwrap();
+	for utxo in utxos {
+		writeln!(out, "\t{{").unwrap();
+		writeln!(out, "\t\t outpoint: {},", utxo.outpoint).unwrap();
+		writeln!(out, "\t\t address: {},", utxo.address).unwrap();
+		writeln!(out, "\t\t amount_sats: {},", utxo.amount_sats).unwrap();
+		writeln!(out, "\t\t confirmations: {},", utxo.confirmations).unwrap();
+		writeln!(out, "\t}},").unwrap();
+	}
+	writeln!(out, "]").unwrap();
+	Ok(())
+}
+
+fn send_on_chain(
+	wallet: &WalletBackend, address: &Address, amount_sats: u64, feerate_sat_per_kw: u32,
+	logger: &disk::FilesystemLogger, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let send = wallet.send_to_address(address, amount_sats, feerate_sat_per_kw);
+	match tokio::runtime::Handle::current().block_on(send) {
+		Ok(txid) => {
+			log_info!(
+				logger,
+				"Sent {} sats to {} at {} sat/KW in transaction {}",
+				amount_sats,
+				address,
+				feerate_sat_per_kw,
+				txid
+			);
+			writeln!(
+				out,
+				"SUCCESS: sent {} sats to {} in transaction {}",
+				amount_sats, address, txid
+			)
+			.unwrap();
+			Ok(())
+		}
+		Err(e) => {
+			log_error!(logger, "Failed to send {} sats to {}: {}", amount_sats, address, e);
+			writeln!(out, "ERROR: failed to send: {}", e).unwrap();
+			Err(())
+		}
+	}
+}
+
+/// Gives up on a wallet transaction which won't confirm, e.g. because it was never relayed, so
+/// that its inputs aren't locked forever. Channel funding transactions are refused, as LDK may
+/// still broadcast them.
+fn abandon_transaction(
+	wallet: &WalletBackend, txid: &Txid, channel_manager: &ChannelManager,
+	logger: &disk::FilesystemLogger, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let is_funding = channel_manager
+		.list_channels()
+		.iter()
+		.any(|chan_info| chan_info.funding_txo.map(|outpoint| outpoint.txid) == Some(*txid));
+	if is_funding {
+		writeln!(out, "ERROR: {} funds a channel, close the channel instead", txid).unwrap();
+		return Err(());
+	}
+	match tokio::runtime::Handle::current().block_on(wallet.abandon_transaction(txid)) {
+		Ok(()) => {
+			log_info!(logger, "Abandoned transaction {}", txid);
+			writeln!(out, "SUCCESS: abandoned transaction {}", txid).unwrap();
+			Ok(())
+		}
+		Err(e) => {
+			writeln!(out, "ERROR: failed to abandon transaction {}: {}", txid, e).unwrap();
+			Err(())
+		}
+	}
+}
+
+fn list_peers(peer_manager: Arc<PeerManager>, format: OutputFormat, out: &mut dyn Write) {
+	if format == OutputFormat::Json {
+		let peers: Vec<Value> = peer_manager
+			.get_peer_node_ids()
+			.into_iter()
+			.map(|(pubkey, addr)| {
+				json!({
+					"pubkey": pubkey.to_string(),
+					"address": addr.map(|addr| addr.to_string()),
+				})
+			})
+			.collect();
+		return write_json(&Value::Array(peers), out);
+	}
+	writeln!(out, "\t{{").unwrap();
 	for (pubkey, _) in peer_manager.get_peer_node_ids() {
-		println!("\t\t pubkey: {}", pubkey);
+		writeln!(out, "\t\t pubkey: {}", pubkey).unwrap();
 	}
-	println!("\t}},");
+	writeln!(out, "\t}},").unwrap();
 }
 
-fn list_channels(channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>) {
-	print!("[");
+fn list_channels(
+	channel_manager: &Arc<ChannelManager>, network_graph: &Arc<NetworkGraph>, format: OutputFormat,
+	out: &mut dyn Write,
+) {
+	if format == OutputFormat::Json {
+		let graph = network_graph.read_only();
+		let channels: Vec<Value> = channel_manager
+			.list_channels()
+			.into_iter()
+			.map(|chan_info| {
+				let peer_alias = graph
+					.nodes()
+					.get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
+					.and_then(|node_info| node_info.announcement_info.as_ref())
+					.map(|announcement| announcement.alias.to_string());
+				json!({
+					"channel_id": chan_info.channel_id.to_string(),
+					"funding_txid": chan_info.funding_txo.map(|txo| txo.txid.to_string()),
+					"peer_pubkey": chan_info.counterparty.node_id.to_string(),
+					"peer_alias": peer_alias,
+					"short_channel_id": chan_info.short_channel_id,
+					"is_channel_ready": chan_info.is_channel_ready,
+					"confirmations": chan_info.confirmations,
+					"confirmations_required": chan_info.confirmations_required,
+					"channel_value_satoshis": chan_info.channel_value_satoshis,
+					"outbound_capacity_msat": chan_info.outbound_capacity_msat,
+					"available_balance_for_send_msat":
+						chan_info.is_usable.then_some(chan_info.outbound_capacity_msat),
+					"available_balance_for_recv_msat":
+						chan_info.is_usable.then_some(chan_info.inbound_capacity_msat),
+					"channel_can_send_payments": chan_info.is_usable,
+					"public": chan_info.is_public,
+					"config": chan_info.config.map(|config| json!({
+						"forwarding_fee_base_msat": config.forwarding_fee_base_msat,
+						"forwarding_fee_proportional_millionths":
+							config.forwarding_fee_proportional_millionths,
+						"cltv_expiry_delta": config.cltv_expiry_delta,
+						"force_close_avoidance_max_fee_satoshis":
+							config.force_close_avoidance_max_fee_satoshis,
+						"accept_underpaying_htlcs": config.accept_underpaying_htlcs,
+					})),
+				})
+			})
+			.collect();
+		return write_json(&Value::Array(channels), out);
+	}
+	write!(out, "[").unwrap();
 	for chan_info in channel_manager.list_channels() {
-		println!("");
-		println!("\t{{");
-		println!("\t\tchannel_id: {},", chan_info.channel_id);
+		writeln!(out, "").unwrap();
+		writeln!(out, "\t{{").unwrap();
+		writeln!(out, "\t\tchannel_id: {},", chan_info.channel_id).unwrap();
 		if let Some(funding_txo) = chan_info.funding_txo {
-			println!("\t\tfunding_txid: {},", funding_txo.txid);
+			writeln!(out, "\t\tfunding_txid: {},", funding_txo.txid).unwrap();
 		}
 
-		println!(
+		writeln!(
+			out,
 			"\t\tpeer_pubkey: {},",
 			hex_utils::hex_str(&chan_info.counterparty.node_id.serialize())
-		);
+		)
+		.unwrap();
 		if let Some(node_info) = network_graph
 			.read_only()
 			.nodes()
 			.get(&NodeId::from_pubkey(&chan_info.counterparty.node_id))
 		{
 			if let Some(announcement) = &node_info.announcement_info {
-				println!("\t\tpeer_alias: {}", announcement.alias);
+				writeln!(out, "\t\tpeer_alias: {}", announcement.alias).unwrap();
 			}
 		}
 
 		if let Some(id) = chan_info.short_channel_id {
-			println!("\t\tshort_channel_id: {},", id);
+			writeln!(out, "\t\tshort_channel_id: {},", id).unwrap();
+		}
+		writeln!(out, "\t\tis_channel_ready: {},", chan_info.is_channel_ready).unwrap();
+		if let Some(confirmations) = chan_info.confirmations {
+			writeln!(out, "\t\tconfirmations: {},", confirmations).unwrap();
 		}
-		println!("\t\tis_channel_ready: {},", chan_info.is_channel_ready);
-		println!("\t\tchannel_value_satoshis: {},", chan_info.channel_value_satoshis);
-		println!("\t\toutbound_capacity_msat: {},", chan_info.outbound_capacity_msat);
+		if let Some(confirmations_required) = chan_info.confirmations_required {
+			writeln!(out, "\t\tconfirmations_required: {},", confirmations_required).unwrap();
+		}
+		writeln!(out, "\t\tchannel_value_satoshis: {},", chan_info.channel_value_satoshis).unwrap();
+		writeln!(out, "\t\toutbound_capacity_msat: {},", chan_info.outbound_capacity_msat).unwrap();
 		if chan_info.is_usable {
-			println!("\t\tavailable_balance_for_send_msat: {},", chan_info.outbound_capacity_msat);
-			println!("\t\tavailable_balance_for_recv_msat: {},", chan_info.inbound_capacity_msat);
+			writeln!(
+				out,
+				"\t\tavailable_balance_for_send_msat: {},",
+				chan_info.outbound_capacity_msat
+			)
+			.unwrap();
+			writeln!(
+				out,
+				"\t\tavailable_balance_for_recv_msat: {},",
+				chan_info.inbound_capacity_msat
+			)
+			.unwrap();
+		}
+		writeln!(out, "\t\tchannel_can_send_payments: {},", chan_info.is_usable).unwrap();
+		writeln!(out, "\t\tpublic: {},", chan_info.is_public).unwrap();
+		if let Some(config) = chan_info.config {
+			writeln!(out, "\t\tforwarding_fee_base_msat: {},", config.forwarding_fee_base_msat)
+				.unwrap();
+			writeln!(
+				out,
+				"\t\tforwarding_fee_proportional_millionths: {},",
+				config.forwarding_fee_proportional_millionths
+			)
+			.unwrap();
+			writeln!(out, "\t\tcltv_expiry_delta: {},", config.cltv_expiry_delta).unwrap();
 		}
-		println!("\t\tchannel_can_send_payments: {},", chan_info.is_usable);
-		println!("\t\tpublic: {},", chan_info.is_public);
-		println!("\t}},");
+		writeln!(out, "\t}},").unwrap();
+	}
+	writeln!(out, "]").unwrap();
+}
+
+fn htlc_status_str(status: &HTLCStatus) -> &'static str {
+	match status {
+		HTLCStatus::Pending => "pending",
+		HTLCStatus::Succeeded => "succeeded",
+		HTLCStatus::Failed => "failed",
 	}
-	println!("]");
 }
 
 fn list_payments(
 	inbound_payments: &InboundPaymentInfoStorage, outbound_payments: &OutboundPaymentInfoStorage,
+	format: OutputFormat, out: &mut dyn Write,
 ) {
-	print!("[");
-	for (payment_hash, payment_info) in &inbound_payments.payments {
-		println!("");
-		println!("\t{{");
-		println!("\t\tamount_millisatoshis: {},", payment_info.amt_msat);
-		println!("\t\tpayment_hash: {},", payment_hash);
-		println!("\t\thtlc_direction: inbound,");
-		println!(
-			"\t\thtlc_status: {},",
-			match payment_info.status {
-				HTLCStatus::Pending => "pending",
-				HTLCStatus::Succeeded => "succeeded",
-				HTLCStatus::Failed => "failed",
-			}
-		);
-
-		println!("\t}},");
+	let payments =
+		inbound_payments
+			.payments
+			.iter()
+			.map(|(payment_hash, payment_info)| (payment_hash.to_string(), payment_info, "inbound"))
+			.chain(outbound_payments.payments.iter().map(|(payment_id, payment_info)| {
+				(payment_id.to_string(), payment_info, "outbound")
+			}));
+	if format == OutputFormat::Json {
+		let payments: Vec<Value> = payments
+			.map(|(payment_hash, payment_info, direction)| {
+				json!({
+					"amount_millisatoshis": payment_info.amt_msat.0,
+					"payment_hash": payment_hash,
+					"htlc_direction": direction,
+					"htlc_status": htlc_status_str(&payment_info.status),
+				})
+			})
+			.collect();
+		return write_json(&Value::Array(payments), out);
+	}
+	write!(out, "[").unwrap();
+	for (payment_hash, payment_info, direction) in payments {
+		writeln!(out, "").unwrap();
+		writeln!(out, "\t{{").unwrap();
+		writeln!(out, "\t\tamount_millisatoshis: {},", payment_info.amt_msat).unwrap();
+		writeln!(out, "\t\tpayment_hash: {},", payment_hash).unwrap();
+		writeln!(out, "\t\thtlc_direction: {},", direction).unwrap();
+		writeln!(out, "\t\thtlc_status: {},", htlc_status_str(&payment_info.status)).unwrap();
+		writeln!(out, "\t}},").unwrap();
 	}
+	writeln!(out, "]").unwrap();
+}
 
-	for (payment_hash, payment_info) in &outbound_payments.payments {
-		println!("");
-		println!("\t{{");
-		println!("\t\tamount_millisatoshis: {},", payment_info.amt_msat);
-		println!("\t\tpayment_hash: {},", payment_hash);
-		println!("\t\thtlc_direction: outbound,");
-		println!(
-			"\t\thtlc_status: {},",
-			match payment_info.status {
-				HTLCStatus::Pending => "pending",
-				HTLCStatus::Succeeded => "succeeded",
-				HTLCStatus::Failed => "failed",
+/// Reads the optional `--json`/`--text` flag of a listing command, falling back to the format set
+/// with `setoutput`.
+fn parse_output_format(
+	ctx: &NodeContext, words: SplitWhitespace, out: &mut dyn Write,
+) -> Result<OutputFormat, ()> {
+	let mut format = *ctx.output_format.lock().unwrap();
+	for word in words {
+		match word {
+			"--json" => format = OutputFormat::Json,
+			"--text" => format = OutputFormat::Text,
+			_ => {
+				writeln!(
+					out,
+					"ERROR: unexpected argument `{}`, expected `--json` or `--text`",
+					word
+				)
+				.unwrap();
+				return Err(());
 			}
-		);
+		}
+	}
+	Ok(format)
+}
 
-		println!("\t}},");
+fn output_format_str(format: OutputFormat) -> &'static str {
+	match format {
+		OutputFormat::Text => "text",
+		OutputFormat::Json => "json",
 	}
-	println!("]");
+}
+
+fn write_json(value: &Value, out: &mut dyn Write) {
+	writeln!(out, "{}", serde_json::to_string_pretty(value).unwrap()).unwrap();
 }
 
 pub(crate) async fn connect_peer_if_necessary(
@@ -706,11 +1450,7 @@ pub(crate) async fn connect_peer_if_necessary(
 			return Ok(());
 		}
 	}
-	let res = do_connect_peer(pubkey, peer_addr, peer_manager).await;
-	if res.is_err() {
-		println!("ERROR: failed to connect to peer");
-	}
-	res
+	do_connect_peer(pubkey, peer_addr, peer_manager).await
 }
 
 pub(crate) async fn do_connect_peer(
@@ -736,12 +1476,16 @@ pub(crate) async fn do_connect_peer(
 
 fn do_disconnect_peer(
 	pubkey: bitcoin::secp256k1::PublicKey, peer_manager: Arc<PeerManager>,
-	channel_manager: Arc<ChannelManager>,
+	channel_manager: Arc<ChannelManager>, out: &mut dyn Write,
 ) -> Result<(), ()> {
 	//check for open channels with peer
 	for channel in channel_manager.list_channels() {
 		if channel.counterparty.node_id == pubkey {
-			println!("Error: Node has an active channel with this peer, close any channels first");
+			writeln!(
+				out,
+				"Error: Node has an active channel with this peer, close any channels first"
+			)
+			.unwrap();
 			return Err(());
 		}
 	}
@@ -749,7 +1493,7 @@ fn do_disconnect_peer(
 	//check the pubkey matches a valid connected peer
 	let peers = peer_manager.get_peer_node_ids();
 	if !peers.iter().any(|(pk, _)| &pubkey == pk) {
-		println!("Error: Could not find peer {}", pubkey);
+		writeln!(out, "Error: Could not find peer {}", pubkey).unwrap();
 		return Err(());
 	}
 
@@ -757,40 +1501,285 @@ fn do_disconnect_peer(
 	Ok(())
 }
 
-fn open_channel(
-	peer_pubkey: PublicKey, channel_amt_sat: u64, announced_channel: bool, with_anchors: bool,
-	channel_manager: Arc<ChannelManager>,
-) -> Result<(), ()> {
-	let config = UserConfig {
+// lnd's max to_self_delay is 2016, so we want to be compatible.
+const MAX_TO_SELF_DELAY: u16 = 2016;
+
+/// How `openchannel` sets up a channel, beyond its peer and amount.
+#[derive(Debug, PartialEq)]
+struct OpenChannelOptions {
+	announced_channel: bool,
+	with_anchors: bool,
+	/// Whether to use the channel before its funding transaction confirms if the peer accepts it as
+	/// zero-conf. We can't ask the peer to, so this only lets us agree when it does.
+	zero_conf: bool,
+	push_msat: u64,
+	/// How long the peer has to wait to claim its funds after force-closing.
+	to_self_delay: Option<u16>,
+	/// The share of the channel the peer may have in-flight towards us at once, in percent.
+	max_htlc_in_flight_percent: Option<u8>,
+	htlc_minimum_msat: Option<u64>,
+	fee_ppm: Option<u32>,
+	base_fee_msat: Option<u32>,
+	user_channel_id: u128,
+	/// The reserve the peer has to keep in the channel.
+	channel_reserve_sats: Option<u64>,
+}
+
+impl Default for OpenChannelOptions {
+	fn default() -> Self {
+		Self {
+			announced_channel: false,
+			with_anchors: false,
+			// LDK's default, as the funding transaction is ours.
+			zero_conf: true,
+			push_msat: 0,
+			to_self_delay: None,
+			max_htlc_in_flight_percent: None,
+			htlc_minimum_msat: None,
+			fee_ppm: None,
+			base_fee_msat: None,
+			user_channel_id: 0,
+			channel_reserve_sats: None,
+		}
+	}
+}
+
+/// Parses `openchannel`'s flags and checks them against the channel amount, so that bad values are
+/// reported before we ask LDK to open the channel.
+fn parse_open_channel_options(
+	words: SplitWhitespace, channel_amt_sat: u64, out: &mut dyn Write,
+) -> Result<OpenChannelOptions, ()> {
+	let mut options = OpenChannelOptions::default();
+	for word in words {
+		match word {
+			"--public" | "--public=true" => options.announced_channel = true,
+			"--public=false" => options.announced_channel = false,
+			"--with-anchors" | "--with-anchors=true" => options.with_anchors = true,
+			"--with-anchors=false" => options.with_anchors = false,
+			// Whoever accepts the channel decides whether it's zero-conf, all we can do is refuse to
+			// use it before it confirms, so only the opt-out is accepted.
+			"--zeroconf" | "--zeroconf=true" => {
+				writeln!(out, "ERROR: the peer decides whether a channel we open is zero-conf, we use it as such by default. Use `--zeroconf=false` to wait for the funding transaction to confirm").unwrap();
+				return Err(());
+			}
+			"--zeroconf=false" => options.zero_conf = false,
+			_ => {
+				let (flag, value) = match word.split_once('=') {
+					Some(flag_and_value) => flag_and_value,
+					None => {
+						writeln!(out, "ERROR: invalid flag `{}`. Valid formats: `--option`, `--option=true`, `--option=false` and `--option=<value>`", word).unwrap();
+						return Err(());
+					}
+				};
+				match flag {
+					"--push-msat" => options.push_msat = parse_option_value(flag, value, out)?,
+					"--to-self-delay" => {
+						options.to_self_delay = Some(parse_option_value(flag, value, out)?)
+					}
+					"--max-htlc-in-flight" => {
+						options.max_htlc_in_flight_percent =
+							Some(parse_option_value(flag, value, out)?)
+					}
+					"--htlc-minimum" => {
+						options.htlc_minimum_msat = Some(parse_option_value(flag, value, out)?)
+					}
+					"--fee-ppm" => options.fee_ppm = Some(parse_option_value(flag, value, out)?),
+					"--base-fee" => {
+						options.base_fee_msat = Some(parse_option_value(flag, value, out)?)
+					}
+					"--user-channel-id" => {
+						options.user_channel_id = parse_option_value(flag, value, out)?
+					}
+					"--channel-reserve" => {
+						options.channel_reserve_sats = Some(parse_option_value(flag, value, out)?)
+					}
+					_ => {
+						writeln!(out, "ERROR: unknown option `{}`", flag).unwrap();
+						return Err(());
+					}
+				}
+			}
+		}
+	}
+
+	if channel_amt_sat > Amount::MAX_MONEY.to_sat() {
+		writeln!(out, "ERROR: the channel amount can't be more than 21 million BTC").unwrap();
+		return Err(());
+	}
+	let channel_amt_msat = channel_amt_sat * 1000;
+	if options.push_msat > channel_amt_msat {
+		writeln!(out, "ERROR: --push-msat can't be more than the channel amount").unwrap();
+		return Err(());
+	}
+	if let Some(to_self_delay) = options.to_self_delay {
+		if !(BREAKDOWN_TIMEOUT..=MAX_TO_SELF_DELAY).contains(&to_self_delay) {
+			writeln!(
+				out,
+				"ERROR: --to-self-delay must be between {} and {} blocks",
+				BREAKDOWN_TIMEOUT, MAX_TO_SELF_DELAY
+			)
+			.unwrap();
+			return Err(());
+		}
+	}
+	if let Some(percent) = options.max_htlc_in_flight_percent {
+		if !(1..=100).contains(&percent) {
+			writeln!(out, "ERROR: --max-htlc-in-flight must be a percentage between 1 and 100")
+				.unwrap();
+			return Err(());
+		}
+	}
+	if let Some(htlc_minimum_msat) = options.htlc_minimum_msat {
+		if htlc_minimum_msat >= channel_amt_msat {
+			writeln!(out, "ERROR: --htlc-minimum must be less than the channel amount").unwrap();
+			return Err(());
+		}
+	}
+	if let Some(channel_reserve_sats) = options.channel_reserve_sats {
+		if channel_reserve_sats > channel_amt_sat {
+			writeln!(out, "ERROR: --channel-reserve can't be more than the channel amount")
+				.unwrap();
+			return Err(());
+		}
+	}
+	Ok(options)
+}
+
+fn channel_config(options: &OpenChannelOptions, channel_amt_sat: u64) -> UserConfig {
+	let mut config = UserConfig {
 		channel_handshake_limits: ChannelHandshakeLimits {
-			// lnd's max to_self_delay is 2016, so we want to be compatible.
-			their_to_self_delay: 2016,
+			their_to_self_delay: MAX_TO_SELF_DELAY,
+			// Unless told not to, use the channel before the funding transaction confirms if the
+			// peer accepts it as zero-conf.
+			trust_own_funding_0conf: options.zero_conf,
 			..Default::default()
 		},
 		channel_handshake_config: ChannelHandshakeConfig {
-			announced_channel,
-			negotiate_anchors_zero_fee_htlc_tx: with_anchors,
+			announced_channel: options.announced_channel,
+			negotiate_anchors_zero_fee_htlc_tx: options.with_anchors,
+			// Committing to a shutdown script would stop `closechannel --address` from working.
+			commit_upfront_shutdown_pubkey: false,
 			..Default::default()
 		},
 		..Default::default()
 	};
+	let handshake_config = &mut config.channel_handshake_config;
+	if let Some(to_self_delay) = options.to_self_delay {
+		handshake_config.our_to_self_delay = to_self_delay;
+	}
+	if let Some(percent) = options.max_htlc_in_flight_percent {
+		handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = percent;
+	}
+	if let Some(htlc_minimum_msat) = options.htlc_minimum_msat {
+		handshake_config.our_htlc_minimum_msat = htlc_minimum_msat;
+	}
+	if let Some(channel_reserve_sats) = options.channel_reserve_sats {
+		// LDK takes the reserve as a share of the channel, so round up to at least the amount asked
+		// for. Reserves of large channels overflow a u64 once in millionths.
+		let channel_amt_sat = channel_amt_sat.max(1) as u128;
+		let reserve_millionths =
+			(channel_reserve_sats as u128 * 1_000_000 + channel_amt_sat - 1) / channel_amt_sat;
+		handshake_config.their_channel_reserve_proportional_millionths =
+			reserve_millionths.min(1_000_000) as u32;
+	}
+	if let Some(fee_ppm) = options.fee_ppm {
+		config.channel_config.forwarding_fee_proportional_millionths = fee_ppm;
+	}
+	if let Some(base_fee_msat) = options.base_fee_msat {
+		config.channel_config.forwarding_fee_base_msat = base_fee_msat;
+	}
+	config
+}
 
-	match channel_manager.create_channel(peer_pubkey, channel_amt_sat, 0, 0, None, Some(config)) {
+fn open_channel(
+	peer_pubkey: PublicKey, channel_amt_sat: u64, options: &OpenChannelOptions,
+	channel_manager: Arc<ChannelManager>, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let config = channel_config(options, channel_amt_sat);
+	match channel_manager.create_channel(
+		peer_pubkey,
+		channel_amt_sat,
+		options.push_msat,
+		options.user_channel_id,
+		None,
+		Some(config),
+	) {
 		Ok(_) => {
-			println!("EVENT: initiated channel with peer {}. ", peer_pubkey);
+			writeln!(out, "EVENT: initiated channel with peer {}. ", peer_pubkey).unwrap();
 			return Ok(());
 		}
 		Err(e) => {
-			println!("ERROR: failed to open channel: {:?}", e);
+			writeln!(out, "ERROR: failed to open channel: {:?}", e).unwrap();
 			return Err(());
 		}
 	}
 }
 
+/// Opens channels which will all be funded by the same transaction, once LDK has generated the
+/// funding output of each of them.
+fn open_channels(
+	channels: &[(PublicKey, u64)], announced_channel: bool, with_anchors: bool,
+	channel_manager: &ChannelManager, keys_manager: &KeysManager, funding_batches: &FundingBatches,
+	out: &mut dyn Write,
+) -> Result<(), ()> {
+	let batch = channels
+		.iter()
+		.map(|(peer_pubkey, _)| {
+			let mut random_bytes = [0u8; 16];
+			random_bytes.copy_from_slice(&keys_manager.get_secure_random_bytes()[..16]);
+			(u128::from_be_bytes(random_bytes), *peer_pubkey)
+		})
+		.collect::<Vec<_>>();
+	// The batch has to be known before the first channel's funding output is generated.
+	funding_batches.register(&batch);
+
+	for ((user_channel_id, peer_pubkey), (_, channel_amt_sat)) in batch.iter().zip(channels) {
+		let options = OpenChannelOptions { announced_channel, with_anchors, ..Default::default() };
+		let config = channel_config(&options, *channel_amt_sat);
+		match channel_manager.create_channel(
+			*peer_pubkey,
+			*channel_amt_sat,
+			0,
+			*user_channel_id,
+			None,
+			Some(config),
+		) {
+			Ok(temporary_channel_id) => {
+				if !funding_batches.channel_created(*user_channel_id, temporary_channel_id) {
+					// Another channel of the batch already failed.
+					let _ = channel_manager
+						.force_close_without_broadcasting_txn(&temporary_channel_id, peer_pubkey);
+					writeln!(out, "ERROR: failed to open channels: a peer refused its channel")
+						.unwrap();
+					return Err(());
+				}
+				writeln!(out, "EVENT: initiated channel with peer {}. ", peer_pubkey).unwrap();
+			}
+			Err(e) => {
+				for (temporary_channel_id, counterparty_node_id) in
+					funding_batches.abort(*user_channel_id)
+				{
+					let _ = channel_manager.force_close_without_broadcasting_txn(
+						&temporary_channel_id,
+						&counterparty_node_id,
+					);
+				}
+				writeln!(out, "ERROR: failed to open channel with peer {}: {:?}", peer_pubkey, e)
+					.unwrap();
+				return Err(());
+			}
+		}
+	}
+	writeln!(out, "The {} channels will be funded by a single transaction.", channels.len())
+		.unwrap();
+	Ok(())
+}
+
 fn send_payment(
 	channel_manager: &ChannelManager, invoice: &Bolt11Invoice, required_amount_msat: Option<u64>,
 	outbound_payments: &mut OutboundPaymentInfoStorage, fs_store: Arc<FilesystemStore>,
-) {
+	out: &mut dyn Write,
+) -> Result<(), ()> {
 	let payment_id = PaymentId((*invoice.payment_hash()).to_byte_array());
 	let payment_secret = Some(*invoice.payment_secret());
 	let zero_amt_invoice =
@@ -799,28 +1788,27 @@ fn send_payment(
 		if let Some(amt_msat) = required_amount_msat {
 			payment_parameters_from_zero_amount_invoice(invoice, amt_msat)
 		} else {
-			println!("Need an amount for the given 0-value invoice");
-			print!("> ");
-			return;
+			writeln!(out, "Need an amount for the given 0-value invoice").unwrap();
+			return Err(());
 		}
 	} else {
 		if required_amount_msat.is_some() && invoice.amount_milli_satoshis() != required_amount_msat
 		{
-			println!(
+			writeln!(
+				out,
 				"Amount didn't match invoice value of {}msat",
 				invoice.amount_milli_satoshis().unwrap_or(0)
-			);
-			print!("> ");
-			return;
+			)
+			.unwrap();
+			return Err(());
 		}
 		payment_parameters_from_invoice(invoice)
 	};
 	let (payment_hash, recipient_onion, route_params) = match pay_params_opt {
 		Ok(res) => res,
-		Err(e) => {
-			println!("Failed to parse invoice");
-			print!("> ");
-			return;
+		Err(_) => {
+			writeln!(out, "Failed to parse invoice").unwrap();
+			return Err(());
 		}
 	};
 	outbound_payments.payments.insert(
@@ -844,22 +1832,24 @@ fn send_payment(
 		Ok(_) => {
 			let payee_pubkey = invoice.recover_payee_pub_key();
 			let amt_msat = invoice.amount_milli_satoshis().unwrap();
-			println!("EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
-			print!("> ");
+			writeln!(out, "EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey)
+				.unwrap();
+			Ok(())
 		}
 		Err(e) => {
-			println!("ERROR: failed to send payment: {:?}", e);
-			print!("> ");
+			writeln!(out, "ERROR: failed to send payment: {:?}", e).unwrap();
 			outbound_payments.payments.get_mut(&payment_id).unwrap().status = HTLCStatus::Failed;
 			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+			Err(())
 		}
-	};
+	}
 }
 
 fn keysend<E: EntropySource>(
 	channel_manager: &ChannelManager, payee_pubkey: PublicKey, amt_msat: u64, entropy_source: &E,
 	outbound_payments: &mut OutboundPaymentInfoStorage, fs_store: Arc<FilesystemStore>,
-) {
+	out: &mut dyn Write,
+) -> Result<(), ()> {
 	let payment_preimage = PaymentPreimage(entropy_source.get_secure_random_bytes());
 	let payment_id = PaymentId(Sha256::hash(&payment_preimage.0[..]).to_byte_array());
 
@@ -885,23 +1875,24 @@ fn keysend<E: EntropySource>(
 		Retry::Timeout(Duration::from_secs(10)),
 	) {
 		Ok(_payment_hash) => {
-			println!("EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey);
-			print!("> ");
+			writeln!(out, "EVENT: initiated sending {} msats to {}", amt_msat, payee_pubkey)
+				.unwrap();
+			Ok(())
 		}
 		Err(e) => {
-			println!("ERROR: failed to send payment: {:?}", e);
-			print!("> ");
+			writeln!(out, "ERROR: failed to send payment: {:?}", e).unwrap();
 			outbound_payments.payments.get_mut(&payment_id).unwrap().status = HTLCStatus::Failed;
 			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound_payments.encode()).unwrap();
+			Err(())
 		}
-	};
+	}
 }
 
 fn get_invoice(
 	amt_msat: u64, inbound_payments: &mut InboundPaymentInfoStorage,
 	channel_manager: &ChannelManager, keys_manager: Arc<KeysManager>, network: Network,
-	expiry_secs: u32, logger: Arc<disk::FilesystemLogger>,
-) {
+	expiry_secs: u32, logger: Arc<disk::FilesystemLogger>, out: &mut dyn Write,
+) -> Result<(), ()> {
 	let currency = match network {
 		Network::Bitcoin => Currency::Bitcoin,
 		Network::Regtest => Currency::Regtest,
@@ -919,12 +1910,12 @@ fn get_invoice(
 		None,
 	) {
 		Ok(inv) => {
-			println!("SUCCESS: generated invoice: {}", inv);
+			writeln!(out, "SUCCESS: generated invoice: {}", inv).unwrap();
 			inv
 		}
 		Err(e) => {
-			println!("ERROR: failed to create invoice: {:?}", e);
-			return;
+			writeln!(out, "ERROR: failed to create invoice: {:?}", e).unwrap();
+			return Err(());
 		}
 	};
 
@@ -938,25 +1929,166 @@ fn get_invoice(
 			amt_msat: MillisatAmount(Some(amt_msat)),
 		},
 	);
+	Ok(())
+}
+
+fn parse_option_value<T: FromStr>(flag: &str, value: &str, out: &mut dyn Write) -> Result<T, ()> {
+	value.parse().map_err(|_| {
+		writeln!(out, "ERROR: invalid value `{}` for `{}`", value, flag).unwrap();
+	})
+}
+
+/// Applies `update` to the forwarding policy of the given channel, or of all our channels.
+fn update_channel_policy(
+	channel_id: Option<ChannelId>, update: &ChannelConfigUpdate, channel_manager: &ChannelManager,
+	out: &mut dyn Write,
+) -> Result<(), ()> {
+	let channels = channel_manager
+		.list_channels()
+		.into_iter()
+		.filter(|chan_info| channel_id.is_none() || channel_id == Some(chan_info.channel_id))
+		.collect::<Vec<_>>();
+	if channel_id.is_some() && channels.is_empty() {
+		writeln!(out, "ERROR: unknown channel").unwrap();
+		return Err(());
+	}
+
+	let mut updated = 0;
+	for chan_info in channels {
+		// Only the fields being changed are written, so a concurrent update of other fields isn't
+		// undone.
+		match channel_manager.update_partial_channel_config(
+			&chan_info.counterparty.node_id,
+			&[chan_info.channel_id],
+			update,
+		) {
+			Ok(()) => updated += 1,
+			Err(e) => {
+				writeln!(
+					out,
+					"ERROR: failed to update the policy of channel {}: {:?}",
+					chan_info.channel_id, e
+				)
+				.unwrap();
+			}
+		}
+	}
+	writeln!(out, "Updated the forwarding policy of {} channel(s)", updated).unwrap();
+	if channel_id.is_some() && updated == 0 {
+		return Err(());
+	}
+	Ok(())
+}
+
+fn parse_address(address: &str, network: Network, out: &mut dyn Write) -> Result<Address, ()> {
+	Address::from_str(address)
+		.map_err(|e| e.to_string())
+		.and_then(|address| address.require_network(network).map_err(|e| e.to_string()))
+		.map_err(|e| {
+			writeln!(out, "ERROR: invalid address: {}", e).unwrap();
+		})
+}
+
+// The smallest amount we'll send on-chain, in sats. Outputs below it are dust for some standard
+// output types, so the transaction wouldn't be relayed and would leave its inputs reserved.
+const MIN_SEND_AMOUNT_SATS: u64 = 546;
+
+/// Parses the amount for `sendonchain`, which must be above the dust limit and no more than exist.
+fn parse_send_amount_sats(amount: &str, out: &mut dyn Write) -> Result<u64, ()> {
+	let max_sats = Amount::MAX_MONEY.to_sat();
+	match amount.parse::<u64>() {
+		Ok(amount_sats) if (MIN_SEND_AMOUNT_SATS..=max_sats).contains(&amount_sats) => {
+			Ok(amount_sats)
+		}
+		_ => {
+			writeln!(
+				out,
+				"ERROR: amount_sats must be a number of sats between {} and {}",
+				MIN_SEND_AMOUNT_SATS, max_sats
+			)
+			.unwrap();
+			Err(())
+		}
+	}
+}
+
+// The highest feerate we'll pay when told to, in sat/vB. Anything above it is far more likely to be
+// a typo than a real need, and would burn most of a channel's or a transaction's value in fees.
+const MAX_FEERATE_SAT_PER_VBYTE: f64 = 1000.0;
+
+/// Parses a feerate given in sat/vB, returning it in sat/KW.
+fn parse_feerate_sat_per_vbyte(feerate: &str, out: &mut dyn Write) -> Result<u32, ()> {
+	let min_sat_per_vbyte = MIN_FEERATE as f64 / 250.0;
+	match feerate.parse::<f64>() {
+		Ok(sat_per_vbyte)
+			if (min_sat_per_vbyte..=MAX_FEERATE_SAT_PER_VBYTE).contains(&sat_per_vbyte) =>
+		{
+			Ok((sat_per_vbyte * 250.0).round() as u32)
+		}
+		_ => {
+			writeln!(
+				out,
+				"ERROR: feerate must be a number of sat/vB between {} and {}",
+				min_sat_per_vbyte, MAX_FEERATE_SAT_PER_VBYTE
+			)
+			.unwrap();
+			Err(())
+		}
+	}
 }
 
+/// Starts a cooperative close, paying our balance to `address` rather than to the script LDK would
+/// pick, and targeting `feerate_sat_per_kw` rather than our `NonAnchorChannelFee` estimate.
 fn close_channel(
-	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
-) {
-	match channel_manager.close_channel(&ChannelId(channel_id), &counterparty_node_id) {
-		Ok(()) => println!("EVENT: initiating channel close"),
-		Err(e) => println!("ERROR: failed to close channel: {:?}", e),
+	channel_id: [u8; 32], counterparty_node_id: PublicKey, address: Option<&Address>,
+	feerate_sat_per_kw: Option<u32>, channel_manager: Arc<ChannelManager>, out: &mut dyn Write,
+) -> Result<(), ()> {
+	let shutdown_script = match address
+		.map(|address| ShutdownScript::try_from(address.script_pubkey()))
+	{
+		Some(Ok(shutdown_script)) => Some(shutdown_script),
+		Some(Err(_)) => {
+			writeln!(out, "ERROR: the address's script can't be used to close a channel").unwrap();
+			return Err(());
+		}
+		None => None,
+	};
+	match channel_manager.close_channel_with_feerate_and_script(
+		&ChannelId(channel_id),
+		&counterparty_node_id,
+		feerate_sat_per_kw,
+		shutdown_script,
+	) {
+		Ok(()) => {
+			writeln!(out, "EVENT: initiating channel close").unwrap();
+			Ok(())
+		}
+		Err(APIError::APIMisuseError { .. }) if address.is_some() => {
+			writeln!(out, "ERROR: failed to close channel: it committed to a shutdown script when it was opened, so can only be closed to that").unwrap();
+			Err(())
+		}
+		Err(e) => {
+			writeln!(out, "ERROR: failed to close channel: {:?}", e).unwrap();
+			Err(())
+		}
 	}
 }
 
 fn force_close_channel(
 	channel_id: [u8; 32], counterparty_node_id: PublicKey, channel_manager: Arc<ChannelManager>,
-) {
+	out: &mut dyn Write,
+) -> Result<(), ()> {
 	match channel_manager
 		.force_close_broadcasting_latest_txn(&ChannelId(channel_id), &counterparty_node_id)
 	{
-		Ok(()) => println!("EVENT: initiating channel force-close"),
-		Err(e) => println!("ERROR: failed to force-close channel: {:?}", e),
+		Ok(()) => {
+			writeln!(out, "EVENT: initiating channel force-close").unwrap();
+			Ok(())
+		}
+		Err(e) => {
+			writeln!(out, "ERROR: failed to force-close channel: {:?}", e).unwrap();
+			Err(())
+		}
 	}
 }
 
@@ -991,3 +2123,163 @@ pub(crate) fn parse_peer_info(
 
 	Ok((pubkey.unwrap(), peer_addr.unwrap().unwrap()))
 }
+
+#[cfg(test)]
+mod cli_tests {
+	use super::*;
+	use std::collections::HashMap;
+
+	#[test]
+	fn test_list_payments_json() {
+		let mut inbound_payments = InboundPaymentInfoStorage { payments: HashMap::new() };
+		inbound_payments.payments.insert(
+			PaymentHash([1; 32]),
+			PaymentInfo {
+				preimage: None,
+				secret: None,
+				status: HTLCStatus::Succeeded,
+				amt_msat: MillisatAmount(Some(1000)),
+			},
+		);
+		let mut outbound_payments = OutboundPaymentInfoStorage { payments: HashMap::new() };
+		outbound_payments.payments.insert(
+			PaymentId([2; 32]),
+			PaymentInfo {
+				preimage: None,
+				secret: None,
+				status: HTLCStatus::Pending,
+				amt_msat: MillisatAmount(None),
+			},
+		);
+
+		let mut out = Vec::new();
+		list_payments(&inbound_payments, &outbound_payments, OutputFormat::Json, &mut out);
+		let payments: Value = serde_json::from_slice(&out).unwrap();
+		assert_eq!(
+			payments,
+			json!([
+				{
+					"amount_millisatoshis": 1000,
+					"payment_hash": "01".repeat(32),
+					"htlc_direction": "inbound",
+					"htlc_status": "succeeded",
+				},
+				{
+					"amount_millisatoshis": null,
+					"payment_hash": "02".repeat(32),
+					"htlc_direction": "outbound",
+					"htlc_status": "pending",
+				},
+			])
+		);
+
+		let mut out = Vec::new();
+		list_payments(&inbound_payments, &outbound_payments, OutputFormat::Text, &mut out);
+		let text = String::from_utf8(out).unwrap();
+		assert!(text.contains("htlc_direction: inbound,"));
+		assert!(text.contains("amount_millisatoshis: unknown,"));
+	}
+
+	#[test]
+	fn test_open_channel_options() {
+		let parse = |flags: &str, channel_amt_sat| {
+			parse_open_channel_options(flags.split_whitespace(), channel_amt_sat, &mut Vec::new())
+		};
+		assert_eq!(parse("", 100_000), Ok(OpenChannelOptions::default()));
+
+		let options = parse(
+			"--public --zeroconf=false --push-msat=5000000 \
+			--to-self-delay=1008 --max-htlc-in-flight=50 --htlc-minimum=1000 --fee-ppm=100 \
+			--base-fee=500 --user-channel-id=42 --channel-reserve=2500",
+			100_000,
+		)
+		.unwrap();
+		assert_eq!(
+			options,
+			OpenChannelOptions {
+				announced_channel: true,
+				with_anchors: false,
+				zero_conf: false,
+				push_msat: 5_000_000,
+				to_self_delay: Some(1008),
+				max_htlc_in_flight_percent: Some(50),
+				htlc_minimum_msat: Some(1000),
+				fee_ppm: Some(100),
+				base_fee_msat: Some(500),
+				user_channel_id: 42,
+				channel_reserve_sats: Some(2500),
+			}
+		);
+		let config = channel_config(&options, 100_000);
+		assert_eq!(config.channel_handshake_config.our_to_self_delay, 1008);
+		assert_eq!(
+			config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel,
+			50
+		);
+		assert_eq!(config.channel_handshake_config.our_htlc_minimum_msat, 1000);
+		assert_eq!(
+			config.channel_handshake_config.their_channel_reserve_proportional_millionths,
+			25_000
+		);
+		assert_eq!(config.channel_config.forwarding_fee_proportional_millionths, 100);
+		assert_eq!(config.channel_config.forwarding_fee_base_msat, 500);
+		assert!(!config.channel_handshake_limits.trust_own_funding_0conf);
+		let default_config = channel_config(&OpenChannelOptions::default(), 100_000);
+		assert!(default_config.channel_handshake_limits.trust_own_funding_0conf);
+		assert!(!config.channel_handshake_config.commit_upfront_shutdown_pubkey);
+
+		for invalid in [
+			"--private",
+			"--push-msat",
+			"--push-msat=100000001",
+			"--to-self-delay=100",
+			"--to-self-delay=2017",
+			"--max-htlc-in-flight=0",
+			"--max-htlc-in-flight=101",
+			"--htlc-minimum=100000000",
+			"--fee-ppm=-1",
+			"--channel-reserve=100001",
+			"--zeroconf",
+			"--zeroconf=true",
+		] {
+			assert!(parse(invalid, 100_000).is_err(), "{} should be rejected", invalid);
+		}
+		assert!(parse("", 2_100_000_000_000_001).is_err());
+
+		// Reserves of large channels don't overflow.
+		let options = parse("--channel-reserve=2000000000000000", 2_100_000_000_000_000).unwrap();
+		let config = channel_config(&options, 2_100_000_000_000_000);
+		assert_eq!(
+			config.channel_handshake_config.their_channel_reserve_proportional_millionths,
+			952_381
+		);
+	}
+
+	#[test]
+	fn test_close_channel_arguments() {
+		let regtest_address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+		assert!(parse_address(regtest_address, Network::Regtest, &mut Vec::new()).is_ok());
+		// Closing to an address of another network would burn the funds.
+		assert!(parse_address(regtest_address, Network::Bitcoin, &mut Vec::new()).is_err());
+		assert!(parse_address("notanaddress", Network::Regtest, &mut Vec::new()).is_err());
+
+		assert_eq!(parse_send_amount_sats("546", &mut Vec::new()), Ok(546));
+		assert_eq!(
+			parse_send_amount_sats("2100000000000000", &mut Vec::new()),
+			Ok(2_100_000_000_000_000)
+		);
+		for invalid in ["0", "545", "2100000000000001", "18446744073709551615", "-1", "1.5"] {
+			assert!(parse_send_amount_sats(invalid, &mut Vec::new()).is_err());
+		}
+
+		assert_eq!(parse_feerate_sat_per_vbyte("10", &mut Vec::new()), Ok(2500));
+		assert_eq!(parse_feerate_sat_per_vbyte("1.5", &mut Vec::new()), Ok(375));
+		assert!(parse_feerate_sat_per_vbyte("0.5", &mut Vec::new()).is_err());
+		assert!(parse_feerate_sat_per_vbyte("fast", &mut Vec::new()).is_err());
+		assert_eq!(parse_feerate_sat_per_vbyte("1000", &mut Vec::new()), Ok(250_000));
+		assert!(parse_feerate_sat_per_vbyte("1000.5", &mut Vec::new()).is_err());
+		assert!(parse_feerate_sat_per_vbyte("1e12", &mut Vec::new()).is_err());
+		assert!(parse_feerate_sat_per_vbyte("inf", &mut Vec::new()).is_err());
+		assert!(parse_feerate_sat_per_vbyte("NaN", &mut Vec::new()).is_err());
+	}
+}
@@ -1,8 +1,64 @@
 use bitcoin::{Address, BlockHash, Txid};
 use lightning_block_sync::http::JsonResponse;
-use std::convert::TryInto;
+use serde_json::Value;
+use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
 
+fn invalid_data(msg: String) -> std::io::Error {
+	std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn get_field<'a>(value: &'a Value, field: &str) -> std::io::Result<&'a Value> {
+	match value.get(field) {
+		Some(field_value) if !field_value.is_null() => Ok(field_value),
+		_ => Err(invalid_data(format!("missing field `{}` in bitcoind response", field))),
+	}
+}
+
+fn get_str<'a>(value: &'a Value, field: &str) -> std::io::Result<&'a str> {
+	get_field(value, field)?
+		.as_str()
+		.ok_or_else(|| invalid_data(format!("field `{}` is not a string", field)))
+}
+
+fn get_u64(value: &Value, field: &str) -> std::io::Result<u64> {
+	get_field(value, field)?
+		.as_u64()
+		.ok_or_else(|| invalid_data(format!("field `{}` is not an unsigned integer", field)))
+}
+
+fn get_i64(value: &Value, field: &str) -> std::io::Result<i64> {
+	get_field(value, field)?
+		.as_i64()
+		.ok_or_else(|| invalid_data(format!("field `{}` is not an integer", field)))
+}
+
+fn get_bool(value: &Value, field: &str) -> std::io::Result<bool> {
+	get_field(value, field)?
+		.as_bool()
+		.ok_or_else(|| invalid_data(format!("field `{}` is not a boolean", field)))
+}
+
+/// Returns the feerate in `field` converted to sat/KW, or `None` if bitcoind didn't give us one.
+fn get_feerate_sat_per_kw(value: &Value, field: &str) -> std::io::Result<Option<u32>> {
+	match value.get(field) {
+		None | Some(Value::Null) => Ok(None),
+		Some(feerate) => match feerate.as_f64() {
+			// Bitcoin Core gives us a feerate in BTC/KvB, which we need to convert to
+			// satoshis/KW. Thus, we first multiply by 10^8 to get satoshis, then divide by 4
+			// to convert virtual-bytes into weight units.
+			Some(feerate_btc_per_kvbyte) => {
+				Ok(Some((feerate_btc_per_kvbyte * 100_000_000.0 / 4.0).round() as u32))
+			}
+			None => Err(invalid_data(format!("field `{}` is not a number", field))),
+		},
+	}
+}
+
+fn as_str(value: &Value) -> std::io::Result<&str> {
+	value.as_str().ok_or_else(|| invalid_data("expected a string from bitcoind".to_string()))
+}
+
 pub struct FundedTx {
 	pub changepos: i64,
 	pub hex: String,
@@ -12,8 +68,8 @@ impl TryInto<FundedTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<FundedTx> {
 		Ok(FundedTx {
-			changepos: self.0["changepos"].as_i64().unwrap(),
-			hex: self.0["hex"].as_str().unwrap().to_string(),
+			changepos: get_i64(&self.0, "changepos")?,
+			hex: get_str(&self.0, "hex")?.to_string(),
 		})
 	}
 }
@@ -23,7 +79,7 @@ pub struct RawTx(pub String);
 impl TryInto<RawTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<RawTx> {
-		Ok(RawTx(self.0.as_str().unwrap().to_string()))
+		Ok(RawTx(as_str(&self.0)?.to_string()))
 	}
 }
 
@@ -36,8 +92,8 @@ impl TryInto<SignedTx> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<SignedTx> {
 		Ok(SignedTx {
-			hex: self.0["hex"].as_str().unwrap().to_string(),
-			complete: self.0["complete"].as_bool().unwrap(),
+			hex: get_str(&self.0, "hex")?.to_string(),
+			complete: get_bool(&self.0, "complete")?,
 		})
 	}
 }
@@ -46,7 +102,34 @@ pub struct NewAddress(pub String);
 impl TryInto<NewAddress> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<NewAddress> {
-		Ok(NewAddress(self.0.as_str().unwrap().to_string()))
+		Ok(NewAddress(as_str(&self.0)?.to_string()))
+	}
+}
+
+/// The result of RPCs which return nothing on success, like `abandontransaction`.
+pub struct NullResponse;
+
+impl TryInto<NullResponse> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<NullResponse> {
+		if !self.0.is_null() {
+			return Err(invalid_data(format!("expected null, got {}", self.0)));
+		}
+		Ok(NullResponse)
+	}
+}
+
+/// Whether `lockunspent` succeeded.
+pub struct LockUnspentResponse(pub bool);
+
+impl TryInto<LockUnspentResponse> for JsonResponse {
+	type Error = std::io::Error;
+	fn try_into(self) -> std::io::Result<LockUnspentResponse> {
+		let locked = self
+			.0
+			.as_bool()
+			.ok_or_else(|| invalid_data("expected a boolean from lockunspent".to_string()))?;
+		Ok(LockUnspentResponse(locked))
 	}
 }
 
@@ -58,19 +141,11 @@ pub struct FeeResponse {
 impl TryInto<FeeResponse> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<FeeResponse> {
+		if !self.0.is_object() {
+			return Err(invalid_data("expected a JSON object from estimatesmartfee".to_string()));
+		}
 		let errored = !self.0["errors"].is_null();
-		Ok(FeeResponse {
-			errored,
-			feerate_sat_per_kw: match self.0["feerate"].as_f64() {
-				// Bitcoin Core gives us a feerate in BTC/KvB, which we need to convert to
-				// satoshis/KW. Thus, we first multiply by 10^8 to get satoshis, then divide by 4
-				// to convert virtual-bytes into weight units.
-				Some(feerate_btc_per_kvbyte) => {
-					Some((feerate_btc_per_kvbyte * 100_000_000.0 / 4.0).round() as u32)
-				}
-				None => None,
-			},
-		})
+		Ok(FeeResponse { errored, feerate_sat_per_kw: get_feerate_sat_per_kw(&self.0, "feerate")? })
 	}
 }
 
@@ -82,19 +157,13 @@ pub struct MempoolMinFeeResponse {
 impl TryInto<MempoolMinFeeResponse> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<MempoolMinFeeResponse> {
+		if !self.0.is_object() {
+			return Err(invalid_data("expected a JSON object from getmempoolinfo".to_string()));
+		}
 		let errored = !self.0["errors"].is_null();
-		assert_eq!(self.0["maxmempool"].as_u64(), Some(300000000));
 		Ok(MempoolMinFeeResponse {
 			errored,
-			feerate_sat_per_kw: match self.0["mempoolminfee"].as_f64() {
-				// Bitcoin Core gives us a feerate in BTC/KvB, which we need to convert to
-				// satoshis/KW. Thus, we first multiply by 10^8 to get satoshis, then divide by 4
-				// to convert virtual-bytes into weight units.
-				Some(feerate_btc_per_kvbyte) => {
-					Some((feerate_btc_per_kvbyte * 100_000_000.0 / 4.0).round() as u32)
-				}
-				None => None,
-			},
+			feerate_sat_per_kw: get_feerate_sat_per_kw(&self.0, "mempoolminfee")?,
 		})
 	}
 }
@@ -103,16 +172,21 @@ pub struct BlockchainInfo {
 	pub latest_height: usize,
 	pub latest_blockhash: BlockHash,
 	pub chain: String,
+	pub headers: usize,
+	pub initial_block_download: bool,
 }
 
 impl TryInto<BlockchainInfo> for JsonResponse {
 	type Error = std::io::Error;
 	fn try_into(self) -> std::io::Result<BlockchainInfo> {
+		let bestblockhash = get_str(&self.0, "bestblockhash")?;
 		Ok(BlockchainInfo {
-			latest_height: self.0["blocks"].as_u64().unwrap() as usize,
-			latest_blockhash: BlockHash::from_str(self.0["bestblockhash"].as_str().unwrap())
-				.unwrap(),
-			chain: self.0["chain"].as_str().unwrap().to_string(),
+			latest_height: get_u64(&self.0, "blocks")? as usize,
+			latest_blockhash: BlockHash::from_str(bestblockhash)
+				.map_err(|_| invalid_data(format!("invalid block hash `{}`", bestblockhash)))?,
+			chain: get_str(&self.0, "chain")?.to_string(),
+			headers: get_u64(&self.0, "headers")? as usize,
+			initial_block_download: get_bool(&self.0, "initialblockdownload")?,
 		})
 	}
 }
@@ -122,6 +196,7 @@ pub struct ListUnspentUtxo {
 	pub vout: u32,
 	pub amount: u64,
 	pub address: Address,
+	pub confirmations: u32,
 }
 
 pub struct ListUnspentResponse(pub Vec<ListUnspentUtxo>);
@@ -132,19 +207,160 @@ impl TryInto<ListUnspentResponse> for JsonResponse {
 		let utxos = self
 			.0
 			.as_array()
-			.unwrap()
-			.iter()
-			.map(|utxo| ListUnspentUtxo {
-				txid: Txid::from_str(&utxo["txid"].as_str().unwrap().to_string()).unwrap(),
-				vout: utxo["vout"].as_u64().unwrap() as u32,
-				amount: bitcoin::Amount::from_btc(utxo["amount"].as_f64().unwrap())
-					.unwrap()
-					.to_sat(),
-				address: Address::from_str(&utxo["address"].as_str().unwrap().to_string())
-					.unwrap()
+			.ok_or_else(|| invalid_data("expected a JSON array from listunspent".to_string()))?;
+		let mut res = Vec::with_capacity(utxos.len());
+		for utxo in utxos {
+			// Outputs without an address (e.g. bare multisig) can't be spent by us anyway.
+			if utxo.get("address").is_none() {
+				continue;
+			}
+			let txid = get_str(utxo, "txid")?;
+			let amount = get_field(utxo, "amount")?
+				.as_f64()
+				.and_then(|amount| bitcoin::Amount::from_btc(amount).ok())
+				.ok_or_else(|| invalid_data("field `amount` is not a valid amount".to_string()))?;
+			let address = get_str(utxo, "address")?;
+			res.push(ListUnspentUtxo {
+				txid: Txid::from_str(txid)
+					.map_err(|_| invalid_data(format!("invalid txid `{}`", txid)))?,
+				vout: u32::try_from(get_u64(utxo, "vout")?)
+					.map_err(|_| invalid_data("field `vout` is out of range".to_string()))?,
+				amount: amount.to_sat(),
+				address: Address::from_str(address)
+					.map_err(|_| invalid_data(format!("invalid address `{}`", address)))?
 					.assume_checked(), // the expected network is not known at this point
-			})
-			.collect();
-		Ok(ListUnspentResponse(utxos))
+				confirmations: u32::try_from(get_u64(utxo, "confirmations")?).map_err(|_| {
+					invalid_data("field `confirmations` is out of range".to_string())
+				})?,
+			});
+		}
+		Ok(ListUnspentResponse(res))
+	}
+}
+
+#[cfg(test)]
+mod convert_tests {
+	use super::*;
+	use std::fs;
+
+	/// Reads a response captured from bitcoind's RPC interface (the `result` field).
+	fn captured_response(name: &str) -> JsonResponse {
+		let path = format!("test_data/rpc_responses/{}.json", name);
+		JsonResponse(serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap())
+	}
+
+	fn response(json: &str) -> JsonResponse {
+		JsonResponse(serde_json::from_str(json).unwrap())
+	}
+
+	#[test]
+	fn test_funded_tx() {
+		let funded_tx: FundedTx = captured_response("fundrawtransaction").try_into().unwrap();
+		assert_eq!(funded_tx.changepos, 1);
+		assert!(funded_tx.hex.starts_with("02000000"));
+
+		let res: std::io::Result<FundedTx> = response(r#"{"hex": "00", "fee": 0.1}"#).try_into();
+		assert_eq!(res.err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn test_raw_tx_and_new_address() {
+		let raw_tx: RawTx = captured_response("createrawtransaction").try_into().unwrap();
+		assert!(raw_tx.0.starts_with("02000000"));
+		let address: NewAddress = captured_response("getnewaddress").try_into().unwrap();
+		assert_eq!(address.0, "bcrt1qe2tczyk2rw7u47kzxxee5g7ufkncdmlcf5v0dv");
+
+		let res: std::io::Result<RawTx> = response("{}").try_into();
+		assert!(res.is_err());
+		let res: std::io::Result<NewAddress> = response("null").try_into();
+		assert!(res.is_err());
+	}
+
+	#[test]
+	fn test_signed_tx() {
+		let signed_tx: SignedTx =
+			captured_response("signrawtransactionwithwallet").try_into().unwrap();
+		assert!(signed_tx.complete);
+		assert!(signed_tx.hex.starts_with("02000000"));
+
+		let res: std::io::Result<SignedTx> =
+			response(r#"{"hex": "00", "complete": "yes"}"#).try_into();
+		assert!(res.is_err());
+	}
+
+	#[test]
+	fn test_fee_response() {
+		let fees: FeeResponse = captured_response("estimatesmartfee").try_into().unwrap();
+		assert!(!fees.errored);
+		assert_eq!(fees.feerate_sat_per_kw, Some(2500));
+
+		// On regtest there's usually not enough data for an estimate.
+		let fees: FeeResponse = captured_response("estimatesmartfee_regtest").try_into().unwrap();
+		assert!(fees.errored);
+		assert_eq!(fees.feerate_sat_per_kw, None);
+
+		let res: std::io::Result<FeeResponse> = response(r#"{"feerate": "high"}"#).try_into();
+		assert!(res.is_err());
+		let res: std::io::Result<FeeResponse> = response("[]").try_into();
+		assert!(res.is_err());
+	}
+
+	#[test]
+	fn test_mempool_min_fee_response() {
+		let fees: MempoolMinFeeResponse = captured_response("getmempoolinfo").try_into().unwrap();
+		assert!(!fees.errored);
+		assert_eq!(fees.feerate_sat_per_kw, Some(250));
+
+		// A non-default `-maxmempool` is fine.
+		let fees: MempoolMinFeeResponse =
+			response(r#"{"maxmempool": 50000000, "mempoolminfee": 0.00002}"#).try_into().unwrap();
+		assert_eq!(fees.feerate_sat_per_kw, Some(500));
+	}
+
+	#[test]
+	fn test_blockchain_info() {
+		let info: BlockchainInfo = captured_response("getblockchaininfo").try_into().unwrap();
+		assert_eq!(info.latest_height, 101);
+		assert_eq!(info.headers, 101);
+		assert_eq!(info.chain, "regtest");
+		assert!(!info.initial_block_download);
+		assert_eq!(
+			info.latest_blockhash.to_string(),
+			"5af2392a948f950e3f93e9f171da623fe6629440bc8d7e27fa7b39573e40ee73"
+		);
+
+		let res: std::io::Result<BlockchainInfo> =
+			response(r#"{"chain": "regtest", "blocks": 101}"#).try_into();
+		assert_eq!(res.err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+	}
+
+	#[test]
+	fn test_lock_unspent() {
+		let res: LockUnspentResponse = response("true").try_into().unwrap();
+		assert!(res.0);
+		let res: std::io::Result<LockUnspentResponse> = response(r#""true""#).try_into();
+		assert!(res.is_err());
+
+		assert!(TryInto::<NullResponse>::try_into(response("null")).is_ok());
+		assert!(TryInto::<NullResponse>::try_into(response("true")).is_err());
+	}
+
+	#[test]
+	fn test_list_unspent() {
+		let utxos: ListUnspentResponse = captured_response("listunspent").try_into().unwrap();
+		// The bare multisig output without an address is skipped.
+		assert_eq!(utxos.0.len(), 2);
+		assert_eq!(utxos.0[0].vout, 0);
+		assert_eq!(utxos.0[0].amount, 5_000_000_000);
+		assert_eq!(utxos.0[0].confirmations, 101);
+		assert_eq!(utxos.0[1].vout, 1);
+		assert_eq!(utxos.0[1].amount, 12_345);
+
+		let res: std::io::Result<ListUnspentResponse> = response("{}").try_into();
+		assert!(res.is_err());
+		let res: std::io::Result<ListUnspentResponse> =
+			response(r#"[{"txid": "nottxid", "vout": 0, "amount": 1.0, "address": "x"}]"#)
+				.try_into();
+		assert!(res.is_err());
 	}
 }
@@ -0,0 +1,333 @@
+//! An Electrum server (e.g. electrs) as the chain backend. Like the Esplora backend, LDK's
+//! `Confirm` interface is driven by [`ElectrumSyncClient`], which only queries the transactions
+//! and outputs registered via `Filter`.
+//!
+//! The Electrum client is blocking, so all calls to it are made from `spawn_blocking`.
+
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::FeePolicy;
+use crate::health::ChainSourceHealth;
+use crate::wallet::OnchainWallet;
+use crate::{ChainMonitor, ChannelManager};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::Txid;
+use bitcoin::network::constants::Network;
+use electrum_client::ElectrumApi;
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{BestBlock, Confirm};
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) type ElectrumSyncClient =
+	lightning_transaction_sync::ElectrumSyncClient<Arc<FilesystemLogger>>;
+
+/// How often we sync LDK with the chain and refresh fee estimates.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+const FEE_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often we sync the internal on-chain wallet, which queries the history of all its scripts.
+const WALLET_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Provides fee estimates and transaction broadcasting via an Electrum server.
+pub struct ElectrumClient {
+	tx_sync: Arc<ElectrumSyncClient>,
+	fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>,
+	fee_policy: Arc<FeePolicy>,
+	health: Arc<ChainSourceHealth>,
+	handle: tokio::runtime::Handle,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl ElectrumClient {
+	/// Checks that the server follows `network`, fetches initial fee estimates, failing if the
+	/// server isn't usable, and starts refreshing them in the background.
+	pub(crate) async fn new(
+		tx_sync: Arc<ElectrumSyncClient>, network: Network, fee_policy: Arc<FeePolicy>,
+		handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+	) -> std::io::Result<Self> {
+		let genesis_sync = Arc::clone(&tx_sync);
+		let genesis =
+			tokio::task::spawn_blocking(move || genesis_sync.client().block_header(0)).await;
+		let genesis_hash = match genesis {
+			Ok(Ok(header)) => header.block_hash(),
+			Ok(Err(e)) => {
+				return Err(std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Failed to make initial call to the Electrum server: {}", e),
+				))
+			}
+			Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+		};
+		if genesis_hash != genesis_block(network).block_hash() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!(
+					"the Electrum server isn't serving {} (its genesis block is {})",
+					network, genesis_hash
+				),
+			));
+		}
+		let estimates =
+			fetch_fee_estimates(Arc::clone(&tx_sync), &fee_policy).await.map_err(|e| {
+				std::io::Error::new(
+					std::io::ErrorKind::Other,
+					format!("Failed to make initial call to the Electrum server: {}", e),
+				)
+			})?;
+		let fees: HashMap<ConfirmationTarget, AtomicU32> = fee_rates(&estimates, &fee_policy)
+			.into_iter()
+			.map(|(target, rate)| (target, AtomicU32::new(rate)))
+			.collect();
+		let health = Arc::new(ChainSourceHealth::new());
+		let client = Self { tx_sync, fees: Arc::new(fees), fee_policy, health, handle, logger };
+		client.poll_for_fee_estimates();
+		Ok(client)
+	}
+
+	fn poll_for_fee_estimates(&self) {
+		let tx_sync = Arc::clone(&self.tx_sync);
+		let fees = Arc::clone(&self.fees);
+		let fee_policy = Arc::clone(&self.fee_policy);
+		let health = Arc::clone(&self.health);
+		let logger = Arc::clone(&self.logger);
+		self.handle.spawn(async move {
+			loop {
+				tokio::time::sleep(FEE_UPDATE_INTERVAL).await;
+				match fetch_fee_estimates(Arc::clone(&tx_sync), &fee_policy).await {
+					Ok(estimates) => {
+						health.record_success();
+						for (target, rate) in fee_rates(&estimates, &fee_policy) {
+							fees.get(&target).unwrap().store(rate, Ordering::Release);
+						}
+					}
+					// Keep using the previous estimates until the server is back.
+					Err(e) => {
+						log_error!(logger, "Failed to update fee estimates: {}", e);
+						health.record_failure(e);
+					}
+				}
+			}
+		});
+	}
+
+	/// The reachability of the Electrum server, as seen by our syncs and fee estimate updates.
+	pub(crate) fn health(&self) -> Arc<ChainSourceHealth> {
+		Arc::clone(&self.health)
+	}
+
+	/// Returns the current chain tip, to initialize a fresh `ChannelManager` with.
+	pub(crate) async fn get_best_block(&self) -> std::io::Result<BestBlock> {
+		let tx_sync = Arc::clone(&self.tx_sync);
+		let res =
+			tokio::task::spawn_blocking(move || tx_sync.client().block_headers_subscribe()).await;
+		match res {
+			Ok(Ok(notification)) => {
+				Ok(BestBlock::new(notification.header.block_hash(), notification.height as u32))
+			}
+			Ok(Err(e)) => Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+			Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e))),
+		}
+	}
+}
+
+/// Fetches the estimates for the conf targets in our fee policy, in BTC/kvB as returned by the
+/// server and keyed by conf target.
+async fn fetch_fee_estimates(
+	tx_sync: Arc<ElectrumSyncClient>, fee_policy: &FeePolicy,
+) -> Result<HashMap<u16, f64>, String> {
+	let mut conf_targets: Vec<u16> =
+		fee_policy.required_estimates().into_iter().map(|(conf_target, _)| conf_target).collect();
+	conf_targets.sort_unstable();
+	conf_targets.dedup();
+	let targets = conf_targets.clone();
+	match tokio::task::spawn_blocking(move || {
+		tx_sync.client().batch_estimate_fee(targets.iter().map(|target| *target as usize))
+	})
+	.await
+	{
+		Ok(Ok(estimates)) if estimates.len() == conf_targets.len() => {
+			Ok(conf_targets.into_iter().zip(estimates).collect())
+		}
+		Ok(Ok(_)) => Err("unexpected number of fee estimates".to_string()),
+		Ok(Err(e)) => Err(e.to_string()),
+		Err(e) => Err(e.to_string()),
+	}
+}
+
+/// Maps the server's estimates (in BTC/kvB, or -1 if the server has no estimate, e.g. on regtest)
+/// to LDK's confirmation targets (in sat/KW) according to our fee policy. Electrum has no estimate
+/// modes, so only the policy's conf targets are used.
+fn fee_rates(
+	estimates: &HashMap<u16, f64>, fee_policy: &FeePolicy,
+) -> Vec<(ConfirmationTarget, u32)> {
+	fee_policy.feerates(|target_policy| {
+		let btc_per_kvb = *estimates.get(&target_policy.conf_target)?;
+		if btc_per_kvb <= 0.0 {
+			return None;
+		}
+		// 1 BTC/kvB is 100_000_000 sat per 4_000 weight units, i.e. 25_000_000 sat/KW.
+		Some((btc_per_kvb * 25_000_000.0).round() as u32)
+	})
+}
+
+impl FeeEstimator for ElectrumClient {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		self.fees.get(&confirmation_target).unwrap().load(Ordering::Acquire)
+	}
+}
+
+impl BroadcasterInterface for ElectrumClient {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		for tx in txs {
+			let tx = (*tx).clone();
+			let tx_sync = Arc::clone(&self.tx_sync);
+			let logger = Arc::clone(&self.logger);
+			self.handle.spawn_blocking(move || {
+				// As with bitcoind, this may fail for transactions we broadcast before, which is
+				// safe to ignore.
+				if let Err(e) = tx_sync.client().transaction_broadcast(&tx) {
+					log_error!(logger,
+						"Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransaction: {}",
+						e,
+						encode::serialize_hex(&tx));
+					print!("Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\n> ", e);
+				}
+			});
+		}
+	}
+}
+
+/// Regularly syncs the `ChannelManager` and `ChainMonitor` with the chain via the Electrum server.
+pub(crate) async fn periodic_sync(
+	tx_sync: Arc<ElectrumSyncClient>, channel_manager: Arc<ChannelManager>,
+	chain_monitor: Arc<ChainMonitor>, health: Arc<ChainSourceHealth>,
+	logger: Arc<FilesystemLogger>,
+) {
+	let mut interval = tokio::time::interval(SYNC_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	loop {
+		interval.tick().await;
+		let (tx_sync, channel_manager, chain_monitor) =
+			(Arc::clone(&tx_sync), Arc::clone(&channel_manager), Arc::clone(&chain_monitor));
+		let res = tokio::task::spawn_blocking(move || {
+			let confirmables = vec![
+				&*channel_manager as &(dyn Confirm + Sync + Send),
+				&*chain_monitor as &(dyn Confirm + Sync + Send),
+			];
+			tx_sync.sync(confirmables)
+		})
+		.await;
+		match res {
+			Ok(Ok(())) => health.record_success(),
+			Ok(Err(e)) => {
+				log_error!(logger, "Failed to sync with the Electrum server: {}", e);
+				health.record_failure(e);
+			}
+			Err(e) => {
+				log_error!(logger, "Failed to sync with the Electrum server: {}", e);
+				health.record_failure(e);
+			}
+		}
+	}
+}
+
+/// Regularly syncs the internal on-chain wallet with the history of its scripts via the Electrum
+/// server.
+pub(crate) async fn periodic_wallet_sync(
+	tx_sync: Arc<ElectrumSyncClient>, wallet: Arc<OnchainWallet>, health: Arc<ChainSourceHealth>,
+	logger: Arc<FilesystemLogger>,
+) {
+	let mut interval = tokio::time::interval(WALLET_SYNC_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	loop {
+		interval.tick().await;
+		let (tx_sync, wallet) = (Arc::clone(&tx_sync), Arc::clone(&wallet));
+		let res = tokio::task::spawn_blocking(move || sync_wallet(&tx_sync, &wallet)).await;
+		match res {
+			Ok(Ok(())) => health.record_success(),
+			Ok(Err(e)) => {
+				log_error!(
+					logger,
+					"Failed to sync the on-chain wallet with the Electrum server: {}",
+					e
+				);
+				health.record_failure(e);
+			}
+			Err(e) => {
+				log_error!(
+					logger,
+					"Failed to sync the on-chain wallet with the Electrum server: {}",
+					e
+				);
+				health.record_failure(e);
+			}
+		}
+	}
+}
+
+/// Fetches the history of every script the wallet watches and syncs it, again as long as that
+/// made the wallet watch new scripts.
+fn sync_wallet(
+	tx_sync: &ElectrumSyncClient, wallet: &OnchainWallet,
+) -> Result<(), electrum_client::Error> {
+	let client = tx_sync.client();
+	loop {
+		let scripts = wallet.watched_scripts();
+		let histories = client.batch_script_get_history(scripts.iter().map(|s| s.as_script()))?;
+		// Unconfirmed transactions have a height of 0, or -1 if they spend unconfirmed outputs.
+		let mut heights: HashMap<Txid, Option<u32>> = HashMap::new();
+		for res in histories.into_iter().flatten() {
+			heights.insert(res.tx_hash, u32::try_from(res.height).ok().filter(|h| *h > 0));
+		}
+		let txids: Vec<Txid> = heights.keys().copied().collect();
+		let txs = client.batch_transaction_get(txids.iter())?;
+		let history: Vec<(Transaction, Option<u32>)> = txs
+			.into_iter()
+			.map(|tx| {
+				let height = heights[&tx.txid()];
+				(tx, height)
+			})
+			.collect();
+		// Fetch the tip last, so that it's at least as recent as any confirmation we've seen.
+		let tip = client.block_headers_subscribe()?;
+		if !wallet.sync_history(&history, tip.header.block_hash(), tip.height as u32) {
+			return Ok(());
+		}
+	}
+}
+
+#[cfg(test)]
+mod electrum_tests {
+	use super::*;
+	use crate::bitcoind_client::MIN_FEERATE;
+
+	#[test]
+	fn test_fee_rates() {
+		let fee_policy = FeePolicy::default();
+		// In BTC/kvB.
+		let estimates: HashMap<u16, f64> =
+			vec![(1008, 0.00001), (144, 0.00004), (18, 0.0001), (6, 0.0002)].into_iter().collect();
+		let rates: HashMap<ConfirmationTarget, u32> =
+			fee_rates(&estimates, &fee_policy).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 5000);
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 2500);
+		assert_eq!(rates[&ConfirmationTarget::AnchorChannelFee], 1000);
+		assert_eq!(rates[&ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee], 253);
+		assert_eq!(rates[&ConfirmationTarget::MinAllowedAnchorChannelRemoteFee], 253);
+
+		// Servers return -1 when they don't have an estimate, so we use the policy's fallbacks.
+		let estimates: HashMap<u16, f64> =
+			estimates.keys().map(|conf_target| (*conf_target, -1.0)).collect();
+		let rates: HashMap<ConfirmationTarget, u32> =
+			fee_rates(&estimates, &fee_policy).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 5000);
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 2000);
+		assert_eq!(rates[&ConfirmationTarget::AnchorChannelFee], MIN_FEERATE);
+	}
+}
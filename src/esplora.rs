@@ -0,0 +1,280 @@
+//! An Esplora HTTP API as the chain backend, for running without a local full node. Rather than
+//! connecting whole blocks, LDK's `Confirm` interface is driven by [`EsploraSyncClient`], which
+//! only queries the transactions and outputs registered via `Filter`.
+
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::FeePolicy;
+use crate::health::ChainSourceHealth;
+use crate::wallet::OnchainWallet;
+use crate::{ChainMonitor, ChannelManager};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::Txid;
+use bitcoin::network::constants::Network;
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{BestBlock, Confirm};
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub(crate) type EsploraSyncClient =
+	lightning_transaction_sync::EsploraSyncClient<Arc<FilesystemLogger>>;
+
+/// How often we sync LDK with the chain and refresh fee estimates.
+const SYNC_INTERVAL: Duration = Duration::from_secs(10);
+const FEE_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+/// How often we sync the internal on-chain wallet, which queries the history of all its scripts.
+const WALLET_SYNC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Esplora returns the confirmed transactions of a script this many at a time.
+const SCRIPT_TXS_PAGE_SIZE: usize = 25;
+
+/// Provides fee estimates and transaction broadcasting via Esplora.
+pub struct EsploraClient {
+	tx_sync: Arc<EsploraSyncClient>,
+	fees: Arc<HashMap<ConfirmationTarget, AtomicU32>>,
+	fee_policy: Arc<FeePolicy>,
+	health: Arc<ChainSourceHealth>,
+	handle: tokio::runtime::Handle,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl EsploraClient {
+	/// Checks that the server is reachable and follows `network`, fetches initial fee estimates
+	/// and starts refreshing them in the background.
+	pub(crate) async fn new(
+		tx_sync: Arc<EsploraSyncClient>, network: Network, fee_policy: Arc<FeePolicy>,
+		handle: tokio::runtime::Handle, logger: Arc<FilesystemLogger>,
+	) -> std::io::Result<Self> {
+		let genesis_hash = tx_sync.client().get_block_hash(0).await.map_err(|e| {
+			std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Failed to make initial call to Esplora: {}", e),
+			)
+		})?;
+		if genesis_hash != genesis_block(network).block_hash() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!(
+					"Esplora isn't serving {} (its genesis block is {})",
+					network, genesis_hash
+				),
+			));
+		}
+		let estimates = tx_sync.client().get_fee_estimates().await.map_err(|e| {
+			std::io::Error::new(
+				std::io::ErrorKind::Other,
+				format!("Failed to make initial call to Esplora: {}", e),
+			)
+		})?;
+		let fees: HashMap<ConfirmationTarget, AtomicU32> = fee_rates(&estimates, &fee_policy)
+			.into_iter()
+			.map(|(target, rate)| (target, AtomicU32::new(rate)))
+			.collect();
+		let health = Arc::new(ChainSourceHealth::new());
+		let client = Self { tx_sync, fees: Arc::new(fees), fee_policy, health, handle, logger };
+		client.poll_for_fee_estimates();
+		Ok(client)
+	}
+
+	fn poll_for_fee_estimates(&self) {
+		let tx_sync = Arc::clone(&self.tx_sync);
+		let fees = Arc::clone(&self.fees);
+		let fee_policy = Arc::clone(&self.fee_policy);
+		let health = Arc::clone(&self.health);
+		let logger = Arc::clone(&self.logger);
+		self.handle.spawn(async move {
+			loop {
+				tokio::time::sleep(FEE_UPDATE_INTERVAL).await;
+				match tx_sync.client().get_fee_estimates().await {
+					Ok(estimates) => {
+						health.record_success();
+						for (target, rate) in fee_rates(&estimates, &fee_policy) {
+							fees.get(&target).unwrap().store(rate, Ordering::Release);
+						}
+					}
+					// Keep using the previous estimates until the server is back.
+					Err(e) => {
+						log_error!(logger, "Failed to update fee estimates: {}", e);
+						health.record_failure(e);
+					}
+				}
+			}
+		});
+	}
+
+	/// The reachability of Esplora, as seen by our syncs and fee estimate updates.
+	pub(crate) fn health(&self) -> Arc<ChainSourceHealth> {
+		Arc::clone(&self.health)
+	}
+
+	/// Returns the current chain tip, to initialize a fresh `ChannelManager` with.
+	pub(crate) async fn get_best_block(&self) -> std::io::Result<BestBlock> {
+		let to_io_err = |e| std::io::Error::new(std::io::ErrorKind::Other, format!("{}", e));
+		let client = self.tx_sync.client();
+		let tip_hash = client.get_tip_hash().await.map_err(to_io_err)?;
+		let tip_height = client.get_height().await.map_err(to_io_err)?;
+		Ok(BestBlock::new(tip_hash, tip_height))
+	}
+}
+
+/// Maps Esplora's estimates (in sat/vB, keyed by confirmation target in blocks) to LDK's
+/// confirmation targets (in sat/KW) according to our fee policy. Esplora has no estimate modes, so
+/// only the policy's conf targets are used.
+fn fee_rates(
+	estimates: &HashMap<String, f64>, fee_policy: &FeePolicy,
+) -> Vec<(ConfirmationTarget, u32)> {
+	fee_policy.feerates(|target_policy| {
+		let sat_per_vb = esplora_fee_rate(target_policy.conf_target as usize, estimates)?;
+		Some((sat_per_vb * 250.0).round() as u32)
+	})
+}
+
+/// Picks the estimate for the largest target not above `target`, falling back to the smallest
+/// target Esplora gave us. Returns `None` if there are no estimates at all, e.g. on regtest.
+fn esplora_fee_rate(target: usize, estimates: &HashMap<String, f64>) -> Option<f64> {
+	let mut pairs: Vec<(usize, f64)> =
+		estimates.iter().filter_map(|(k, v)| Some((k.parse().ok()?, *v))).collect();
+	pairs.sort_unstable_by_key(|(k, _)| *k);
+	pairs.iter().rev().find(|(k, _)| *k <= target).or(pairs.first()).map(|(_, v)| *v)
+}
+
+impl FeeEstimator for EsploraClient {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		self.fees.get(&confirmation_target).unwrap().load(Ordering::Acquire)
+	}
+}
+
+impl BroadcasterInterface for EsploraClient {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		for tx in txs {
+			let tx = (*tx).clone();
+			let tx_sync = Arc::clone(&self.tx_sync);
+			let logger = Arc::clone(&self.logger);
+			self.handle.spawn(async move {
+				// As with bitcoind, this may fail for transactions we broadcast before, which is
+				// safe to ignore.
+				if let Err(e) = tx_sync.client().broadcast(&tx).await {
+					log_error!(logger,
+						"Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\nTransaction: {}",
+						e,
+						encode::serialize_hex(&tx));
+					print!("Warning, failed to broadcast a transaction, this is likely okay but may indicate an error: {}\n> ", e);
+				}
+			});
+		}
+	}
+}
+
+/// Regularly syncs the `ChannelManager` and `ChainMonitor` with the chain via Esplora.
+pub(crate) async fn periodic_sync(
+	tx_sync: Arc<EsploraSyncClient>, channel_manager: Arc<ChannelManager>,
+	chain_monitor: Arc<ChainMonitor>, health: Arc<ChainSourceHealth>,
+	logger: Arc<FilesystemLogger>,
+) {
+	let mut interval = tokio::time::interval(SYNC_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	loop {
+		interval.tick().await;
+		let confirmables = vec![
+			&*channel_manager as &(dyn Confirm + Sync + Send),
+			&*chain_monitor as &(dyn Confirm + Sync + Send),
+		];
+		match tx_sync.sync(confirmables).await {
+			Ok(()) => health.record_success(),
+			Err(e) => {
+				log_error!(logger, "Failed to sync with Esplora: {}", e);
+				health.record_failure(e);
+			}
+		}
+	}
+}
+
+/// Regularly syncs the internal on-chain wallet with the history of its scripts via Esplora.
+pub(crate) async fn periodic_wallet_sync(
+	tx_sync: Arc<EsploraSyncClient>, wallet: Arc<OnchainWallet>, health: Arc<ChainSourceHealth>,
+	logger: Arc<FilesystemLogger>,
+) {
+	let mut interval = tokio::time::interval(WALLET_SYNC_INTERVAL);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	loop {
+		interval.tick().await;
+		match sync_wallet(&tx_sync, &wallet).await {
+			Ok(()) => health.record_success(),
+			Err(e) => {
+				log_error!(logger, "Failed to sync the on-chain wallet with Esplora: {}", e);
+				health.record_failure(e);
+			}
+		}
+	}
+}
+
+/// Fetches the history of every script the wallet watches and syncs it, again as long as that
+/// made the wallet watch new scripts.
+async fn sync_wallet(tx_sync: &EsploraSyncClient, wallet: &OnchainWallet) -> Result<(), String> {
+	let client = tx_sync.client();
+	loop {
+		let mut history = HashMap::new();
+		for script in wallet.watched_scripts() {
+			// The first page also has the script's unconfirmed transactions, later ones only
+			// confirmed transactions older than the last one we've seen.
+			let mut last_seen = None;
+			loop {
+				let txs =
+					client.scripthash_txs(&script, last_seen).await.map_err(|e| e.to_string())?;
+				let confirmed: Vec<Txid> =
+					txs.iter().filter(|tx| tx.status.confirmed).map(|tx| tx.txid).collect();
+				for tx in txs {
+					let height = tx.status.block_height.filter(|_| tx.status.confirmed);
+					history.insert(tx.txid, (tx.to_tx(), height));
+				}
+				if confirmed.len() < SCRIPT_TXS_PAGE_SIZE {
+					break;
+				}
+				last_seen = confirmed.last().copied();
+			}
+		}
+		// Fetch the tip last, so that it's at least as recent as any confirmation we've seen.
+		let tip_height = client.get_height().await.map_err(|e| e.to_string())?;
+		let tip_hash = client.get_block_hash(tip_height).await.map_err(|e| e.to_string())?;
+		let history: Vec<(Transaction, Option<u32>)> = history.into_values().collect();
+		if !wallet.sync_history(&history, tip_hash, tip_height) {
+			return Ok(());
+		}
+	}
+}
+
+#[cfg(test)]
+mod esplora_tests {
+	use super::*;
+	use crate::bitcoind_client::MIN_FEERATE;
+
+	#[test]
+	fn test_fee_rates() {
+		let estimates: HashMap<String, f64> =
+			[("1", 50.0), ("6", 20.0), ("144", 4.0), ("1008", 1.5)]
+				.iter()
+				.map(|(k, v)| (k.to_string(), *v))
+				.collect();
+		let fee_policy = FeePolicy::default();
+		let rates: HashMap<ConfirmationTarget, u32> =
+			fee_rates(&estimates, &fee_policy).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 5000);
+		// There's no estimate for 18 blocks, so we use the one for 6.
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 5000);
+		assert_eq!(rates[&ConfirmationTarget::AnchorChannelFee], 1000);
+		assert_eq!(rates[&ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee], 375);
+		assert_eq!(rates[&ConfirmationTarget::MinAllowedAnchorChannelRemoteFee], 375);
+
+		// Without any estimates we use the policy's fallbacks.
+		let rates: HashMap<ConfirmationTarget, u32> =
+			fee_rates(&HashMap::new(), &fee_policy).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 5000);
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 2000);
+		assert_eq!(rates[&ConfirmationTarget::AnchorChannelFee], MIN_FEERATE);
+	}
+}
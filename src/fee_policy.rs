@@ -0,0 +1,331 @@
+//! How we turn our chain source's fee estimates into the feerates LDK asks for. Each of LDK's
+//! `ConfirmationTarget`s can be tuned with `fee.<target>.<setting>` options, e.g.
+//! `fee.on-chain-sweep.multiplier = 1.5` or `fee.channel-close-minimum.static = 1000`.
+
+use crate::bitcoind_client::MIN_FEERATE;
+use lightning::chain::chaininterface::ConfirmationTarget;
+use std::collections::HashMap;
+
+/// Every confirmation target LDK may ask us about, with the name used in config options and in
+/// the `feerates` command.
+pub(crate) const CONFIRMATION_TARGETS: [(ConfirmationTarget, &str); 6] = [
+	(ConfirmationTarget::OnChainSweep, "on-chain-sweep"),
+	(ConfirmationTarget::MinAllowedAnchorChannelRemoteFee, "min-allowed-anchor-channel-remote-fee"),
+	(
+		ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+		"min-allowed-non-anchor-channel-remote-fee",
+	),
+	(ConfirmationTarget::AnchorChannelFee, "anchor-channel-fee"),
+	(ConfirmationTarget::NonAnchorChannelFee, "non-anchor-channel-fee"),
+	(ConfirmationTarget::ChannelCloseMinimum, "channel-close-minimum"),
+];
+
+/// The settings which may be given for each confirmation target.
+pub(crate) const POLICY_SETTINGS: [&str; 7] =
+	["conf-target", "estimate-mode", "fallback", "floor", "ceiling", "multiplier", "static"];
+
+/// Whether `key` is a `fee.<target>.<setting>` option.
+pub(crate) fn is_config_key(key: &str) -> bool {
+	let mut parts = key.split('.');
+	match (parts.next(), parts.next(), parts.next(), parts.next()) {
+		(Some("fee"), Some(name), Some(setting), None) => {
+			CONFIRMATION_TARGETS.iter().any(|(_, n)| *n == name)
+				&& POLICY_SETTINGS.contains(&setting)
+		}
+		_ => false,
+	}
+}
+
+/// Which estimate we ask the chain source for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum EstimateMode {
+	/// bitcoind's `estimatesmartfee` in `ECONOMICAL` mode.
+	Economical,
+	/// bitcoind's `estimatesmartfee` in `CONSERVATIVE` mode.
+	Conservative,
+	/// The minimum feerate for getting into bitcoind's mempool, from `getmempoolinfo`.
+	MempoolMin,
+}
+
+impl EstimateMode {
+	pub(crate) fn parse(mode: &str) -> Result<Self, String> {
+		match mode {
+			"economical" => Ok(EstimateMode::Economical),
+			"conservative" => Ok(EstimateMode::Conservative),
+			"mempool-min" => Ok(EstimateMode::MempoolMin),
+			_ => Err(format!(
+				"invalid estimate mode `{}`, expected `economical`, `conservative` or `mempool-min`",
+				mode
+			)),
+		}
+	}
+
+	/// The mode as passed to `estimatesmartfee`, if it is an `estimatesmartfee` mode.
+	pub(crate) fn estimatesmartfee_mode(&self) -> Option<&'static str> {
+		match self {
+			EstimateMode::Economical => Some("ECONOMICAL"),
+			EstimateMode::Conservative => Some("CONSERVATIVE"),
+			EstimateMode::MempoolMin => None,
+		}
+	}
+}
+
+/// How the feerate for a single confirmation target is derived. All feerates are in sat/KW.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct TargetPolicy {
+	/// The number of blocks we want to confirm within. Esplora and Electrum servers don't expose
+	/// bitcoind's mempool minimum, so with them this is also used for [`EstimateMode::MempoolMin`].
+	pub(crate) conf_target: u16,
+	pub(crate) estimate_mode: EstimateMode,
+	/// Used when the chain source has no estimate, e.g. on regtest.
+	pub(crate) fallback: u32,
+	pub(crate) floor: u32,
+	pub(crate) ceiling: Option<u32>,
+	/// Applied to the chain source's estimate, before clamping it to `floor` and `ceiling`.
+	pub(crate) multiplier: f64,
+	/// If set, used instead of asking the chain source at all.
+	pub(crate) static_feerate: Option<u32>,
+}
+
+impl TargetPolicy {
+	fn new(conf_target: u16, estimate_mode: EstimateMode, fallback: u32) -> Self {
+		Self {
+			conf_target,
+			estimate_mode,
+			fallback,
+			floor: MIN_FEERATE,
+			ceiling: None,
+			multiplier: 1.0,
+			static_feerate: None,
+		}
+	}
+
+	/// Turns the chain source's estimate into the feerate we give LDK.
+	pub(crate) fn feerate(&self, estimate: Option<u32>) -> u32 {
+		if let Some(feerate) = self.static_feerate {
+			return feerate;
+		}
+		let feerate = match estimate {
+			Some(estimate) => (estimate as f64 * self.multiplier).round() as u32,
+			None => self.fallback,
+		};
+		let feerate = std::cmp::max(feerate, self.floor);
+		let feerate = self.ceiling.map_or(feerate, |ceiling| std::cmp::min(feerate, ceiling));
+		std::cmp::max(feerate, MIN_FEERATE)
+	}
+
+	fn set(&mut self, setting: &str, value: &str) -> Result<(), String> {
+		let feerate = |value: &str| match value.parse::<u32>() {
+			Ok(feerate) if feerate >= MIN_FEERATE => Ok(feerate),
+			_ => Err(format!("expected a feerate of at least {} sat/KW", MIN_FEERATE)),
+		};
+		match setting {
+			"conf-target" => match value.parse() {
+				Ok(conf_target) if (1..=1008).contains(&conf_target) => {
+					self.conf_target = conf_target
+				}
+				_ => return Err("expected a number of blocks between 1 and 1008".to_string()),
+			},
+			"estimate-mode" => self.estimate_mode = EstimateMode::parse(value)?,
+			"fallback" => self.fallback = feerate(value)?,
+			"floor" => self.floor = feerate(value)?,
+			"ceiling" => self.ceiling = Some(feerate(value)?),
+			"multiplier" => match value.parse::<f64>() {
+				Ok(multiplier) if multiplier.is_finite() && multiplier > 0.0 => {
+					self.multiplier = multiplier
+				}
+				_ => return Err("expected a positive number".to_string()),
+			},
+			"static" => self.static_feerate = Some(feerate(value)?),
+			_ => return Err(format!("unknown setting `{}`", setting)),
+		}
+		Ok(())
+	}
+}
+
+/// The [`TargetPolicy`] of every confirmation target.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct FeePolicy {
+	targets: HashMap<ConfirmationTarget, TargetPolicy>,
+}
+
+impl Default for FeePolicy {
+	fn default() -> Self {
+		use EstimateMode::*;
+		let targets = vec![
+			(ConfirmationTarget::OnChainSweep, TargetPolicy::new(6, Conservative, 5000)),
+			(
+				ConfirmationTarget::MinAllowedAnchorChannelRemoteFee,
+				TargetPolicy::new(1008, MempoolMin, MIN_FEERATE),
+			),
+			// We'd rather accept a lowish feerate from our counterparty than force-close over a
+			// disagreement on fees, so we allow anything which should confirm within a week.
+			(
+				ConfirmationTarget::MinAllowedNonAnchorChannelRemoteFee,
+				TargetPolicy::new(1008, Economical, MIN_FEERATE),
+			),
+			(ConfirmationTarget::AnchorChannelFee, TargetPolicy::new(144, Economical, MIN_FEERATE)),
+			(ConfirmationTarget::NonAnchorChannelFee, TargetPolicy::new(18, Economical, 2000)),
+			(
+				ConfirmationTarget::ChannelCloseMinimum,
+				TargetPolicy::new(144, Economical, MIN_FEERATE),
+			),
+		];
+		Self { targets: targets.into_iter().collect() }
+	}
+}
+
+impl FeePolicy {
+	/// Builds a policy from `(target name, setting, value)` triples, applied over the defaults.
+	pub(crate) fn from_settings<'a>(
+		settings: impl IntoIterator<Item = (&'a str, &'a str, &'a str)>,
+	) -> Result<Self, String> {
+		let mut policy = Self::default();
+		for (name, setting, value) in settings {
+			let target = match CONFIRMATION_TARGETS.iter().find(|(_, n)| *n == name) {
+				Some((target, _)) => target,
+				None => return Err(format!("unknown confirmation target `{}`", name)),
+			};
+			let target_policy = policy.targets.get_mut(target).unwrap();
+			target_policy.set(setting, value).map_err(|e| {
+				format!("invalid value `{}` for `fee.{}.{}`: {}", value, name, setting, e)
+			})?;
+		}
+		for (target, name) in CONFIRMATION_TARGETS.iter() {
+			let target_policy = policy.get(target);
+			if matches!(target_policy.ceiling, Some(ceiling) if ceiling < target_policy.floor) {
+				return Err(format!("`fee.{}.ceiling` must not be below its floor", name));
+			}
+		}
+		Ok(policy)
+	}
+
+	pub(crate) fn get(&self, target: &ConfirmationTarget) -> &TargetPolicy {
+		self.targets.get(target).unwrap()
+	}
+
+	/// Returns a copy of this policy where every target uses a static feerate, falling back to its
+	/// `fallback`, so that no estimates are ever requested from the chain source.
+	pub(crate) fn with_static_feerates(&self) -> Self {
+		let mut policy = self.clone();
+		for target_policy in policy.targets.values_mut() {
+			target_policy.static_feerate = Some(target_policy.feerate(None));
+		}
+		policy
+	}
+
+	/// Computes the feerate of every confirmation target, given a way to look up the chain source's
+	/// estimate for a target's policy.
+	pub(crate) fn feerates<F: FnMut(&TargetPolicy) -> Option<u32>>(
+		&self, mut estimate: F,
+	) -> Vec<(ConfirmationTarget, u32)> {
+		CONFIRMATION_TARGETS
+			.iter()
+			.map(|(target, _)| {
+				let target_policy = self.get(target);
+				let estimate = match target_policy.static_feerate {
+					Some(_) => None,
+					None => estimate(target_policy),
+				};
+				(*target, target_policy.feerate(estimate))
+			})
+			.collect()
+	}
+
+	/// The `(conf target, estimate mode)` pairs we need estimates for, without duplicates.
+	pub(crate) fn required_estimates(&self) -> Vec<(u16, EstimateMode)> {
+		let mut estimates = Vec::new();
+		for (target, _) in CONFIRMATION_TARGETS.iter() {
+			let target_policy = self.get(target);
+			let estimate = (target_policy.conf_target, target_policy.estimate_mode);
+			if target_policy.static_feerate.is_none() && !estimates.contains(&estimate) {
+				estimates.push(estimate);
+			}
+		}
+		estimates
+	}
+}
+
+#[cfg(test)]
+mod fee_policy_tests {
+	use super::*;
+
+	#[test]
+	fn test_default_policy() {
+		let policy = FeePolicy::default();
+		let rates: HashMap<ConfirmationTarget, u32> =
+			policy.feerates(|_| None).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 5000);
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 2000);
+		assert_eq!(rates[&ConfirmationTarget::AnchorChannelFee], MIN_FEERATE);
+
+		// Estimates below the minimum feerate are raised to it rather than underflowing.
+		let rates: HashMap<ConfirmationTarget, u32> =
+			policy.feerates(|_| Some(100)).into_iter().collect();
+		assert!(rates.values().all(|rate| *rate == MIN_FEERATE));
+
+		assert_eq!(
+			policy.required_estimates(),
+			vec![
+				(6, EstimateMode::Conservative),
+				(1008, EstimateMode::MempoolMin),
+				(1008, EstimateMode::Economical),
+				(144, EstimateMode::Economical),
+				(18, EstimateMode::Economical),
+			]
+		);
+
+		// A fully static policy never needs estimates, and keeps the fallbacks.
+		let static_policy = policy.with_static_feerates();
+		assert!(static_policy.required_estimates().is_empty());
+		assert_eq!(static_policy.feerates(|_| Some(10_000)), policy.feerates(|_| None));
+	}
+
+	#[test]
+	fn test_configured_policy() {
+		let policy = FeePolicy::from_settings(vec![
+			("on-chain-sweep", "multiplier", "1.5"),
+			("on-chain-sweep", "ceiling", "6000"),
+			("non-anchor-channel-fee", "floor", "1000"),
+			("non-anchor-channel-fee", "estimate-mode", "conservative"),
+			("channel-close-minimum", "static", "300"),
+		])
+		.unwrap();
+		let rates: HashMap<ConfirmationTarget, u32> =
+			policy.feerates(|_| Some(3000)).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 4500);
+		assert_eq!(rates[&ConfirmationTarget::ChannelCloseMinimum], 300);
+		let rates: HashMap<ConfirmationTarget, u32> =
+			policy.feerates(|_| Some(5000)).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::OnChainSweep], 6000);
+		let rates: HashMap<ConfirmationTarget, u32> =
+			policy.feerates(|_| Some(500)).into_iter().collect();
+		assert_eq!(rates[&ConfirmationTarget::NonAnchorChannelFee], 1000);
+		assert!(policy.required_estimates().contains(&(18, EstimateMode::Conservative)));
+
+		for invalid in [
+			("on-chain-sweep", "multiplier", "0"),
+			("on-chain-sweep", "conf-target", "0"),
+			("on-chain-sweep", "static", "100"),
+			("on-chain-sweep", "estimate-mode", "fast"),
+			("on-sweep", "static", "1000"),
+		] {
+			assert!(FeePolicy::from_settings(vec![invalid]).is_err(), "{:?}", invalid);
+		}
+		assert!(FeePolicy::from_settings(vec![
+			("anchor-channel-fee", "floor", "2000"),
+			("anchor-channel-fee", "ceiling", "1000"),
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn test_is_config_key() {
+		assert!(is_config_key("fee.on-chain-sweep.multiplier"));
+		assert!(is_config_key("fee.channel-close-minimum.static"));
+		assert!(!is_config_key("fee.on-chain-sweep"));
+		assert!(!is_config_key("fee.on-chain-sweep.speed"));
+		assert!(!is_config_key("fee.on-chain-sweep.static.extra"));
+		assert!(!is_config_key("feerate.on-chain-sweep.static"));
+	}
+}
@@ -0,0 +1,97 @@
+//! Tracks whether our chain source is currently reachable, so `nodeinfo` can tell a node which is
+//! stuck waiting for bitcoind (or Esplora/Electrum) apart from one which simply has nothing to do.
+
+use std::fmt::Display;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Backoff used by retry loops talking to the chain source.
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Returns the delay to wait after `backoff`, doubling it up to [`MAX_BACKOFF`].
+pub(crate) fn next_backoff(backoff: Duration) -> Duration {
+	std::cmp::min(backoff * 2, MAX_BACKOFF)
+}
+
+#[derive(Default)]
+struct HealthState {
+	consecutive_failures: u32,
+	last_error: Option<String>,
+	last_success_time: Option<u64>,
+}
+
+/// The outcome of our most recent calls to the chain source.
+#[derive(Default)]
+pub(crate) struct ChainSourceHealth {
+	state: Mutex<HealthState>,
+}
+
+impl ChainSourceHealth {
+	pub(crate) fn new() -> Self {
+		Self::default()
+	}
+
+	pub(crate) fn record_success(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.consecutive_failures = 0;
+		state.last_success_time =
+			Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs());
+	}
+
+	pub(crate) fn record_failure<E: Display>(&self, error: E) {
+		let mut state = self.state.lock().unwrap();
+		state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+		state.last_error = Some(error.to_string());
+	}
+
+	/// Whether the last call to the chain source succeeded.
+	pub(crate) fn is_healthy(&self) -> bool {
+		self.state.lock().unwrap().consecutive_failures == 0
+	}
+
+	/// The number of calls which failed since the last successful one.
+	pub(crate) fn consecutive_failures(&self) -> u32 {
+		self.state.lock().unwrap().consecutive_failures
+	}
+
+	/// The error of the most recent failed call, if any call failed since startup.
+	pub(crate) fn last_error(&self) -> Option<String> {
+		self.state.lock().unwrap().last_error.clone()
+	}
+
+	/// When a call last succeeded, as a UNIX timestamp.
+	pub(crate) fn last_success_time(&self) -> Option<u64> {
+		self.state.lock().unwrap().last_success_time
+	}
+}
+
+#[cfg(test)]
+mod health_tests {
+	use super::*;
+
+	#[test]
+	fn test_chain_source_health() {
+		let health = ChainSourceHealth::new();
+		assert!(health.is_healthy());
+		assert_eq!(health.last_success_time(), None);
+
+		health.record_failure("connection refused");
+		health.record_failure("connection refused");
+		assert!(!health.is_healthy());
+		assert_eq!(health.consecutive_failures(), 2);
+		assert_eq!(health.last_error().as_deref(), Some("connection refused"));
+
+		health.record_success();
+		assert!(health.is_healthy());
+		assert!(health.last_success_time().is_some());
+		// The last error is kept around for diagnosis.
+		assert_eq!(health.last_error().as_deref(), Some("connection refused"));
+	}
+
+	#[test]
+	fn test_next_backoff() {
+		assert_eq!(next_backoff(INITIAL_BACKOFF), Duration::from_millis(500));
+		assert_eq!(next_backoff(MAX_BACKOFF), MAX_BACKOFF);
+	}
+}
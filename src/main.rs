@@ -1,20 +1,37 @@
 mod args;
 pub mod bitcoind_client;
+mod channel_policy;
 mod cli;
 mod convert;
 mod disk;
+mod electrum;
+mod esplora;
+mod fee_policy;
+mod health;
 mod hex_utils;
+mod rgs;
+mod rpc;
+mod seed;
+mod static_fees;
 mod sweep;
+mod wallet;
+mod zmq;
 
 use crate::bitcoind_client::BitcoindClient;
+use crate::channel_policy::{InboundChannelDecision, InboundChannelPolicy};
+use crate::cli::{ChainSource, FeeSource, WalletKind};
 use crate::disk::FilesystemLogger;
-use bitcoin::blockdata::transaction::Transaction;
-use bitcoin::consensus::encode;
+use crate::electrum::{ElectrumClient, ElectrumSyncClient};
+use crate::esplora::{EsploraClient, EsploraSyncClient};
+use crate::static_fees::StaticFeeEstimator;
+use crate::wallet::{BatchFunding, FundingBatches, FundingLocks, OnchainWallet, WalletBackend};
+use bitcoin::blockdata::transaction::TxOut;
 use bitcoin::network::constants::Network;
-use bitcoin::BlockHash;
-use bitcoin_bech32::WitnessProgram;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::{BlockHash, Txid};
 use disk::{INBOUND_PAYMENTS_FNAME, OUTBOUND_PAYMENTS_FNAME};
-use lightning::chain::{chainmonitor, ChannelMonitorUpdateStatus};
+use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::{chainmonitor, BestBlock, ChannelMonitorUpdateStatus};
 use lightning::chain::{Filter, Watch};
 use lightning::events::bump_transaction::{BumpTransactionEventHandler, Wallet};
 use lightning::events::{Event, PaymentFailureReason, PaymentPurpose};
@@ -32,6 +49,7 @@ use lightning::routing::router::DefaultRouter;
 use lightning::routing::scoring::ProbabilisticScoringFeeParameters;
 use lightning::sign::{EntropySource, InMemorySigner, KeysManager, SpendableOutputDescriptor};
 use lightning::util::config::UserConfig;
+use lightning::util::logger::Logger;
 use lightning::util::persist::{self, KVStore, MonitorUpdatingPersister};
 use lightning::util::ser::{Readable, ReadableArgs, Writeable, Writer};
 use lightning::{chain, impl_writeable_tlv_based, impl_writeable_tlv_based_enum};
@@ -44,15 +62,14 @@ use lightning_net_tokio::SocketDescriptor;
 use lightning_persister::fs_store::FilesystemStore;
 use rand::{thread_rng, Rng};
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt;
 use std::fs;
-use std::fs::File;
 use std::io;
 use std::io::Write;
 use std::net::ToSocketAddrs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
 use std::time::{Duration, SystemTime};
@@ -126,11 +143,24 @@ impl_writeable_tlv_based!(OutboundPaymentInfoStorage, {
 	(0, payments, required),
 });
 
+/// A chain backend which syncs LDK through the `Confirm` interface, only querying the transactions
+/// and outputs it registered via `Filter`, rather than by having blocks connected from bitcoind.
+enum TxSyncBackend {
+	Esplora(Arc<EsploraSyncClient>, Arc<EsploraClient>),
+	Electrum(Arc<ElectrumSyncClient>, Arc<ElectrumClient>),
+}
+
+/// The fee estimator of whichever chain backend we're using.
+pub(crate) type DynFeeEstimator = dyn FeeEstimator + Send + Sync;
+
+/// The transaction broadcaster of whichever chain backend we're using.
+pub(crate) type DynBroadcaster = dyn BroadcasterInterface + Send + Sync;
+
 type ChainMonitor = chainmonitor::ChainMonitor<
 	InMemorySigner,
 	Arc<dyn Filter + Send + Sync>,
-	Arc<BitcoindClient>,
-	Arc<BitcoindClient>,
+	Arc<DynBroadcaster>,
+	Arc<DynFeeEstimator>,
 	Arc<FilesystemLogger>,
 	Arc<
 		MonitorUpdatingPersister<
@@ -151,34 +181,35 @@ pub(crate) type GossipVerifier = lightning_block_sync::gossip::GossipVerifier<
 pub(crate) type PeerManager = SimpleArcPeerManager<
 	SocketDescriptor,
 	ChainMonitor,
-	BitcoindClient,
-	BitcoindClient,
+	DynBroadcaster,
+	DynFeeEstimator,
 	GossipVerifier,
 	FilesystemLogger,
 >;
 
 pub(crate) type ChannelManager =
-	SimpleArcChannelManager<ChainMonitor, BitcoindClient, BitcoindClient, FilesystemLogger>;
+	SimpleArcChannelManager<ChainMonitor, DynBroadcaster, DynFeeEstimator, FilesystemLogger>;
 
 pub(crate) type NetworkGraph = gossip::NetworkGraph<Arc<FilesystemLogger>>;
 
 type OnionMessenger =
-	SimpleArcOnionMessenger<ChainMonitor, BitcoindClient, BitcoindClient, FilesystemLogger>;
+	SimpleArcOnionMessenger<ChainMonitor, DynBroadcaster, DynFeeEstimator, FilesystemLogger>;
 
 pub(crate) type BumpTxEventHandler = BumpTransactionEventHandler<
-	Arc<BitcoindClient>,
-	Arc<Wallet<Arc<BitcoindClient>, Arc<FilesystemLogger>>>,
+	Arc<DynBroadcaster>,
+	Arc<Wallet<Arc<WalletBackend>, Arc<FilesystemLogger>>>,
 	Arc<KeysManager>,
 	Arc<FilesystemLogger>,
 >;
 
 async fn handle_ldk_events(
-	channel_manager: Arc<ChannelManager>, bitcoind_client: &BitcoindClient,
-	network_graph: &NetworkGraph, keys_manager: &KeysManager,
+	channel_manager: Arc<ChannelManager>, wallet: &WalletBackend, funding_locks: &FundingLocks,
+	funding_batches: &FundingBatches, inbound_channel_policy: &InboundChannelPolicy,
+	fee_estimator: &DynFeeEstimator, network_graph: &NetworkGraph, keys_manager: &KeysManager,
 	bump_tx_event_handler: &BumpTxEventHandler, peer_manager: Arc<PeerManager>,
 	inbound_payments: Arc<Mutex<InboundPaymentInfoStorage>>,
 	outbound_payments: Arc<Mutex<OutboundPaymentInfoStorage>>, fs_store: Arc<FilesystemStore>,
-	network: Network, event: Event,
+	logger: &FilesystemLogger, event: Event,
 ) {
 	match event {
 		Event::FundingGenerationReady {
@@ -186,43 +217,54 @@ async fn handle_ldk_events(
 			counterparty_node_id,
 			channel_value_satoshis,
 			output_script,
-			..
+			user_channel_id,
 		} => {
-			// Construct the raw transaction with one output, that is paid the amount of the
-			// channel.
-			let addr = WitnessProgram::from_scriptpubkey(
-				&output_script.as_bytes(),
-				match network {
-					Network::Bitcoin => bitcoin_bech32::constants::Network::Bitcoin,
-					Network::Regtest => bitcoin_bech32::constants::Network::Regtest,
-					Network::Signet => bitcoin_bech32::constants::Network::Signet,
-					Network::Testnet | _ => bitcoin_bech32::constants::Network::Testnet,
-				},
-			)
-			.expect("Lightning funding tx should always be to a SegWit output")
-			.to_address();
-			let mut outputs = vec![HashMap::with_capacity(1)];
-			outputs[0].insert(addr, channel_value_satoshis as f64 / 100_000_000.0);
-			let raw_tx = bitcoind_client.create_raw_transaction(outputs).await;
-
-			// Have your wallet put the inputs into the transaction such that the output is
-			// satisfied.
-			let funded_tx = bitcoind_client.fund_raw_transaction(raw_tx).await;
-
-			// Sign the final funding transaction and give it to LDK, who will eventually broadcast it.
-			let signed_tx = bitcoind_client.sign_raw_transaction_with_wallet(funded_tx.hex).await;
-			assert_eq!(signed_tx.complete, true);
-			let final_tx: Transaction =
-				encode::deserialize(&hex_utils::to_vec(&signed_tx.hex).unwrap()).unwrap();
-			// Give the funding transaction back to LDK for opening the channel.
+			// Channels opened with `openchannels` share one funding transaction, built once LDK has
+			// given us the funding outputs of every channel in the batch.
+			let funding_output =
+				TxOut { value: channel_value_satoshis, script_pubkey: output_script };
+			let (channels, outputs) = match funding_batches.funding_ready(
+				user_channel_id,
+				temporary_channel_id,
+				funding_output.clone(),
+			) {
+				BatchFunding::NotBatched => {
+					(vec![(temporary_channel_id, counterparty_node_id)], vec![funding_output])
+				}
+				BatchFunding::Pending => return,
+				BatchFunding::Ready { channels, outputs } => (channels, outputs),
+			};
+			// Have our wallet fund and sign a transaction paying the channel amounts to the
+			// funding outputs, at the feerate we use for channel transactions.
+			let feerate =
+				fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee);
+			let final_tx = match wallet.create_transaction(outputs, feerate).await {
+				Ok(tx) => tx,
+				Err(e) => {
+					// Without a funding transaction the channels can never open, so give up on them.
+					println!("\nERROR: Failed to fund channel: {}", e);
+					print!("> ");
+					io::stdout().flush().unwrap();
+					for (temporary_channel_id, counterparty_node_id) in channels.iter() {
+						let _ = channel_manager.force_close_without_broadcasting_txn(
+							temporary_channel_id,
+							counterparty_node_id,
+						);
+					}
+					return;
+				}
+			};
+			// The wallet keeps the inputs locked until the channel is ready or LDK discards the
+			// funding transaction.
+			funding_locks.insert(&final_tx);
+			// Give the funding transaction back to LDK for opening the channels.
+			let temporary_channels: Vec<(&ChannelId, &PublicKey)> =
+				channels.iter().map(|(channel_id, node_id)| (channel_id, node_id)).collect();
 			if channel_manager
-				.funding_transaction_generated(
-					&temporary_channel_id,
-					&counterparty_node_id,
-					final_tx,
-				)
+				.batch_funding_transaction_generated(&temporary_channels, final_tx.clone())
 				.is_err()
 			{
+				funding_locks.release(wallet, &final_tx).await;
 				println!(
 					"\nERROR: Channel went away before we could fund it. The peer disconnected or refused the channel.");
 				print!("> ");
@@ -318,16 +360,62 @@ async fn handle_ldk_events(
 			fs_store.write("", "", OUTBOUND_PAYMENTS_FNAME, &outbound.encode()).unwrap();
 		}
 		Event::OpenChannelRequest {
-			ref temporary_channel_id, ref counterparty_node_id, ..
+			ref temporary_channel_id,
+			ref counterparty_node_id,
+			funding_satoshis,
+			push_msat: _,
+			ref channel_type,
 		} => {
+			let pending_channels = channel_manager
+				.list_channels_with_counterparty(counterparty_node_id)
+				.into_iter()
+				.filter(|channel| !channel.is_channel_ready)
+				.count();
+			let decision = inbound_channel_policy.check(
+				counterparty_node_id,
+				funding_satoshis,
+				channel_type,
+				pending_channels,
+			);
 			let mut random_bytes = [0u8; 16];
 			random_bytes.copy_from_slice(&keys_manager.get_secure_random_bytes()[..16]);
 			let user_channel_id = u128::from_be_bytes(random_bytes);
-			let res = channel_manager.accept_inbound_channel(
-				temporary_channel_id,
-				counterparty_node_id,
-				user_channel_id,
-			);
+			let res = match decision {
+				InboundChannelDecision::Accept => channel_manager.accept_inbound_channel(
+					temporary_channel_id,
+					counterparty_node_id,
+					user_channel_id,
+				),
+				InboundChannelDecision::AcceptZeroConf => channel_manager
+					.accept_inbound_channel_from_trusted_peer_0conf(
+						temporary_channel_id,
+						counterparty_node_id,
+						user_channel_id,
+					),
+				InboundChannelDecision::Reject(reason) => {
+					lightning::log_info!(
+						logger,
+						"Rejected inbound channel {} of {} sats from {}: {}",
+						temporary_channel_id,
+						funding_satoshis,
+						counterparty_node_id,
+						reason
+					);
+					print!(
+						"\nEVENT: Rejected inbound channel ({}) from {}: {}",
+						temporary_channel_id,
+						hex_utils::hex_str(&counterparty_node_id.serialize()),
+						reason,
+					);
+					print!("> ");
+					io::stdout().flush().unwrap();
+					let _ = channel_manager.force_close_without_broadcasting_txn(
+						temporary_channel_id,
+						counterparty_node_id,
+					);
+					return;
+				}
+			};
 
 			if let Err(e) = res {
 				print!(
@@ -482,10 +570,21 @@ async fn handle_ldk_events(
 			ref counterparty_node_id,
 			channel_type: _,
 		} => {
+			// The funding transaction has confirmed (or we trust our peer not to double-spend it),
+			// so its inputs no longer need to be locked.
+			let channel = channel_manager
+				.list_channels()
+				.into_iter()
+				.find(|channel| channel.channel_id == *channel_id);
+			if let Some(funding_txo) = channel.as_ref().and_then(|channel| channel.funding_txo) {
+				funding_locks.remove(&funding_txo.txid);
+			}
+			let zero_conf = channel.and_then(|channel| channel.confirmations) == Some(0);
 			println!(
-				"\nEVENT: Channel {} with peer {} is ready to be used!",
+				"\nEVENT: Channel {} with peer {} is ready to be used{}!",
 				channel_id,
 				hex_utils::hex_str(&counterparty_node_id.serialize()),
+				if zero_conf { " before its funding transaction confirmed" } else { "" },
 			);
 			print!("> ");
 			io::stdout().flush().unwrap();
@@ -493,11 +592,21 @@ async fn handle_ldk_events(
 		Event::ChannelClosed {
 			channel_id,
 			reason,
-			user_channel_id: _,
+			user_channel_id,
 			counterparty_node_id,
 			channel_capacity_sats: _,
 			channel_funding_txo: _,
 		} => {
+			// If the channel was part of a batch which hasn't been funded yet, the rest of the batch
+			// can no longer be funded with it.
+			for (temporary_channel_id, counterparty_node_id) in
+				funding_batches.abort(user_channel_id)
+			{
+				let _ = channel_manager.force_close_without_broadcasting_txn(
+					&temporary_channel_id,
+					&counterparty_node_id,
+				);
+			}
 			println!(
 				"\nEVENT: Channel {} with counterparty {} closed due to: {:?}",
 				channel_id,
@@ -507,9 +616,9 @@ async fn handle_ldk_events(
 			print!("> ");
 			io::stdout().flush().unwrap();
 		}
-		Event::DiscardFunding { .. } => {
-			// A "real" node should probably "lock" the UTXOs spent in funding transactions until
-			// the funding transaction either confirms, or this event is generated.
+		Event::DiscardFunding { transaction, .. } => {
+			// The funding transaction will never be broadcast, so its inputs can be spent again.
+			funding_locks.release(wallet, &transaction).await;
 		}
 		Event::HTLCIntercepted { .. } => {}
 		Event::BumpTransaction(event) => bump_tx_event_handler.handle_event(&event),
@@ -544,87 +653,207 @@ async fn start_ldk() {
 	// Step 1: Initialize the Logger
 	let logger = Arc::new(FilesystemLogger::new(ldk_data_dir.clone()));
 
-	// Initialize our bitcoind client.
-	let bitcoind_client = match BitcoindClient::new(
-		args.bitcoind_rpc_host.clone(),
-		args.bitcoind_rpc_port,
-		args.bitcoind_rpc_username.clone(),
-		args.bitcoind_rpc_password.clone(),
-		args.network,
-		tokio::runtime::Handle::current(),
-		Arc::clone(&logger),
-	)
-	.await
-	{
-		Ok(client) => Arc::new(client),
-		Err(e) => {
-			println!("Failed to connect to bitcoind client: {}", e);
-			return;
+	// With `bitcoind-zmq-hashblock`, bitcoind tells us about new blocks, which we use to sync and
+	// refresh fee estimates right away rather than by polling.
+	let block_notifications = args
+		.bitcoind_zmq_hashblock
+		.clone()
+		.map(|endpoint| zmq::subscribe_blocks(endpoint, Arc::clone(&logger)));
+
+	let fee_policy = Arc::new(args.fee_policy.clone());
+	// With static feerates, the chain source is never asked for estimates.
+	let chain_fee_policy = match args.fee_source {
+		FeeSource::Chain => Arc::clone(&fee_policy),
+		FeeSource::Static { .. } => Arc::new(fee_policy.with_static_feerates()),
+	};
+
+	// Initialize our bitcoind client, which we don't use at all with Esplora or Electrum.
+	let (bitcoind_client, blockchain_info) = match args.chain_source {
+		ChainSource::Bitcoind => {
+			let bitcoind_client = match BitcoindClient::new(
+				args.bitcoind_rpc_host.clone(),
+				args.bitcoind_rpc_port,
+				args.bitcoind_rpc_username.clone(),
+				args.bitcoind_rpc_password.clone(),
+				args.network,
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+				block_notifications.clone(),
+				Arc::clone(&chain_fee_policy),
+			)
+			.await
+			{
+				Ok(mut client) => {
+					if args.bitcoind_rest {
+						if let Err(e) = client.enable_rest_block_source().await {
+							println!("Failed to connect to bitcoind's REST interface: {}", e);
+							return;
+						}
+					}
+					Arc::new(client)
+				}
+				Err(e) => {
+					println!("Failed to connect to bitcoind client: {}", e);
+					return;
+				}
+			};
+
+			// Check that the bitcoind we've connected to is running the network we expect
+			let blockchain_info = match bitcoind_client.get_blockchain_info().await {
+				Ok(blockchain_info) => blockchain_info,
+				Err(e) => {
+					println!("Failed to get blockchain info from bitcoind: {}", e);
+					return;
+				}
+			};
+			let bitcoind_chain = &blockchain_info.chain;
+			if bitcoind_chain
+				!= match args.network {
+					bitcoin::Network::Bitcoin => "main",
+					bitcoin::Network::Regtest => "regtest",
+					bitcoin::Network::Signet => "signet",
+					bitcoin::Network::Testnet | _ => "test",
+				} {
+				println!(
+					"Chain argument ({}) didn't match bitcoind chain ({})",
+					args.network, bitcoind_chain
+				);
+				return;
+			}
+
+			// On mainnet, refuse to start against a bitcoind which is still syncing, as we'd
+			// otherwise act on a stale view of the chain (e.g. miss that a counterparty broadcast a
+			// revoked state).
+			if args.network == Network::Bitcoin
+				&& (blockchain_info.initial_block_download
+					|| blockchain_info.latest_height < blockchain_info.headers)
+			{
+				println!(
+					"ERROR: bitcoind is not fully synced (block {} of {}), please wait for it to catch up before starting on mainnet",
+					blockchain_info.latest_height, blockchain_info.headers
+				);
+				return;
+			}
+
+			(Some(bitcoind_client), Some(blockchain_info))
 		}
+		ChainSource::Esplora { .. } | ChainSource::Electrum { .. } => (None, None),
 	};
 
-	// Check that the bitcoind we've connected to is running the network we expect
-	let bitcoind_chain = bitcoind_client.get_blockchain_info().await.chain;
-	if bitcoind_chain
-		!= match args.network {
-			bitcoin::Network::Bitcoin => "main",
-			bitcoin::Network::Regtest => "regtest",
-			bitcoin::Network::Signet => "signet",
-			bitcoin::Network::Testnet | _ => "test",
-		} {
-		println!(
-			"Chain argument ({}) didn't match bitcoind chain ({})",
-			args.network, bitcoind_chain
-		);
-		return;
-	}
+	// With `chain-source=esplora` or `chain-source=electrum`, we sync with the chain, estimate fees
+	// and broadcast via Esplora or an Electrum server instead of bitcoind, after checking that it
+	// follows the network we expect.
+	let tx_sync = match &args.chain_source {
+		ChainSource::Bitcoind => None,
+		ChainSource::Esplora { url } => {
+			let tx_sync = Arc::new(EsploraSyncClient::new(url.clone(), Arc::clone(&logger)));
+			match EsploraClient::new(
+				Arc::clone(&tx_sync),
+				args.network,
+				Arc::clone(&chain_fee_policy),
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+			)
+			.await
+			{
+				Ok(client) => Some(TxSyncBackend::Esplora(tx_sync, Arc::new(client))),
+				Err(e) => {
+					println!("Failed to connect to Esplora at {}: {}", url, e);
+					return;
+				}
+			}
+		}
+		ChainSource::Electrum { url } => {
+			let (server_url, sync_logger) = (url.clone(), Arc::clone(&logger));
+			let tx_sync = match tokio::task::spawn_blocking(move || {
+				ElectrumSyncClient::new(server_url, sync_logger)
+			})
+			.await
+			.unwrap()
+			{
+				Ok(tx_sync) => Arc::new(tx_sync),
+				Err(e) => {
+					println!("Failed to connect to the Electrum server at {}: {}", url, e);
+					return;
+				}
+			};
+			match ElectrumClient::new(
+				Arc::clone(&tx_sync),
+				args.network,
+				Arc::clone(&chain_fee_policy),
+				tokio::runtime::Handle::current(),
+				Arc::clone(&logger),
+			)
+			.await
+			{
+				Ok(client) => Some(TxSyncBackend::Electrum(tx_sync, Arc::new(client))),
+				Err(e) => {
+					println!("Failed to connect to the Electrum server at {}: {}", url, e);
+					return;
+				}
+			}
+		}
+	};
 
 	// Step 2: Initialize the FeeEstimator
 
-	// BitcoindClient implements the FeeEstimator trait, so it'll act as our fee estimator.
-	let fee_estimator = bitcoind_client.clone();
+	// BitcoindClient, EsploraClient and ElectrumClient implement the FeeEstimator trait, so they can
+	// act as our fee estimator. With `fee-source=static` we use fixed feerates instead.
+	let fee_estimator: Arc<DynFeeEstimator> = match (&args.fee_source, &bitcoind_client, &tx_sync) {
+		(FeeSource::Static { estimates_file }, _, _) => {
+			match StaticFeeEstimator::new(
+				Arc::clone(&fee_policy),
+				estimates_file.clone(),
+				Arc::clone(&logger),
+			) {
+				Ok(estimator) => Arc::new(estimator),
+				Err(e) => {
+					println!("ERROR: {}", e);
+					return;
+				}
+			}
+		}
+		(FeeSource::Chain, _, Some(TxSyncBackend::Esplora(_, esplora_client))) => {
+			esplora_client.clone()
+		}
+		(FeeSource::Chain, _, Some(TxSyncBackend::Electrum(_, electrum_client))) => {
+			electrum_client.clone()
+		}
+		(FeeSource::Chain, Some(bitcoind_client), None) => bitcoind_client.clone(),
+		(FeeSource::Chain, None, None) => unreachable!(),
+	};
 
 	// Step 3: Initialize the BroadcasterInterface
 
-	// BitcoindClient implements the BroadcasterInterface trait, so it'll act as our transaction
-	// broadcaster.
-	let broadcaster = bitcoind_client.clone();
+	// BitcoindClient, EsploraClient and ElectrumClient implement the BroadcasterInterface trait, so
+	// they can act as our transaction broadcaster.
+	let broadcaster: Arc<DynBroadcaster> = match (&bitcoind_client, &tx_sync) {
+		(_, Some(TxSyncBackend::Esplora(_, esplora_client))) => esplora_client.clone(),
+		(_, Some(TxSyncBackend::Electrum(_, electrum_client))) => electrum_client.clone(),
+		(Some(bitcoind_client), None) => bitcoind_client.clone(),
+		(None, None) => unreachable!(),
+	};
 
 	// Step 4: Initialize the KeysManager
 
 	// The key seed that we use to derive the node privkey (that corresponds to the node pubkey) and
 	// other secret key material.
 	let keys_seed_path = format!("{}/keys_seed", ldk_data_dir.clone());
-	let keys_seed = if let Ok(seed) = fs::read(keys_seed_path.clone()) {
-		assert_eq!(seed.len(), 32);
-		let mut key = [0; 32];
-		key.copy_from_slice(&seed);
-		key
-	} else {
-		let mut key = [0; 32];
-		thread_rng().fill_bytes(&mut key);
-		match File::create(keys_seed_path.clone()) {
-			Ok(mut f) => {
-				Write::write_all(&mut f, &key).expect("Failed to write node keys seed to disk");
-				f.sync_all().expect("Failed to sync node keys seed to disk");
-			}
-			Err(e) => {
-				println!("ERROR: Unable to create keys seed file {}: {}", keys_seed_path, e);
-				return;
-			}
-		}
-		key
+	let seed_options = seed::SeedOptions {
+		backup_path: args.keys_seed_backup_path.as_ref().map(PathBuf::from),
+		restore_from_mnemonic: args.restore_from_mnemonic,
+		verify_mnemonic: args.verify_mnemonic,
+		encrypt: args.encrypt_keys_seed,
+		passphrase_fd: args.seed_passphrase_fd,
+		require_existing_backup: args.network == Network::Bitcoin,
+	};
+	let keys_seed = match seed::init_keys_seed(Path::new(&keys_seed_path), &seed_options) {
+		Ok(keys_seed) => keys_seed,
+		Err(()) => return,
 	};
 	let cur = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
 	let keys_manager = Arc::new(KeysManager::new(&keys_seed, cur.as_secs(), cur.subsec_nanos()));
 
-	let bump_tx_event_handler = Arc::new(BumpTransactionEventHandler::new(
-		Arc::clone(&broadcaster),
-		Arc::new(Wallet::new(Arc::clone(&bitcoind_client), Arc::clone(&logger))),
-		Arc::clone(&keys_manager),
-		Arc::clone(&logger),
-	));
-
 	// Step 5: Initialize Persistence
 	let fs_store = Arc::new(FilesystemStore::new(ldk_data_dir.clone().into()));
 	let persister = Arc::new(MonitorUpdatingPersister::new(
@@ -638,9 +867,79 @@ async fn start_ldk() {
 	// larger `ChannelMonitor` update writes (but no deletion or cleanup):
 	//let persister = Arc::clone(&fs_store);
 
+	// The on-chain wallet we fund channels from, sweep to and bump anchor transactions with. A new
+	// internal wallet starts watching the chain from its current tip. Without bitcoind, the
+	// arguments only allow the internal wallet.
+	let wallet = Arc::new(match (args.onchain_wallet, &bitcoind_client) {
+		(WalletKind::Bitcoind, Some(bitcoind_client)) => {
+			WalletBackend::Bitcoind(Arc::clone(bitcoind_client))
+		}
+		(WalletKind::Bitcoind, None) => unreachable!(),
+		(WalletKind::Internal, _) => {
+			let best_block = match (&blockchain_info, &tx_sync) {
+				(Some(info), _) => BestBlock::new(info.latest_blockhash, info.latest_height as u32),
+				(None, Some(TxSyncBackend::Esplora(_, esplora_client))) => {
+					match esplora_client.get_best_block().await {
+						Ok(best_block) => best_block,
+						Err(e) => {
+							println!("ERROR: failed to fetch the best block from Esplora: {}", e);
+							return;
+						}
+					}
+				}
+				(None, Some(TxSyncBackend::Electrum(_, electrum_client))) => {
+					match electrum_client.get_best_block().await {
+						Ok(best_block) => best_block,
+						Err(e) => {
+							println!("ERROR: failed to fetch the best block from the Electrum server: {}", e);
+							return;
+						}
+					}
+				}
+				(None, None) => unreachable!(),
+			};
+			match OnchainWallet::new(
+				&keys_seed,
+				args.network,
+				best_block.block_hash(),
+				best_block.height(),
+				Arc::clone(&broadcaster),
+				Arc::clone(&fs_store),
+				Arc::clone(&logger),
+			) {
+				Ok(wallet) => WalletBackend::Internal(Arc::new(wallet)),
+				Err(e) => {
+					println!("ERROR: {}", e);
+					return;
+				}
+			}
+		}
+	});
+	if let WalletBackend::Internal(onchain_wallet) = &*wallet {
+		let [receive, change] = onchain_wallet.descriptors();
+		lightning::log_info!(logger, "On-chain wallet descriptors: {} {}", receive, change);
+	}
+
+	let funding_locks = Arc::new(FundingLocks::new(Arc::clone(&fs_store), Arc::clone(&logger)));
+	let funding_batches = Arc::new(FundingBatches::new());
+
+	let bump_tx_event_handler = Arc::new(BumpTransactionEventHandler::new(
+		Arc::clone(&broadcaster),
+		Arc::new(Wallet::new(Arc::clone(&wallet), Arc::clone(&logger))),
+		Arc::clone(&keys_manager),
+		Arc::clone(&logger),
+	));
+
 	// Step 6: Initialize the ChainMonitor
+	// When syncing via Esplora or Electrum, the chain monitor registers the transactions and outputs
+	// we need to watch with the sync client.
+	let chain_filter: Option<Arc<dyn Filter + Send + Sync>> = match &tx_sync {
+		None => None,
+		Some(TxSyncBackend::Esplora(esplora_sync, _)) => Some(esplora_sync.clone()),
+		Some(TxSyncBackend::Electrum(electrum_sync, _)) => Some(electrum_sync.clone()),
+	};
 	let chain_monitor: Arc<ChainMonitor> = Arc::new(chainmonitor::ChainMonitor::new(
-		None,
+		chain_filter,
 		Arc::clone(&broadcaster),
 		Arc::clone(&logger),
 		Arc::clone(&fee_estimator),
@@ -648,17 +947,21 @@ async fn start_ldk() {
 	));
 
 	// Step 7: Read ChannelMonitor state from disk
-	let mut channelmonitors = persister
-		.read_all_channel_monitors_with_updates(&bitcoind_client, &bitcoind_client)
-		.unwrap();
+	let mut channelmonitors =
+		persister.read_all_channel_monitors_with_updates(&broadcaster, &fee_estimator).unwrap();
 	// If you are using the `FilesystemStore` as a `Persist` directly, use
 	// `lightning::util::persist::read_channel_monitors` like this:
 	//read_channel_monitors(Arc::clone(&persister), Arc::clone(&keys_manager), Arc::clone(&keys_manager)).unwrap();
 
 	// Step 8: Poll for the best chain tip, which may be used by the channel manager & spv client
-	let polled_chain_tip = init::validate_best_block_header(bitcoind_client.as_ref())
-		.await
-		.expect("Failed to fetch best block header and best block");
+	let polled_chain_tip = match &bitcoind_client {
+		Some(bitcoind_client) => Some(
+			init::validate_best_block_header(bitcoind_client.as_ref())
+				.await
+				.expect("Failed to fetch best block header and best block"),
+		),
+		None => None,
+	};
 
 	// Step 9: Initialize routing ProbabilisticScorer
 	let network_graph_path = format!("{}/network_graph", ldk_data_dir.clone());
@@ -687,6 +990,15 @@ async fn start_ldk() {
 	user_config.channel_handshake_limits.force_announced_channel_preference = false;
 	user_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
 	user_config.manually_accept_inbound_channels = true;
+	// Don't commit to a shutdown script when opening channels, so that `closechannel --address` can
+	// pay our balance anywhere.
+	user_config.channel_handshake_config.commit_upfront_shutdown_pubkey = false;
+	if args.inbound_channel_policy.reject_private {
+		// Have LDK refuse inbound channels which wouldn't be announced, as we only learn whether
+		// they would be after accepting them.
+		user_config.channel_handshake_limits.force_announced_channel_preference = true;
+		user_config.channel_handshake_config.announced_channel = true;
+	}
 	let mut restarting_node = true;
 	let (channel_manager_blockhash, channel_manager) = {
 		if let Ok(mut f) = fs::File::open(format!("{}/manager", ldk_data_dir.clone())) {
@@ -711,7 +1023,28 @@ async fn start_ldk() {
 			// We're starting a fresh node.
 			restarting_node = false;
 
-			let polled_best_block = polled_chain_tip.to_best_block();
+			let polled_best_block = match (&polled_chain_tip, &tx_sync) {
+				(Some(polled_chain_tip), _) => polled_chain_tip.to_best_block(),
+				(None, Some(TxSyncBackend::Esplora(_, esplora_client))) => {
+					match esplora_client.get_best_block().await {
+						Ok(best_block) => best_block,
+						Err(e) => {
+							println!("ERROR: failed to fetch the best block from Esplora: {}", e);
+							return;
+						}
+					}
+				}
+				(None, Some(TxSyncBackend::Electrum(_, electrum_client))) => {
+					match electrum_client.get_best_block().await {
+						Ok(best_block) => best_block,
+						Err(e) => {
+							println!("ERROR: failed to fetch the best block from the Electrum server: {}", e);
+							return;
+						}
+					}
+				}
+				(None, None) => unreachable!(),
+			};
 			let polled_best_block_hash = polled_best_block.block_hash();
 			let chain_params =
 				ChainParameters { network: args.network, best_block: polled_best_block };
@@ -735,12 +1068,7 @@ async fn start_ldk() {
 	// Step 12: Sync ChannelMonitors and ChannelManager to chain tip
 	let mut chain_listener_channel_monitors = Vec::new();
 	let mut cache = UnboundedCache::new();
-	let chain_tip = if restarting_node {
-		let mut chain_listeners = vec![(
-			channel_manager_blockhash,
-			&channel_manager as &(dyn chain::Listen + Send + Sync),
-		)];
-
+	if restarting_node {
 		for (blockhash, channel_monitor) in channelmonitors.drain(..) {
 			let outpoint = channel_monitor.get_funding_txo().0;
 			chain_listener_channel_monitors.push((
@@ -749,24 +1077,46 @@ async fn start_ldk() {
 				outpoint,
 			));
 		}
+	}
+	// When syncing via Esplora or Electrum there's no block source to replay blocks from, instead
+	// the first sync below catches the ChannelMonitors, ChannelManager and internal wallet up.
+	let chain_tip = match (&bitcoind_client, polled_chain_tip) {
+		(Some(bitcoind_client), Some(_))
+			if restarting_node || matches!(*wallet, WalletBackend::Internal(_)) =>
+		{
+			let mut chain_listeners = Vec::new();
+			if restarting_node {
+				chain_listeners.push((
+					channel_manager_blockhash,
+					&channel_manager as &(dyn chain::Listen + Send + Sync),
+				));
+			}
+			if let WalletBackend::Internal(onchain_wallet) = &*wallet {
+				chain_listeners.push((
+					onchain_wallet.best_block_hash(),
+					&**onchain_wallet as &(dyn chain::Listen + Send + Sync),
+				));
+			}
 
-		for monitor_listener_info in chain_listener_channel_monitors.iter_mut() {
-			chain_listeners.push((
-				monitor_listener_info.0,
-				&monitor_listener_info.1 as &(dyn chain::Listen + Send + Sync),
-			));
-		}
+			for monitor_listener_info in chain_listener_channel_monitors.iter_mut() {
+				chain_listeners.push((
+					monitor_listener_info.0,
+					&monitor_listener_info.1 as &(dyn chain::Listen + Send + Sync),
+				));
+			}
 
-		init::synchronize_listeners(
-			bitcoind_client.as_ref(),
-			args.network,
-			&mut cache,
-			chain_listeners,
-		)
-		.await
-		.unwrap()
-	} else {
-		polled_chain_tip
+			Some(
+				init::synchronize_listeners(
+					bitcoind_client.as_ref(),
+					args.network,
+					&mut cache,
+					chain_listeners,
+				)
+				.await
+				.unwrap(),
+			)
+		}
+		(_, polled_chain_tip) => polled_chain_tip,
 	};
 
 	// Step 13: Give ChannelMonitors to ChainMonitor
@@ -779,10 +1129,59 @@ async fn start_ldk() {
 		);
 	}
 
+	// Release the inputs locked for funding transactions of channels which went away while we were
+	// offline, without LDK telling us to discard them. A channel with a `ChannelMonitor` got as far
+	// as LDK broadcasting its funding transaction, whose inputs may well be spent in the mempool, so
+	// we only stop tracking those. If it never confirms, `abandontransaction` releases them.
+	let funded_txids: HashSet<Txid> = channel_manager
+		.list_channels()
+		.into_iter()
+		.filter_map(|channel| channel.funding_txo.map(|funding_txo| funding_txo.txid))
+		.collect();
+	let broadcast_txids: HashSet<Txid> =
+		chain_monitor.list_monitors().into_iter().map(|funding_txo| funding_txo.txid).collect();
+	for funding_tx in funding_locks.pending() {
+		let txid = funding_tx.txid();
+		if funded_txids.contains(&txid) {
+			continue;
+		}
+		if broadcast_txids.contains(&txid) {
+			funding_locks.remove(&txid);
+		} else {
+			funding_locks.release(&wallet, &funding_tx).await;
+		}
+	}
+
 	// Step 14: Optional: Initialize the P2PGossipSync
 	let gossip_sync =
 		Arc::new(P2PGossipSync::new(Arc::clone(&network_graph), None, Arc::clone(&logger)));
 
+	// Optional: bootstrap the NetworkGraph via Rapid Gossip Sync so we can route right away. We
+	// still accept gossip from our peers, but leave pruning the graph to RGS.
+	let rapid_gossip_sync = match &args.rgs_source {
+		Some(source) => {
+			let rgs = Arc::new(rgs::RapidGossipSync::new(
+				Arc::clone(&network_graph),
+				Arc::clone(&logger),
+			));
+			if rgs::sync(&rgs, source).await.is_ok() {
+				let graph = network_graph.read_only();
+				println!(
+					"Synced the network graph via RGS: {} nodes, {} channels",
+					graph.nodes().len(),
+					graph.channels().len()
+				);
+			}
+			tokio::spawn(rgs::periodic_sync(
+				Arc::clone(&rgs),
+				source.clone(),
+				Duration::from_secs(args.rgs_sync_interval_secs),
+			));
+			Some(rgs)
+		}
+		None => None,
+	};
+
 	// Step 15: Initialize the PeerManager
 	let channel_manager: Arc<ChannelManager> = Arc::new(channel_manager);
 	let onion_messenger: Arc<OnionMessenger> = Arc::new(OnionMessenger::new(
@@ -810,14 +1209,17 @@ async fn start_ldk() {
 		Arc::clone(&keys_manager),
 	));
 
-	// Install a GossipVerifier in in the P2PGossipSync
-	let utxo_lookup = GossipVerifier::new(
-		Arc::clone(&bitcoind_client.bitcoind_rpc_client),
-		lightning_block_sync::gossip::TokioSpawner,
-		Arc::clone(&gossip_sync),
-		Arc::clone(&peer_manager),
-	);
-	gossip_sync.add_utxo_lookup(Some(utxo_lookup));
+	// Install a GossipVerifier in in the P2PGossipSync. Without bitcoind we have no way to look up
+	// the funding outputs of announced channels, so accept gossip without checking them.
+	if let Some(bitcoind_client) = &bitcoind_client {
+		let utxo_lookup = GossipVerifier::new(
+			Arc::clone(&bitcoind_client.bitcoind_rpc_client),
+			lightning_block_sync::gossip::TokioSpawner,
+			Arc::clone(&gossip_sync),
+			Arc::clone(&peer_manager),
+		);
+		gossip_sync.add_utxo_lookup(Some(utxo_lookup));
+	}
 
 	// ## Running LDK
 	// Step 16: Initialize networking
@@ -847,19 +1249,90 @@ async fn start_ldk() {
 	});
 
 	// Step 17: Connect and Disconnect Blocks
+	// `nodeinfo` reports whether whichever chain source we sync from is reachable.
+	let chain_health = match (&bitcoind_client, &tx_sync) {
+		(_, Some(TxSyncBackend::Esplora(_, esplora_client))) => esplora_client.health(),
+		(_, Some(TxSyncBackend::Electrum(_, electrum_client))) => electrum_client.health(),
+		(Some(bitcoind_client), None) => bitcoind_client.health(),
+		(None, None) => unreachable!(),
+	};
 	let channel_manager_listener = channel_manager.clone();
 	let chain_monitor_listener = chain_monitor.clone();
-	let bitcoind_block_source = bitcoind_client.clone();
+	let wallet_listener = Arc::clone(&wallet);
 	let network = args.network;
-	tokio::spawn(async move {
-		let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), network);
-		let chain_listener = (chain_monitor_listener, channel_manager_listener);
-		let mut spv_client = SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
-		loop {
-			spv_client.poll_best_tip().await.unwrap();
-			tokio::time::sleep(Duration::from_secs(1)).await;
+	match (chain_tip, bitcoind_client, &tx_sync) {
+		(Some(chain_tip), Some(bitcoind_block_source), _) => {
+			// Without block notifications we poll bitcoind every second, with them we only poll as
+			// a fallback in case a notification was lost.
+			let mut block_notifications = block_notifications;
+			let poll_interval = match block_notifications {
+				Some(_) => zmq::FALLBACK_POLL_INTERVAL,
+				None => Duration::from_secs(1),
+			};
+			let (chain_health, logger) = (Arc::clone(&chain_health), Arc::clone(&logger));
+			tokio::spawn(async move {
+				let chain_poller = poll::ChainPoller::new(bitcoind_block_source.as_ref(), network);
+				let manager_and_wallet = (channel_manager_listener, wallet_listener);
+				let chain_listener = (chain_monitor_listener, &manager_and_wallet);
+				let mut spv_client =
+					SpvClient::new(chain_tip, chain_poller, &mut cache, &chain_listener);
+				// If bitcoind goes away, keep retrying with backoff until it's back rather than
+				// giving up on syncing.
+				let mut backoff = health::INITIAL_BACKOFF;
+				loop {
+					match spv_client.poll_best_tip().await {
+						Ok(_) => {
+							chain_health.record_success();
+							backoff = health::INITIAL_BACKOFF;
+							zmq::wait_for_block(&mut block_notifications, poll_interval).await;
+						}
+						Err(e) => {
+							let e = e.into_inner();
+							lightning::log_error!(logger, "Failed to sync with bitcoind: {}", e);
+							chain_health.record_failure(e);
+							tokio::time::sleep(backoff).await;
+							backoff = health::next_backoff(backoff);
+						}
+					}
+				}
+			});
 		}
-	});
+		(None, _, Some(TxSyncBackend::Esplora(esplora_sync, _))) => {
+			tokio::spawn(esplora::periodic_sync(
+				Arc::clone(esplora_sync),
+				channel_manager_listener,
+				chain_monitor_listener,
+				Arc::clone(&chain_health),
+				Arc::clone(&logger),
+			));
+			if let WalletBackend::Internal(onchain_wallet) = &*wallet_listener {
+				tokio::spawn(esplora::periodic_wallet_sync(
+					Arc::clone(esplora_sync),
+					Arc::clone(onchain_wallet),
+					Arc::clone(&chain_health),
+					Arc::clone(&logger),
+				));
+			}
+		}
+		(None, _, Some(TxSyncBackend::Electrum(electrum_sync, _))) => {
+			tokio::spawn(electrum::periodic_sync(
+				Arc::clone(electrum_sync),
+				channel_manager_listener,
+				chain_monitor_listener,
+				Arc::clone(&chain_health),
+				Arc::clone(&logger),
+			));
+			if let WalletBackend::Internal(onchain_wallet) = &*wallet_listener {
+				tokio::spawn(electrum::periodic_wallet_sync(
+					Arc::clone(electrum_sync),
+					Arc::clone(onchain_wallet),
+					Arc::clone(&chain_health),
+					Arc::clone(&logger),
+				));
+			}
+		}
+		(Some(_), None, _) | (None, _, None) => unreachable!(),
+	}
 
 	let inbound_payments = Arc::new(Mutex::new(disk::read_inbound_payment_info(Path::new(
 		&format!("{}/{}", ldk_data_dir, INBOUND_PAYMENTS_FNAME),
@@ -894,17 +1367,25 @@ async fn start_ldk() {
 
 	// Step 18: Handle LDK Events
 	let channel_manager_event_listener = Arc::clone(&channel_manager);
-	let bitcoind_client_event_listener = Arc::clone(&bitcoind_client);
+	let wallet_event_listener = Arc::clone(&wallet);
+	let funding_locks_event_listener = Arc::clone(&funding_locks);
+	let funding_batches_event_listener = Arc::clone(&funding_batches);
+	let inbound_channel_policy = Arc::new(args.inbound_channel_policy.clone());
+	let fee_estimator_event_listener = Arc::clone(&fee_estimator);
 	let network_graph_event_listener = Arc::clone(&network_graph);
 	let keys_manager_event_listener = Arc::clone(&keys_manager);
 	let inbound_payments_event_listener = Arc::clone(&inbound_payments);
 	let outbound_payments_event_listener = Arc::clone(&outbound_payments);
 	let fs_store_event_listener = Arc::clone(&fs_store);
 	let peer_manager_event_listener = Arc::clone(&peer_manager);
-	let network = args.network;
+	let logger_event_listener = Arc::clone(&logger);
 	let event_handler = move |event: Event| {
 		let channel_manager_event_listener = Arc::clone(&channel_manager_event_listener);
-		let bitcoind_client_event_listener = Arc::clone(&bitcoind_client_event_listener);
+		let wallet_event_listener = Arc::clone(&wallet_event_listener);
+		let funding_locks_event_listener = Arc::clone(&funding_locks_event_listener);
+		let funding_batches_event_listener = Arc::clone(&funding_batches_event_listener);
+		let inbound_channel_policy = Arc::clone(&inbound_channel_policy);
+		let fee_estimator_event_listener = Arc::clone(&fee_estimator_event_listener);
 		let network_graph_event_listener = Arc::clone(&network_graph_event_listener);
 		let keys_manager_event_listener = Arc::clone(&keys_manager_event_listener);
 		let bump_tx_event_handler = Arc::clone(&bump_tx_event_handler);
@@ -912,10 +1393,15 @@ async fn start_ldk() {
 		let outbound_payments_event_listener = Arc::clone(&outbound_payments_event_listener);
 		let fs_store_event_listener = Arc::clone(&fs_store_event_listener);
 		let peer_manager_event_listener = Arc::clone(&peer_manager_event_listener);
+		let logger_event_listener = Arc::clone(&logger_event_listener);
 		async move {
 			handle_ldk_events(
 				channel_manager_event_listener,
-				&bitcoind_client_event_listener,
+				&wallet_event_listener,
+				&funding_locks_event_listener,
+				&funding_batches_event_listener,
+				&inbound_channel_policy,
+				&*fee_estimator_event_listener,
 				&network_graph_event_listener,
 				&keys_manager_event_listener,
 				&bump_tx_event_handler,
@@ -923,7 +1409,7 @@ async fn start_ldk() {
 				inbound_payments_event_listener,
 				outbound_payments_event_listener,
 				fs_store_event_listener,
-				network,
+				&logger_event_listener,
 				event,
 			)
 			.await;
@@ -935,12 +1421,16 @@ async fn start_ldk() {
 
 	// Step 20: Background Processing
 	let (bp_exit, bp_exit_check) = tokio::sync::watch::channel(());
+	let bp_gossip_sync = match &rapid_gossip_sync {
+		Some(rgs) => GossipSync::Rapid(Arc::clone(rgs)),
+		None => GossipSync::P2P(Arc::clone(&gossip_sync)),
+	};
 	let mut background_processor = tokio::spawn(process_events_async(
 		Arc::clone(&persister),
 		event_handler,
 		chain_monitor.clone(),
 		channel_manager.clone(),
-		GossipSync::p2p(gossip_sync.clone()),
+		bp_gossip_sync,
 		peer_manager.clone(),
 		logger.clone(),
 		Some(scorer.clone()),
@@ -1001,6 +1491,7 @@ async fn start_ldk() {
 	let peer_man = Arc::clone(&peer_manager);
 	let chan_man = Arc::clone(&channel_manager);
 	let network = args.network;
+	let (daemon, rpc_bind, rpc_socket) = (args.daemon, args.rpc_bind, args.rpc_socket.clone());
 	tokio::spawn(async move {
 		// First wait a minute until we have some peers and maybe have opened a channel.
 		tokio::time::sleep(Duration::from_secs(60)).await;
@@ -1027,41 +1518,87 @@ async fn start_ldk() {
 		Arc::clone(&keys_manager),
 		Arc::clone(&logger),
 		Arc::clone(&persister),
-		Arc::clone(&bitcoind_client),
+		Arc::clone(&wallet),
+		Arc::clone(&fee_estimator),
+		Arc::clone(&broadcaster),
 		Arc::clone(&channel_manager),
 	));
 
-	// Start the CLI.
-	let cli_channel_manager = Arc::clone(&channel_manager);
-	let cli_persister = Arc::clone(&persister);
-	let cli_logger = Arc::clone(&logger);
-	let cli_peer_manager = Arc::clone(&peer_manager);
-	let cli_poll = tokio::task::spawn_blocking(move || {
-		cli::poll_for_user_input(
-			cli_peer_manager,
-			cli_channel_manager,
-			keys_manager,
-			network_graph,
-			onion_messenger,
-			inbound_payments,
-			outbound_payments,
-			ldk_data_dir,
-			network,
-			cli_logger,
-			cli_persister,
-		)
-	});
+	let ctx = cli::NodeContext {
+		peer_manager: Arc::clone(&peer_manager),
+		channel_manager: Arc::clone(&channel_manager),
+		keys_manager,
+		network_graph,
+		onion_messenger,
+		inbound_payments,
+		outbound_payments,
+		ldk_data_dir: ldk_data_dir.clone(),
+		keys_seed_backup_path: seed_options.backup_path,
+		network,
+		logger: Arc::clone(&logger),
+		fs_store: Arc::clone(&persister),
+		output_format: Arc::new(Mutex::new(cli::OutputFormat::Text)),
+		chain_health,
+		fee_estimator: Arc::clone(&fee_estimator),
+		// The policy feerates are actually computed with, so `feerates` marks every feerate as
+		// static with `fee-source=static`.
+		fee_policy: chain_fee_policy,
+		wallet,
+		funding_batches,
+	};
+
+	// Start the JSON-RPC control API, if requested.
+	let stop_rpc = Arc::new(tokio::sync::Notify::new());
+	let rpc_listen_addr = match (rpc_bind, rpc_socket) {
+		(Some(addr), _) => Some(rpc::RpcListenAddr::Tcp(addr)),
+		#[cfg(unix)]
+		(None, Some(path)) => Some(rpc::RpcListenAddr::Unix(path)),
+		_ => None,
+	};
+	if let Some(listen_addr) = &rpc_listen_addr {
+		let cookie = match rpc::write_cookie(&ldk_data_dir) {
+			Ok(cookie) => cookie,
+			Err(()) => return,
+		};
+		if rpc::start_server(listen_addr, ctx.clone(), cookie, Arc::clone(&stop_rpc)).await.is_err()
+		{
+			rpc::remove_cookie(&ldk_data_dir);
+			return;
+		}
+	}
+
+	// Start the CLI, unless we're running as a daemon in which case we wait for a signal or an RPC
+	// `stop` instead.
+	let cli_poll =
+		async {
+			if daemon {
+				println!("LDK startup successful. Running as a daemon, stop with SIGTERM or the `stop` RPC.");
+				println!("Local Node ID is {}.", channel_manager.get_our_node_id());
+				wait_for_shutdown_signal().await;
+			} else {
+				let _ = tokio::task::spawn_blocking(move || cli::poll_for_user_input(ctx)).await;
+			}
+		};
 
 	// Exit if either CLI polling exits or the background processor exits (which shouldn't happen
 	// unless we fail to write to the filesystem).
 	let mut bg_res = Ok(Ok(()));
 	tokio::select! {
 		_ = cli_poll => {},
+		_ = stop_rpc.notified() => {},
 		bg_exit = &mut background_processor => {
 			bg_res = bg_exit;
 		},
 	}
 
+	if let Some(listen_addr) = &rpc_listen_addr {
+		rpc::remove_cookie(&ldk_data_dir);
+		#[cfg(unix)]
+		if let rpc::RpcListenAddr::Unix(path) = listen_addr {
+			let _ = fs::remove_file(path);
+		}
+	}
+
 	// Disconnect our peers and stop accepting new connections. This ensures we don't continue
 	// updating our channel data after we've stopped the background processor.
 	stop_listen_connect.store(true, Ordering::Release);
@@ -1076,7 +1613,6 @@ async fn start_ldk() {
 				&channel_manager.encode(),
 			)
 			.unwrap();
-		use lightning::util::logger::Logger;
 		lightning::log_error!(
 			&*logger,
 			"Last-ditch ChannelManager persistence result: {:?}",
@@ -1096,6 +1632,20 @@ async fn start_ldk() {
 	}
 }
 
+async fn wait_for_shutdown_signal() {
+	#[cfg(unix)]
+	{
+		use tokio::signal::unix::{signal, SignalKind};
+		let mut sigterm = signal(SignalKind::terminate()).unwrap();
+		tokio::select! {
+			_ = sigterm.recv() => {},
+			_ = tokio::signal::ctrl_c() => {},
+		}
+	}
+	#[cfg(not(unix))]
+	let _ = tokio::signal::ctrl_c().await;
+}
+
 #[tokio::main]
 pub async fn main() {
 	#[cfg(not(target_os = "windows"))]
@@ -1122,4 +1672,8 @@ pub async fn main() {
 	}
 
 	start_ldk().await;
+
+	// The CLI may still be blocked reading stdin if we were stopped some other way, so don't wait
+	// for it when the runtime shuts down.
+	std::process::exit(0);
 }
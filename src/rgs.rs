@@ -0,0 +1,187 @@
+//! Rapid Gossip Sync: bootstrapping the network graph from a snapshot served by a trusted source
+//! instead of learning it from peers over the course of hours.
+
+use crate::disk::FilesystemLogger;
+use crate::NetworkGraph;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+pub(crate) type RapidGossipSync =
+	lightning_rapid_gossip_sync::RapidGossipSync<Arc<NetworkGraph>, Arc<FilesystemLogger>>;
+
+/// How often we fetch a new delta from an HTTP source unless configured otherwise.
+pub(crate) const DEFAULT_SYNC_INTERVAL_SECS: u64 = 3600;
+
+/// Snapshots of the full mainnet graph are a few MB, anything much larger is not a snapshot.
+const MAX_SNAPSHOT_SIZE: usize = 64 * 1024 * 1024;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where we get RGS snapshots from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum RgsSource {
+	/// A snapshot file which is applied once on startup, e.g. for offline or regtest testing.
+	File(PathBuf),
+	/// An RGS server. Snapshots are fetched from `<path>/<last_sync_timestamp>`, so after the
+	/// initial full snapshot we only download deltas.
+	Http { host: String, port: u16, path: String },
+}
+
+impl RgsSource {
+	/// Parses an `http://host[:port][/path]` URL or a local file path.
+	pub(crate) fn parse(source: &str) -> Result<Self, String> {
+		if let Some(rest) = source.strip_prefix("http://") {
+			let (authority, path) = match rest.find('/') {
+				Some(idx) => (&rest[..idx], &rest[idx..]),
+				None => (rest, ""),
+			};
+			let (host, port) = match authority.rsplit_once(':') {
+				Some((host, port)) => {
+					let port = port.parse().map_err(|_| format!("invalid port in `{}`", source))?;
+					(host, port)
+				}
+				None => (authority, 80),
+			};
+			if host.is_empty() {
+				return Err(format!("missing host in `{}`", source));
+			}
+			let path = path.trim_end_matches('/').to_string();
+			Ok(RgsSource::Http { host: host.to_string(), port, path })
+		} else if source.contains("://") {
+			Err(format!(
+				"unsupported URL `{}`, only http:// URLs and file paths are supported",
+				source
+			))
+		} else {
+			Ok(RgsSource::File(PathBuf::from(source)))
+		}
+	}
+}
+
+/// Fetches a snapshot (or a delta since the last sync) and applies it to the network graph.
+pub(crate) async fn sync(rgs: &RapidGossipSync, source: &RgsSource) -> Result<(), ()> {
+	let snapshot = match source {
+		RgsSource::File(path) => match fs::read(path) {
+			Ok(snapshot) => snapshot,
+			Err(e) => {
+				println!("ERROR: failed to read RGS snapshot {}: {}", path.display(), e);
+				return Err(());
+			}
+		},
+		RgsSource::Http { host, port, path } => {
+			let last_sync_timestamp =
+				rgs.network_graph().get_last_rapid_gossip_sync_timestamp().unwrap_or(0);
+			let uri = format!("{}/{}", path, last_sync_timestamp);
+			match tokio::time::timeout(HTTP_TIMEOUT, http_get(host, *port, &uri)).await {
+				Ok(Ok(snapshot)) => snapshot,
+				Ok(Err(e)) => {
+					println!(
+						"ERROR: failed to fetch RGS snapshot from {}:{}{}: {}",
+						host, port, uri, e
+					);
+					return Err(());
+				}
+				Err(_) => {
+					println!(
+						"ERROR: timed out fetching RGS snapshot from {}:{}{}",
+						host, port, uri
+					);
+					return Err(());
+				}
+			}
+		}
+	};
+	match rgs.update_network_graph(&snapshot) {
+		Ok(_) => Ok(()),
+		Err(e) => {
+			println!("ERROR: failed to apply RGS snapshot: {:?}", e);
+			Err(())
+		}
+	}
+}
+
+/// Regularly applies the latest delta from an HTTP source. File sources are only read on startup.
+pub(crate) async fn periodic_sync(
+	rgs: Arc<RapidGossipSync>, source: RgsSource, interval: Duration,
+) {
+	if let RgsSource::File(_) = source {
+		return;
+	}
+	let mut interval = tokio::time::interval(interval);
+	interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+	// The first tick completes immediately, but we just synced on startup.
+	interval.tick().await;
+	loop {
+		interval.tick().await;
+		let _ = sync(&rgs, &source).await;
+	}
+}
+
+/// A minimal HTTP/1.0 `GET`, which is all RGS servers need. Using HTTP/1.0 means the response
+/// isn't chunked and the body simply ends when the server closes the connection.
+async fn http_get(host: &str, port: u16, uri: &str) -> std::io::Result<Vec<u8>> {
+	let mut stream = TcpStream::connect((host, port)).await?;
+	let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", uri, host);
+	stream.write_all(request.as_bytes()).await?;
+	let mut response = Vec::new();
+	stream.take(MAX_SNAPSHOT_SIZE as u64 + 1).read_to_end(&mut response).await?;
+	if response.len() > MAX_SNAPSHOT_SIZE {
+		return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "response too large"));
+	}
+	parse_http_response(response)
+}
+
+fn parse_http_response(mut response: Vec<u8>) -> std::io::Result<Vec<u8>> {
+	let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+	let header_end = match response.windows(4).position(|w| w == b"\r\n\r\n") {
+		Some(pos) => pos,
+		None => return Err(invalid("malformed HTTP response")),
+	};
+	let headers = String::from_utf8_lossy(&response[..header_end]).into_owned();
+	let status_line = headers.lines().next().unwrap_or("");
+	match status_line.split_whitespace().nth(1) {
+		Some("200") => {}
+		_ => return Err(invalid(&format!("unexpected HTTP status `{}`", status_line))),
+	}
+	Ok(response.split_off(header_end + 4))
+}
+
+#[cfg(test)]
+mod rgs_tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_rgs_source() {
+		assert_eq!(
+			RgsSource::parse("http://127.0.0.1:8080/snapshot/").unwrap(),
+			RgsSource::Http {
+				host: "127.0.0.1".to_string(),
+				port: 8080,
+				path: "/snapshot".to_string()
+			}
+		);
+		assert_eq!(
+			RgsSource::parse("http://rgs.local").unwrap(),
+			RgsSource::Http { host: "rgs.local".to_string(), port: 80, path: String::new() }
+		);
+		assert_eq!(
+			RgsSource::parse("/tmp/rgs_snapshot").unwrap(),
+			RgsSource::File(PathBuf::from("/tmp/rgs_snapshot"))
+		);
+		assert!(RgsSource::parse("https://rapidsync.lightningdevkit.org/snapshot").is_err());
+		assert!(RgsSource::parse("http://:80/snapshot").is_err());
+		assert!(RgsSource::parse("http://host:port/snapshot").is_err());
+	}
+
+	#[test]
+	fn test_parse_http_response() {
+		let response = b"HTTP/1.0 200 OK\r\nContent-Length: 3\r\n\r\n\x01\x02\x03".to_vec();
+		assert_eq!(parse_http_response(response).unwrap(), vec![1, 2, 3]);
+		let response = b"HTTP/1.1 404 Not Found\r\n\r\nnot found".to_vec();
+		assert!(parse_http_response(response).is_err());
+		assert!(parse_http_response(b"garbage".to_vec()).is_err());
+	}
+}
@@ -0,0 +1,347 @@
+//! A small JSON-RPC 2.0 control API, so the node can be driven without a terminal attached.
+//!
+//! Requests and responses are single-line JSON objects separated by newlines. Every connection
+//! must first call `auth` with the contents of the cookie file written to the `.ldk` directory on
+//! startup. After that, any command understood by the interactive CLI can be called by using the
+//! command name as the method and its arguments as the (positional) params, e.g.
+//! `{"jsonrpc":"2.0","id":1,"method":"getinvoice","params":[100000,3600]}`. The result is the text
+//! the command would have printed at the prompt. `stop` shuts the node down.
+
+use crate::cli::{self, NodeContext, OutputFormat};
+use crate::hex_utils;
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use rand::{thread_rng, Rng};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+
+pub(crate) const COOKIE_FNAME: &str = ".cookie";
+pub(crate) const DEFAULT_RPC_BIND: &str = "127.0.0.1:9736";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const INVALID_PARAMS: i64 = -32602;
+const UNAUTHORIZED: i64 = -32001;
+const COMMAND_FAILED: i64 = -32000;
+
+/// How long to pause accepting connections after it failed, e.g. because we ran out of file
+/// descriptors, rather than spinning on the error.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Where the control API listens for connections.
+pub(crate) enum RpcListenAddr {
+	Tcp(SocketAddr),
+	#[cfg(unix)]
+	Unix(PathBuf),
+}
+
+/// Writes a fresh random cookie to `<ldk_data_dir>/.cookie`, readable only by the current user,
+/// and returns it. Clients authenticate by presenting the cookie.
+pub(crate) fn write_cookie(ldk_data_dir: &str) -> Result<String, ()> {
+	let mut token = [0; 32];
+	thread_rng().fill_bytes(&mut token);
+	let cookie = hex_utils::hex_str(&token);
+
+	let cookie_path = format!("{}/{}", ldk_data_dir, COOKIE_FNAME);
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create(true).truncate(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	match options.open(&cookie_path).and_then(|mut f| f.write_all(cookie.as_bytes())) {
+		Ok(()) => Ok(cookie),
+		Err(e) => {
+			println!("ERROR: Unable to write RPC cookie file {}: {}", cookie_path, e);
+			Err(())
+		}
+	}
+}
+
+pub(crate) fn remove_cookie(ldk_data_dir: &str) {
+	let _ = fs::remove_file(format!("{}/{}", ldk_data_dir, COOKIE_FNAME));
+}
+
+/// Binds the control API and starts serving it in the background. `stop` is notified when a
+/// client calls `stop`.
+pub(crate) async fn start_server(
+	listen_addr: &RpcListenAddr, ctx: NodeContext, cookie: String, stop: Arc<Notify>,
+) -> Result<(), ()> {
+	let cookie = Arc::new(cookie);
+	match listen_addr {
+		RpcListenAddr::Tcp(addr) => {
+			let listener = match tokio::net::TcpListener::bind(addr).await {
+				Ok(listener) => listener,
+				Err(e) => {
+					println!("ERROR: Unable to bind the RPC server to {}: {}", addr, e);
+					return Err(());
+				}
+			};
+			tokio::spawn(async move {
+				loop {
+					match listener.accept().await {
+						Ok((stream, _)) => {
+							let (ctx, cookie, stop) =
+								(ctx.clone(), Arc::clone(&cookie), Arc::clone(&stop));
+							tokio::spawn(handle_connection(stream, ctx, cookie, stop));
+						}
+						Err(e) => {
+							log_error!(ctx.logger, "Failed to accept an RPC connection: {}", e);
+							tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+						}
+					}
+				}
+			});
+		}
+		#[cfg(unix)]
+		RpcListenAddr::Unix(path) => {
+			let listener = match bind_unix_socket(path) {
+				Ok(listener) => listener,
+				Err(e) => {
+					println!("ERROR: Unable to bind the RPC server to {}: {}", path.display(), e);
+					return Err(());
+				}
+			};
+			tokio::spawn(async move {
+				loop {
+					match listener.accept().await {
+						Ok((stream, _)) => {
+							let (ctx, cookie, stop) =
+								(ctx.clone(), Arc::clone(&cookie), Arc::clone(&stop));
+							tokio::spawn(handle_connection(stream, ctx, cookie, stop));
+						}
+						Err(e) => {
+							log_error!(ctx.logger, "Failed to accept an RPC connection: {}", e);
+							tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+						}
+					}
+				}
+			});
+		}
+	}
+	Ok(())
+}
+
+/// Binds a Unix socket at `path` which only the current user can connect to. The socket is bound
+/// inside a directory only we can enter and restricted before being moved to `path`, so other users
+/// can't connect in between.
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path) -> std::io::Result<tokio::net::UnixListener> {
+	use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+	let file_name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+	let bind_dir = path.with_file_name(format!(".{}.bind", file_name));
+	// Clean up after a previous run which didn't shut down cleanly.
+	let _ = fs::remove_file(path);
+	let _ = fs::remove_dir_all(&bind_dir);
+	fs::DirBuilder::new().mode(0o700).create(&bind_dir)?;
+	let bind_path = bind_dir.join("socket");
+	let res = tokio::net::UnixListener::bind(&bind_path).and_then(|listener| {
+		fs::set_permissions(&bind_path, fs::Permissions::from_mode(0o600))?;
+		fs::rename(&bind_path, path)?;
+		Ok(listener)
+	});
+	let _ = fs::remove_dir_all(&bind_dir);
+	res
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite>(
+	stream: S, ctx: NodeContext, cookie: Arc<String>, stop: Arc<Notify>,
+) {
+	// Each connection gets its own `setoutput` setting, independent of the prompt and other clients.
+	let ctx = NodeContext { output_format: Arc::new(Mutex::new(OutputFormat::Text)), ..ctx };
+	let (reader, mut writer) = tokio::io::split(stream);
+	let mut lines = BufReader::new(reader).lines();
+	let mut authenticated = false;
+	while let Ok(Some(line)) = lines.next_line().await {
+		if line.trim().is_empty() {
+			continue;
+		}
+		let mut close = false;
+		let response = match parse_request(&line) {
+			Err(response) => response,
+			Ok((id, method, params)) => {
+				if !authenticated {
+					if method == "auth" && check_auth(&params, &cookie) {
+						authenticated = true;
+						success_response(id, "authenticated".to_string())
+					} else {
+						close = true;
+						error_response(id, UNAUTHORIZED, "unauthorized".to_string())
+					}
+				} else if method == "stop" {
+					stop.notify_one();
+					success_response(id, "stopping".to_string())
+				} else {
+					match command_line(&method, &params) {
+						Ok(command) => run_command(id, &ctx, command).await,
+						Err(msg) => error_response(id, INVALID_PARAMS, msg),
+					}
+				}
+			}
+		};
+		let mut response = response.to_string();
+		response.push('\n');
+		if writer.write_all(response.as_bytes()).await.is_err() || close {
+			break;
+		}
+	}
+}
+
+async fn run_command(id: Value, ctx: &NodeContext, command: String) -> Value {
+	let ctx = ctx.clone();
+	let res = tokio::task::spawn_blocking(move || {
+		let mut out = Vec::new();
+		let res = cli::handle_command(&ctx, &command, &mut out, false);
+		(res, String::from_utf8_lossy(&out).into_owned())
+	})
+	.await;
+	match res {
+		Ok((Ok(()), output)) => success_response(id, output),
+		Ok((Err(()), output)) => error_response(id, COMMAND_FAILED, output.trim_end().to_string()),
+		Err(e) => error_response(id, COMMAND_FAILED, format!("command panicked: {}", e)),
+	}
+}
+
+/// Splits a request into its id, method and params, or returns the error response to send back.
+fn parse_request(line: &str) -> Result<(Value, String, Vec<Value>), Value> {
+	let request: Value = match serde_json::from_str(line) {
+		Ok(request) => request,
+		Err(e) => return Err(error_response(Value::Null, PARSE_ERROR, e.to_string())),
+	};
+	let id = request.get("id").cloned().unwrap_or(Value::Null);
+	let method = match request.get("method").and_then(|m| m.as_str()) {
+		Some(method) => method.to_string(),
+		None => {
+			return Err(error_response(id, INVALID_REQUEST, "missing method".to_string()));
+		}
+	};
+	let params = match request.get("params") {
+		None | Some(Value::Null) => Vec::new(),
+		Some(Value::Array(params)) => params.clone(),
+		Some(_) => {
+			return Err(error_response(id, INVALID_REQUEST, "params must be an array".to_string()));
+		}
+	};
+	Ok((id, method, params))
+}
+
+/// Turns a method and its params back into the line the user would have typed at the prompt.
+fn command_line(method: &str, params: &[Value]) -> Result<String, String> {
+	if method.is_empty() || method.contains(char::is_whitespace) {
+		return Err(format!("invalid method {:?}", method));
+	}
+	let mut line = method.to_string();
+	for param in params {
+		line.push(' ');
+		match param {
+			// Commands are split on whitespace, which would silently turn such a parameter into
+			// several.
+			Value::String(s) if s.is_empty() || s.contains(char::is_whitespace) => {
+				return Err(format!("invalid parameter {:?}", s))
+			}
+			Value::String(s) => line.push_str(s),
+			Value::Number(n) => line.push_str(&n.to_string()),
+			Value::Bool(b) => line.push_str(&b.to_string()),
+			_ => return Err(format!("unsupported parameter {}", param)),
+		}
+	}
+	Ok(line)
+}
+
+fn check_auth(params: &[Value], cookie: &str) -> bool {
+	match params {
+		[Value::String(provided)] => constant_time_eq(provided.as_bytes(), cookie.as_bytes()),
+		_ => false,
+	}
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	if a.len() != b.len() {
+		return false;
+	}
+	a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn success_response(id: Value, result: String) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn error_response(id: Value, code: i64, message: String) -> Value {
+	json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+#[cfg(test)]
+mod rpc_tests {
+	use super::*;
+
+	#[test]
+	fn test_command_line() {
+		let params = vec![json!("02aa@127.0.0.1:9735"), json!(10000), json!("--public")];
+		assert_eq!(
+			command_line("openchannel", &params).unwrap(),
+			"openchannel 02aa@127.0.0.1:9735 10000 --public"
+		);
+		assert_eq!(command_line("listchannels", &[]).unwrap(), "listchannels");
+		assert!(command_line("list channels", &[]).is_err());
+		assert!(command_line("getinvoice", &[json!([1, 2])]).is_err());
+		assert!(command_line("sendonchain", &[json!("bcrt1q... 1000")]).is_err());
+		assert!(command_line("getinvoice", &[json!("")]).is_err());
+	}
+
+	#[test]
+	fn test_parse_request() {
+		let (id, method, params) =
+			parse_request(r#"{"jsonrpc":"2.0","id":7,"method":"getinvoice","params":[1000,60]}"#)
+				.unwrap();
+		assert_eq!(id, json!(7));
+		assert_eq!(method, "getinvoice");
+		assert_eq!(params, vec![json!(1000), json!(60)]);
+
+		let (_, method, params) = parse_request(r#"{"id":"a","method":"nodeinfo"}"#).unwrap();
+		assert_eq!(method, "nodeinfo");
+		assert!(params.is_empty());
+
+		let err = parse_request("not json").unwrap_err();
+		assert_eq!(err["error"]["code"], json!(PARSE_ERROR));
+		let err = parse_request(r#"{"id":1,"params":[]}"#).unwrap_err();
+		assert_eq!(err["error"]["code"], json!(INVALID_REQUEST));
+		assert_eq!(err["id"], json!(1));
+	}
+
+	#[cfg(unix)]
+	#[tokio::test]
+	async fn test_bind_unix_socket() {
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = std::env::temp_dir().join(format!("ldk-rpc-socket-{}", std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("rpc.sock");
+		// A socket left behind by a previous run is replaced.
+		fs::write(&path, b"stale").unwrap();
+		let _listener = bind_unix_socket(&path).unwrap();
+		let mode = fs::metadata(&path).unwrap().permissions().mode();
+		assert_eq!(mode & 0o777, 0o600);
+		assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+		tokio::net::UnixStream::connect(&path).await.unwrap();
+		fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_check_auth() {
+		let cookie = "00ff".repeat(16);
+		assert!(check_auth(&[json!(cookie.clone())], &cookie));
+		assert!(!check_auth(&[json!("00ff")], &cookie));
+		assert!(!check_auth(&[json!("00fe".repeat(16))], &cookie));
+		assert!(!check_auth(&[], &cookie));
+		assert!(!check_auth(&[json!(cookie.clone()), json!(cookie.clone())], &cookie));
+	}
+}
@@ -0,0 +1,570 @@
+use bip39::Mnemonic;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use rand::{thread_rng, Rng};
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// The number of words in the mnemonics we generate, encoding 256 bits of entropy.
+const MNEMONIC_WORD_COUNT: usize = 24;
+/// The number of randomly picked words the user has to re-enter when verifying their backup.
+const MNEMONIC_VERIFY_WORDS: usize = 3;
+
+// Environment variable keys, allowing a mnemonic to be restored without an interactive prompt.
+const MNEMONIC_KEY: &str = "LDK_MNEMONIC";
+const MNEMONIC_PASSPHRASE_KEY: &str = "LDK_MNEMONIC_PASSPHRASE";
+// Environment variable key for the passphrase unlocking an encrypted keys seed.
+const SEED_PASSPHRASE_KEY: &str = "LDK_SEED_PASSPHRASE";
+
+/// Encrypted keys seed files start with this magic, followed by a version byte, the scrypt
+/// parameters (`log_n`, `r` and `p`), a random salt and nonce, and finally the ChaCha20Poly1305
+/// encrypted seed. Everything before the ciphertext is authenticated as associated data.
+///
+/// Plain 32-byte files are still read as unencrypted seeds.
+const ENCRYPTED_SEED_MAGIC: &[u8] = b"ldkseed";
+const ENCRYPTED_SEED_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = ENCRYPTED_SEED_MAGIC.len() + 1 + 1 + 4 + 4 + SALT_LEN + NONCE_LEN;
+const ENCRYPTED_SEED_LEN: usize = HEADER_LEN + 32 + 16;
+
+/// The scrypt cost parameters used when encrypting a seed. Decryption uses whatever parameters
+/// are stored in the file, up to these values.
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// How many times we prompt for the passphrase before giving up.
+const PASSPHRASE_ATTEMPTS: usize = 3;
+
+/// How the node's keys seed is created, protected and backed up.
+#[derive(Default)]
+pub(crate) struct SeedOptions {
+	/// Where to keep a copy of the keys seed file, if anywhere.
+	pub(crate) backup_path: Option<PathBuf>,
+	pub(crate) restore_from_mnemonic: bool,
+	pub(crate) verify_mnemonic: bool,
+	/// Whether the keys seed should be encrypted at rest. An existing plaintext seed is encrypted
+	/// on startup.
+	pub(crate) encrypt: bool,
+	/// A file descriptor to read the seed passphrase from, instead of the environment or a prompt.
+	pub(crate) passphrase_fd: Option<i32>,
+	/// Whether an existing keys seed must already be backed up at `backup_path`, rather than the
+	/// backup being written on startup. Set on mainnet, where a missing backup may mean it's on a
+	/// disk which isn't mounted.
+	pub(crate) require_existing_backup: bool,
+}
+
+/// Derives the 32-byte seed which is handed to the `KeysManager` from a BIP39 mnemonic and
+/// passphrase, by truncating the standard 64-byte BIP39 seed.
+pub(crate) fn keys_seed_from_mnemonic(mnemonic: &Mnemonic, passphrase: &str) -> [u8; 32] {
+	let mut keys_seed = [0; 32];
+	keys_seed.copy_from_slice(&mnemonic.to_seed(passphrase)[..32]);
+	keys_seed
+}
+
+/// Loads the node's key seed from `keys_seed_path`, unlocking it if it is encrypted. If there
+/// isn't one yet, it is either restored from a user-provided mnemonic or derived from a freshly
+/// generated mnemonic, which is printed exactly once and never written to disk.
+pub(crate) fn init_keys_seed(keys_seed_path: &Path, options: &SeedOptions) -> Result<[u8; 32], ()> {
+	let mut created = true;
+	let keys_seed = if options.restore_from_mnemonic {
+		if keys_seed_path.exists() {
+			println!(
+				"ERROR: refusing to restore from a mnemonic as a keys seed already exists at {}. Move it out of the way first.",
+				keys_seed_path.display()
+			);
+			return Err(());
+		}
+		let keys_seed = restore_keys_seed()?;
+		write_new_keys_seed(keys_seed_path, &keys_seed, options)?;
+		println!("Restored the node keys seed from the provided mnemonic.");
+		keys_seed
+	} else if keys_seed_path.exists() {
+		created = false;
+		let contents = read_file(keys_seed_path)?;
+		if is_encrypted(&contents) {
+			unlock_keys_seed(&contents, options.passphrase_fd)?
+		} else {
+			let keys_seed = parse_plaintext_keys_seed(keys_seed_path, &contents)?;
+			if options.encrypt {
+				println!("Encrypting the existing plaintext keys seed.");
+				let passphrase = new_passphrase(options.passphrase_fd)?;
+				let encrypted = encrypt_keys_seed(&keys_seed, &passphrase, SCRYPT_LOG_N);
+				replace_keys_seed(keys_seed_path, options.backup_path.as_deref(), &encrypted)?;
+				println!(
+					"WARNING: any other copies of the plaintext keys seed should be destroyed."
+				);
+			}
+			keys_seed
+		}
+	} else {
+		let keys_seed = generate_keys_seed(options.verify_mnemonic)?;
+		write_new_keys_seed(keys_seed_path, &keys_seed, options)?;
+		keys_seed
+	};
+	if let Some(backup_path) = &options.backup_path {
+		let may_write_backup = created || !options.require_existing_backup;
+		check_keys_seed_backup(keys_seed_path, backup_path, may_write_backup)?;
+	}
+	Ok(keys_seed)
+}
+
+/// Re-encrypts the keys seed at `keys_seed_path` under a new, interactively prompted passphrase.
+/// A plaintext seed is encrypted for the first time. The backup is updated too if it matched.
+pub(crate) fn change_passphrase(
+	keys_seed_path: &Path, backup_path: Option<&Path>,
+) -> Result<(), ()> {
+	let contents = read_file(keys_seed_path)?;
+	let keys_seed = if is_encrypted(&contents) {
+		let passphrase = read_passphrase("Enter the current keys seed passphrase: ")?;
+		decrypt_keys_seed(&contents, &passphrase).map_err(|_| {
+			println!("ERROR: wrong passphrase");
+		})?
+	} else {
+		parse_plaintext_keys_seed(keys_seed_path, &contents)?
+	};
+	let passphrase = prompt_new_passphrase()?;
+	let encrypted = encrypt_keys_seed(&keys_seed, &passphrase, SCRYPT_LOG_N);
+	replace_keys_seed(keys_seed_path, backup_path, &encrypted)
+}
+
+/// Keeps a copy of the keys seed file at `backup_path`, and makes sure the copy there actually
+/// matches the seed we're about to use. Unless `may_write_backup`, a missing copy is an error.
+fn check_keys_seed_backup(
+	keys_seed_path: &Path, backup_path: &Path, may_write_backup: bool,
+) -> Result<(), ()> {
+	let contents = read_file(keys_seed_path)?;
+	if !backup_path.exists() {
+		if !may_write_backup {
+			println!(
+				"ERROR: There is no keys seed backup at {}, refusing to start. Copy {} there first.",
+				backup_path.display(),
+				keys_seed_path.display()
+			);
+			return Err(());
+		}
+		write_file(backup_path, &contents)?;
+		println!("Wrote a backup of the node keys seed to {}", backup_path.display());
+	}
+	if fs::read(backup_path).ok() != Some(contents) {
+		println!(
+			"ERROR: The keys seed backup at {} doesn't match {}, refusing to start",
+			backup_path.display(),
+			keys_seed_path.display()
+		);
+		return Err(());
+	}
+	Ok(())
+}
+
+fn write_new_keys_seed(
+	keys_seed_path: &Path, keys_seed: &[u8; 32], options: &SeedOptions,
+) -> Result<(), ()> {
+	if options.encrypt {
+		let passphrase = new_passphrase(options.passphrase_fd)?;
+		write_file(keys_seed_path, &encrypt_keys_seed(keys_seed, &passphrase, SCRYPT_LOG_N))
+	} else {
+		write_file(keys_seed_path, keys_seed)
+	}
+}
+
+/// Overwrites the keys seed file, updating the backup as well if it held the previous contents.
+fn replace_keys_seed(
+	keys_seed_path: &Path, backup_path: Option<&Path>, contents: &[u8],
+) -> Result<(), ()> {
+	let previous = read_file(keys_seed_path)?;
+	write_file(keys_seed_path, contents)?;
+	if let Some(backup_path) = backup_path {
+		if fs::read(backup_path).ok() == Some(previous) {
+			write_file(backup_path, contents)?;
+			println!("Updated the keys seed backup at {}", backup_path.display());
+		} else {
+			println!(
+				"WARNING: the keys seed backup at {} didn't match, remember to update it",
+				backup_path.display()
+			);
+		}
+	}
+	Ok(())
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, ()> {
+	fs::read(path).map_err(|e| {
+		println!("ERROR: Unable to read keys seed file {}: {}", path.display(), e);
+	})
+}
+
+/// Writes `contents` to a temporary file first and renames it into place, so that a crash can
+/// never leave us with a truncated seed. The file is only readable by its owner.
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), ()> {
+	let mut tmp_path = path.as_os_str().to_owned();
+	tmp_path.push(".tmp");
+	let tmp_path = PathBuf::from(tmp_path);
+	// A file left over from a crash may have been created with other permissions, so we always
+	// create a new one.
+	let res = match fs::remove_file(&tmp_path) {
+		Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+		_ => Ok(()),
+	};
+	let mut options = fs::OpenOptions::new();
+	options.write(true).create_new(true);
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::OpenOptionsExt;
+		options.mode(0o600);
+	}
+	let res = res.and_then(|_| options.open(&tmp_path)).and_then(|mut f| {
+		f.write_all(contents)?;
+		f.sync_all()
+	});
+	match res.and_then(|_| fs::rename(&tmp_path, path)) {
+		Ok(()) => Ok(()),
+		Err(e) => {
+			println!("ERROR: Unable to write keys seed file {}: {}", path.display(), e);
+			Err(())
+		}
+	}
+}
+
+fn parse_plaintext_keys_seed(keys_seed_path: &Path, contents: &[u8]) -> Result<[u8; 32], ()> {
+	if contents.len() != 32 {
+		println!("ERROR: keys seed file {} is not 32 bytes long", keys_seed_path.display());
+		return Err(());
+	}
+	let mut key = [0; 32];
+	key.copy_from_slice(contents);
+	Ok(key)
+}
+
+fn is_encrypted(contents: &[u8]) -> bool {
+	contents.starts_with(ENCRYPTED_SEED_MAGIC)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], log_n: u8, r: u32, p: u32) -> Result<[u8; 32], ()> {
+	let params = scrypt::Params::new(log_n, r, p, 32).map_err(|_| ())?;
+	let mut key = [0; 32];
+	scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key).map_err(|_| ())?;
+	Ok(key)
+}
+
+fn encrypt_keys_seed(keys_seed: &[u8; 32], passphrase: &str, log_n: u8) -> Vec<u8> {
+	let mut salt = [0; SALT_LEN];
+	thread_rng().fill_bytes(&mut salt);
+	let mut nonce = [0; NONCE_LEN];
+	thread_rng().fill_bytes(&mut nonce);
+
+	let mut header = Vec::with_capacity(ENCRYPTED_SEED_LEN);
+	header.extend_from_slice(ENCRYPTED_SEED_MAGIC);
+	header.push(ENCRYPTED_SEED_VERSION);
+	header.push(log_n);
+	header.extend_from_slice(&SCRYPT_R.to_be_bytes());
+	header.extend_from_slice(&SCRYPT_P.to_be_bytes());
+	header.extend_from_slice(&salt);
+	header.extend_from_slice(&nonce);
+
+	let key = derive_key(passphrase, &salt, log_n, SCRYPT_R, SCRYPT_P).expect("Valid parameters");
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+	let ciphertext = cipher
+		.encrypt(Nonce::from_slice(&nonce), Payload { msg: keys_seed, aad: &header })
+		.expect("Encrypting 32 bytes can't fail");
+	header.extend_from_slice(&ciphertext);
+	header
+}
+
+/// Decrypts an encrypted keys seed file. Fails on a wrong passphrase as well as on any corruption
+/// of the file.
+fn decrypt_keys_seed(contents: &[u8], passphrase: &str) -> Result<[u8; 32], ()> {
+	if contents.len() != ENCRYPTED_SEED_LEN || !is_encrypted(contents) {
+		return Err(());
+	}
+	let (header, ciphertext) = contents.split_at(HEADER_LEN);
+	let params = &header[ENCRYPTED_SEED_MAGIC.len()..];
+	if params[0] != ENCRYPTED_SEED_VERSION {
+		return Err(());
+	}
+	let log_n = params[1];
+	let mut r = [0; 4];
+	r.copy_from_slice(&params[2..6]);
+	let mut p = [0; 4];
+	p.copy_from_slice(&params[6..10]);
+	let salt = &params[10..10 + SALT_LEN];
+	let nonce = &params[10 + SALT_LEN..];
+
+	let (r, p) = (u32::from_be_bytes(r), u32::from_be_bytes(p));
+	// Don't let a corrupted header make us burn unbounded time and memory.
+	if log_n > SCRYPT_LOG_N || r > SCRYPT_R || p > SCRYPT_P {
+		return Err(());
+	}
+
+	let key = derive_key(passphrase, salt, log_n, r, p)?;
+	let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+	let keys_seed = cipher
+		.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+		.map_err(|_| ())?;
+	let mut key = [0; 32];
+	key.copy_from_slice(&keys_seed);
+	Ok(key)
+}
+
+/// Unlocks an encrypted keys seed with a passphrase from the given file descriptor, the
+/// environment, or an interactive prompt, in that order of preference.
+fn unlock_keys_seed(contents: &[u8], passphrase_fd: Option<i32>) -> Result<[u8; 32], ()> {
+	let wrong_passphrase = |()| println!("ERROR: wrong keys seed passphrase or corrupted file");
+	if let Some(fd) = passphrase_fd {
+		let passphrase = read_passphrase_from_fd(fd)?;
+		return decrypt_keys_seed(contents, &passphrase).map_err(wrong_passphrase);
+	}
+	if let Ok(passphrase) = env::var(SEED_PASSPHRASE_KEY) {
+		return decrypt_keys_seed(contents, &passphrase).map_err(wrong_passphrase);
+	}
+	for _ in 0..PASSPHRASE_ATTEMPTS {
+		let passphrase = read_passphrase("Enter the keys seed passphrase: ")?;
+		match decrypt_keys_seed(contents, &passphrase) {
+			Ok(keys_seed) => return Ok(keys_seed),
+			Err(()) => wrong_passphrase(()),
+		}
+	}
+	Err(())
+}
+
+/// Gets the passphrase to encrypt a new seed with, prompting twice if it isn't provided via a file
+/// descriptor or the environment.
+fn new_passphrase(passphrase_fd: Option<i32>) -> Result<String, ()> {
+	let passphrase = match passphrase_fd {
+		Some(fd) => read_passphrase_from_fd(fd)?,
+		None => match env::var(SEED_PASSPHRASE_KEY) {
+			Ok(passphrase) => passphrase,
+			Err(_) => return prompt_new_passphrase(),
+		},
+	};
+	if passphrase.is_empty() {
+		println!("ERROR: the keys seed passphrase must not be empty");
+		return Err(());
+	}
+	Ok(passphrase)
+}
+
+fn prompt_new_passphrase() -> Result<String, ()> {
+	loop {
+		let passphrase = read_passphrase("Enter a new keys seed passphrase: ")?;
+		if passphrase.is_empty() {
+			println!("ERROR: the keys seed passphrase must not be empty");
+			continue;
+		}
+		if read_passphrase("Repeat the new keys seed passphrase: ")? == passphrase {
+			return Ok(passphrase);
+		}
+		println!("ERROR: the passphrases didn't match");
+	}
+}
+
+#[cfg(not(target_os = "windows"))]
+fn read_passphrase_from_fd(fd: i32) -> Result<String, ()> {
+	use std::os::unix::io::FromRawFd;
+	// Safety: the user explicitly handed us this file descriptor to read the passphrase from, and
+	// we only ever read it once.
+	let mut file = unsafe { File::from_raw_fd(fd) };
+	let mut passphrase = String::new();
+	file.read_to_string(&mut passphrase).map_err(|e| {
+		println!("ERROR: unable to read the keys seed passphrase from fd {}: {}", fd, e);
+	})?;
+	Ok(passphrase.trim_end_matches(['\n', '\r']).to_string())
+}
+
+#[cfg(target_os = "windows")]
+fn read_passphrase_from_fd(_fd: i32) -> Result<String, ()> {
+	println!("ERROR: reading the keys seed passphrase from a file descriptor is not supported on Windows");
+	Err(())
+}
+
+/// Prompts for a passphrase, hiding the input if stdin is a terminal.
+fn read_passphrase(message: &str) -> Result<String, ()> {
+	#[cfg(not(target_os = "windows"))]
+	unsafe {
+		let mut termios: libc::termios = core::mem::zeroed();
+		if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) == 0 {
+			let mut echo_off = termios;
+			echo_off.c_lflag &= !libc::ECHO;
+			libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &echo_off);
+			let res = prompt(message);
+			libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+			println!();
+			return res;
+		}
+	}
+	prompt(message)
+}
+
+fn generate_keys_seed(verify_mnemonic: bool) -> Result<[u8; 32], ()> {
+	let mut entropy = [0; MNEMONIC_WORD_COUNT / 3 * 4];
+	thread_rng().fill_bytes(&mut entropy);
+	let mnemonic = Mnemonic::from_entropy(&entropy).expect("Valid entropy length");
+	let passphrase = env::var(MNEMONIC_PASSPHRASE_KEY).unwrap_or_default();
+
+	println!("Generated a new node keys seed. Write down the following words, in order, and store them safely. They are the only way to recover this node's funds and will NOT be shown again:");
+	for (idx, word) in mnemonic.words().enumerate() {
+		println!("{:>4}. {}", idx + 1, word);
+	}
+	if !passphrase.is_empty() {
+		println!(
+			"The mnemonic is protected by the passphrase from {}, which is needed to restore as well.",
+			MNEMONIC_PASSPHRASE_KEY
+		);
+	}
+
+	if verify_mnemonic {
+		let words: Vec<&str> = mnemonic.words().collect();
+		let mut verified = 0;
+		while verified < MNEMONIC_VERIFY_WORDS {
+			let idx = thread_rng().gen_range(0, words.len());
+			let word = prompt(&format!("To verify your backup, enter word #{}: ", idx + 1))?;
+			if word.to_lowercase() == words[idx] {
+				verified += 1;
+			} else {
+				println!("ERROR: word #{} doesn't match, please check your backup", idx + 1);
+			}
+		}
+		println!("Mnemonic backup verified.");
+	}
+
+	Ok(keys_seed_from_mnemonic(&mnemonic, &passphrase))
+}
+
+fn restore_keys_seed() -> Result<[u8; 32], ()> {
+	let words = match env::var(MNEMONIC_KEY) {
+		Ok(words) => words,
+		Err(_) => prompt("Enter the mnemonic words, separated by spaces: ")?,
+	};
+	let mnemonic = Mnemonic::parse(words.to_lowercase()).map_err(|e| {
+		println!("ERROR: invalid mnemonic: {}", e);
+	})?;
+	let passphrase = match env::var(MNEMONIC_PASSPHRASE_KEY) {
+		Ok(passphrase) => passphrase,
+		Err(_) => prompt("Enter the mnemonic passphrase (leave empty if none): ")?,
+	};
+	Ok(keys_seed_from_mnemonic(&mnemonic, &passphrase))
+}
+
+fn prompt(message: &str) -> Result<String, ()> {
+	print!("{}", message);
+	io::stdout().flush().unwrap();
+	let mut line = String::new();
+	match io::stdin().read_line(&mut line) {
+		// We hit EOF / Ctrl-D
+		Ok(0) => Err(()),
+		Ok(_) => Ok(line.trim().to_string()),
+		Err(e) => {
+			println!("ERROR: {}", e);
+			Err(())
+		}
+	}
+}
+
+#[cfg(test)]
+mod seed_tests {
+	use super::*;
+	use crate::hex_utils;
+
+	#[test]
+	fn test_keys_seed_from_mnemonic() {
+		// Test vector from BIP39, using the "TREZOR" passphrase.
+		let mnemonic = Mnemonic::parse(
+			"abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+		)
+		.unwrap();
+		let keys_seed = keys_seed_from_mnemonic(&mnemonic, "TREZOR");
+		assert_eq!(
+			hex_utils::hex_str(&keys_seed),
+			"c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e5349553"
+		);
+		assert_ne!(keys_seed_from_mnemonic(&mnemonic, ""), keys_seed);
+	}
+
+	#[test]
+	fn test_keys_seed_roundtrip() {
+		let keys_seed_path = env::temp_dir().join("ldk_sample_test_keys_seed");
+		let _ = fs::remove_file(&keys_seed_path);
+		let keys_seed = [42; 32];
+		write_file(&keys_seed_path, &keys_seed).unwrap();
+		#[cfg(unix)]
+		{
+			use std::os::unix::fs::PermissionsExt;
+			let mode = fs::metadata(&keys_seed_path).unwrap().permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+		assert_eq!(init_keys_seed(&keys_seed_path, &SeedOptions::default()).unwrap(), keys_seed);
+		// Restoring on top of an existing seed is refused.
+		let restore = SeedOptions { restore_from_mnemonic: true, ..SeedOptions::default() };
+		assert!(init_keys_seed(&keys_seed_path, &restore).is_err());
+
+		fs::write(&keys_seed_path, [42; 31]).unwrap();
+		assert!(init_keys_seed(&keys_seed_path, &SeedOptions::default()).is_err());
+		fs::remove_file(&keys_seed_path).unwrap();
+	}
+
+	#[test]
+	fn test_encrypt_decrypt_keys_seed() {
+		let keys_seed = [42; 32];
+		let encrypted = encrypt_keys_seed(&keys_seed, "hunter2", 4);
+		assert_eq!(encrypted.len(), ENCRYPTED_SEED_LEN);
+		assert!(is_encrypted(&encrypted));
+		assert_eq!(decrypt_keys_seed(&encrypted, "hunter2").unwrap(), keys_seed);
+		assert!(decrypt_keys_seed(&encrypted, "hunter3").is_err());
+
+		// Salts and nonces are random, so encrypting twice gives different results.
+		assert_ne!(encrypt_keys_seed(&keys_seed, "hunter2", 4), encrypted);
+
+		// Any modification of the header or the ciphertext is detected.
+		for idx in ENCRYPTED_SEED_MAGIC.len()..ENCRYPTED_SEED_LEN {
+			let mut tampered = encrypted.clone();
+			tampered[idx] ^= 1;
+			assert!(decrypt_keys_seed(&tampered, "hunter2").is_err());
+		}
+		assert!(decrypt_keys_seed(&encrypted[..ENCRYPTED_SEED_LEN - 1], "hunter2").is_err());
+	}
+
+	#[test]
+	fn test_unlock_and_backup_keys_seed() {
+		let keys_seed_path = env::temp_dir().join("ldk_sample_test_encrypted_keys_seed");
+		let backup_path = env::temp_dir().join("ldk_sample_test_encrypted_keys_seed_backup");
+		let _ = fs::remove_file(&backup_path);
+		let keys_seed = [7; 32];
+		write_file(&keys_seed_path, &encrypt_keys_seed(&keys_seed, "hunter2", 4)).unwrap();
+
+		env::set_var(SEED_PASSPHRASE_KEY, "hunter2");
+		let options =
+			SeedOptions { backup_path: Some(backup_path.clone()), ..SeedOptions::default() };
+		assert_eq!(init_keys_seed(&keys_seed_path, &options).unwrap(), keys_seed);
+		assert_eq!(fs::read(&backup_path).unwrap(), fs::read(&keys_seed_path).unwrap());
+
+		// Replacing the seed file updates a matching backup.
+		let reencrypted = encrypt_keys_seed(&keys_seed, "hunter3", 4);
+		replace_keys_seed(&keys_seed_path, Some(&backup_path), &reencrypted).unwrap();
+		assert_eq!(fs::read(&backup_path).unwrap(), reencrypted);
+
+		// The passphrase from the environment no longer unlocks the seed.
+		assert!(init_keys_seed(&keys_seed_path, &options).is_err());
+		env::set_var(SEED_PASSPHRASE_KEY, "hunter3");
+		assert_eq!(init_keys_seed(&keys_seed_path, &options).unwrap(), keys_seed);
+
+		// A backup which doesn't match the seed in use is refused.
+		fs::write(&backup_path, [0; 32]).unwrap();
+		assert!(init_keys_seed(&keys_seed_path, &options).is_err());
+
+		// As is a missing backup of an existing seed, when it has to exist already.
+		fs::remove_file(&backup_path).unwrap();
+		let mainnet_options = SeedOptions { require_existing_backup: true, ..options };
+		assert!(init_keys_seed(&keys_seed_path, &mainnet_options).is_err());
+		assert!(!backup_path.exists());
+		fs::copy(&keys_seed_path, &backup_path).unwrap();
+		assert_eq!(init_keys_seed(&keys_seed_path, &mainnet_options).unwrap(), keys_seed);
+
+		fs::remove_file(&keys_seed_path).unwrap();
+		fs::remove_file(&backup_path).unwrap();
+	}
+}
@@ -0,0 +1,179 @@
+//! A [`FeeEstimator`] which never asks the chain source, for regtest and deterministic testing.
+//! Feerates come from the `fee.<target>.static` (or, failing that, `fee.<target>.fallback`)
+//! options, optionally overridden by a JSON file which is re-read whenever it changes, e.g.
+//! `{"on-chain-sweep": 5000, "non-anchor-channel-fee": 1000}` (in sat/KW).
+
+use crate::bitcoind_client::MIN_FEERATE;
+use crate::disk::FilesystemLogger;
+use crate::fee_policy::{FeePolicy, CONFIRMATION_TARGETS};
+use lightning::chain::chaininterface::{ConfirmationTarget, FeeEstimator};
+use lightning::log_error;
+use lightning::util::logger::Logger;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often we check whether the estimates file changed, as LDK asks for feerates often.
+const ESTIMATES_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct EstimatesFile {
+	/// When the file was last modified as of our last read of it.
+	modified: Option<SystemTime>,
+	/// When we last checked whether the file changed.
+	checked: Option<Instant>,
+	feerates: HashMap<ConfirmationTarget, u32>,
+}
+
+/// Provides fixed feerates from our config and, if set, an estimates file.
+pub(crate) struct StaticFeeEstimator {
+	fee_policy: Arc<FeePolicy>,
+	estimates_file: Option<PathBuf>,
+	state: Mutex<EstimatesFile>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl StaticFeeEstimator {
+	/// Fails if the estimates file is set but can't be read, so typos are caught on startup.
+	pub(crate) fn new(
+		fee_policy: Arc<FeePolicy>, estimates_file: Option<PathBuf>, logger: Arc<FilesystemLogger>,
+	) -> Result<Self, String> {
+		let mut state = EstimatesFile::default();
+		if let Some(path) = &estimates_file {
+			state = read_estimates_file(path)?;
+		}
+		Ok(Self { fee_policy, estimates_file, state: Mutex::new(state), logger })
+	}
+}
+
+fn read_estimates_file(path: &Path) -> Result<EstimatesFile, String> {
+	let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+	let contents = fs::read_to_string(path)
+		.map_err(|e| format!("unable to read fee estimates file {}: {}", path.display(), e))?;
+	let feerates = parse_estimates(&contents)
+		.map_err(|e| format!("in fee estimates file {}: {}", path.display(), e))?;
+	Ok(EstimatesFile { modified, checked: Some(Instant::now()), feerates })
+}
+
+/// Parses a JSON object of feerates (in sat/KW) keyed by confirmation target name.
+fn parse_estimates(contents: &str) -> Result<HashMap<ConfirmationTarget, u32>, String> {
+	let json: serde_json::Value = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+	let object = json.as_object().ok_or("expected a JSON object")?;
+	let mut feerates = HashMap::new();
+	for (name, value) in object {
+		let target = match CONFIRMATION_TARGETS.iter().find(|(_, n)| n == name) {
+			Some((target, _)) => *target,
+			None => return Err(format!("unknown confirmation target `{}`", name)),
+		};
+		match value.as_u64() {
+			Some(feerate) if feerate >= MIN_FEERATE as u64 && feerate <= u32::MAX as u64 => {
+				feerates.insert(target, feerate as u32);
+			}
+			_ => {
+				return Err(format!(
+					"expected a feerate of at least {} sat/KW for `{}`",
+					MIN_FEERATE, name
+				))
+			}
+		}
+	}
+	Ok(feerates)
+}
+
+impl FeeEstimator for StaticFeeEstimator {
+	fn get_est_sat_per_1000_weight(&self, confirmation_target: ConfirmationTarget) -> u32 {
+		let mut state = self.state.lock().unwrap();
+		let check_due = match state.checked {
+			Some(checked) => checked.elapsed() >= ESTIMATES_FILE_CHECK_INTERVAL,
+			None => true,
+		};
+		if let (Some(path), true) = (&self.estimates_file, check_due) {
+			state.checked = Some(Instant::now());
+			let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+			if modified != state.modified {
+				match read_estimates_file(path) {
+					Ok(estimates) => *state = estimates,
+					Err(e) => {
+						// Keep the previous feerates, and don't complain again until it changes.
+						log_error!(self.logger, "Failed to reload fee estimates: {}", e);
+						state.modified = modified;
+					}
+				}
+			}
+		}
+		match state.feerates.get(&confirmation_target) {
+			Some(feerate) => *feerate,
+			None => self.fee_policy.get(&confirmation_target).feerate(None),
+		}
+	}
+}
+
+#[cfg(test)]
+mod static_fees_tests {
+	use super::*;
+
+	const TEST_ESTIMATES_FILE: &str = "test_data/fee_estimates.json";
+
+	#[test]
+	fn test_parse_estimates() {
+		let contents = fs::read_to_string(TEST_ESTIMATES_FILE).unwrap();
+		let feerates = parse_estimates(&contents).unwrap();
+		assert_eq!(feerates[&ConfirmationTarget::OnChainSweep], 7500);
+		assert_eq!(feerates[&ConfirmationTarget::NonAnchorChannelFee], 1250);
+		assert!(!feerates.contains_key(&ConfirmationTarget::AnchorChannelFee));
+
+		assert!(parse_estimates("[]").is_err());
+		assert!(parse_estimates(r#"{"on-chain-sweep": 100}"#).is_err());
+		assert!(parse_estimates(r#"{"on-chain-sweep": "5000"}"#).is_err());
+		assert!(parse_estimates(r#"{"fast": 5000}"#).is_err());
+	}
+
+	#[test]
+	fn test_static_fee_estimator() {
+		let dir = std::env::temp_dir().join(format!("ldk-static-fees-{}", std::process::id()));
+		let logger = Arc::new(FilesystemLogger::new(dir.to_str().unwrap().to_string()));
+		let path = dir.join("fee_estimates.json");
+		fs::write(&path, r#"{"on-chain-sweep": 6000}"#).unwrap();
+		let fee_policy = Arc::new(
+			FeePolicy::from_settings(vec![("anchor-channel-fee", "static", "400")]).unwrap(),
+		);
+		let estimator =
+			StaticFeeEstimator::new(fee_policy, Some(path.clone()), Arc::clone(&logger)).unwrap();
+		assert_eq!(estimator.get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep), 6000);
+		assert_eq!(
+			estimator.get_est_sat_per_1000_weight(ConfirmationTarget::AnchorChannelFee),
+			400
+		);
+		assert_eq!(
+			estimator.get_est_sat_per_1000_weight(ConfirmationTarget::NonAnchorChannelFee),
+			2000
+		);
+
+		// Edits are picked up once we check the file again, while invalid ones are ignored.
+		fs::write(&path, r#"{"on-chain-sweep": 9000, "anchor-channel-fee": 500}"#).unwrap();
+		let later = SystemTime::now() + std::time::Duration::from_secs(10);
+		fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+		assert_eq!(estimator.get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep), 6000);
+		estimator.state.lock().unwrap().checked = None;
+		assert_eq!(estimator.get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep), 9000);
+		assert_eq!(
+			estimator.get_est_sat_per_1000_weight(ConfirmationTarget::AnchorChannelFee),
+			500
+		);
+		fs::write(&path, "not json").unwrap();
+		let later = later + std::time::Duration::from_secs(10);
+		fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+		estimator.state.lock().unwrap().checked = None;
+		assert_eq!(estimator.get_est_sat_per_1000_weight(ConfirmationTarget::OnChainSweep), 9000);
+
+		assert!(StaticFeeEstimator::new(
+			Arc::new(FeePolicy::default()),
+			Some(dir.join("nonexistent_file")),
+			logger
+		)
+		.is_err());
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
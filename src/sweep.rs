@@ -4,7 +4,7 @@ use std::sync::Arc;
 use std::time::Duration;
 use std::{fs, io};
 
-use lightning::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator};
+use lightning::chain::chaininterface::ConfirmationTarget;
 use lightning::sign::{EntropySource, KeysManager, SpendableOutputDescriptor};
 use lightning::util::logger::Logger;
 use lightning::util::persist::KVStore;
@@ -17,9 +17,10 @@ use bitcoin::secp256k1::Secp256k1;
 use rand::{thread_rng, Rng};
 
 use crate::hex_utils;
-use crate::BitcoindClient;
+use crate::wallet::WalletBackend;
 use crate::ChannelManager;
 use crate::FilesystemLogger;
+use crate::{DynBroadcaster, DynFeeEstimator};
 
 /// If we have any pending claimable outputs, we should slowly sweep them to our Bitcoin Core
 /// wallet. We technically don't need to do this - they're ours to spend when we want and can just
@@ -31,7 +32,8 @@ use crate::FilesystemLogger;
 /// we don't do that here either.
 pub(crate) async fn periodic_sweep(
 	ldk_data_dir: String, keys_manager: Arc<KeysManager>, logger: Arc<FilesystemLogger>,
-	persister: Arc<FilesystemStore>, bitcoind_client: Arc<BitcoindClient>,
+	persister: Arc<FilesystemStore>, wallet: Arc<WalletBackend>,
+	fee_estimator: Arc<DynFeeEstimator>, broadcaster: Arc<DynBroadcaster>,
 	channel_manager: Arc<ChannelManager>,
 ) {
 	// Regularly claim outputs which are exclusively spendable by us and send them to Bitcoin Core.
@@ -107,9 +109,19 @@ pub(crate) async fn periodic_sweep(
 					}
 					outputs.push(Readable::read(&mut file).unwrap());
 				}
-				let destination_address = bitcoind_client.get_new_address().await;
+				let destination_address = match wallet.get_new_address().await {
+					Ok(address) => address,
+					Err(e) => {
+						lightning::log_error!(
+							logger,
+							"Failed to get an address to sweep spendable outputs to: {}. Will try again in a day.",
+							e
+						);
+						continue;
+					}
+				};
 				let output_descriptors = &outputs.iter().map(|a| a).collect::<Vec<_>>();
-				let tx_feerate = bitcoind_client
+				let tx_feerate = fee_estimator
 					.get_est_sat_per_1000_weight(ConfirmationTarget::ChannelCloseMinimum);
 
 				// We set nLockTime to the current height to discourage fee sniping.
@@ -139,7 +151,7 @@ pub(crate) async fn periodic_sweep(
 				) {
 					// Note that, most likely, we've already sweeped this set of outputs
 					// and they're already confirmed on-chain, so this broadcast will fail.
-					bitcoind_client.broadcast_transactions(&[&spending_tx]);
+					broadcaster.broadcast_transactions(&[&spending_tx]);
 				} else {
 					lightning::log_error!(
 						logger,
@@ -0,0 +1,1418 @@
+//! A minimal on-chain wallet derived from our `keys_seed`, so we don't need a bitcoind wallet to
+//! fund channels, sweep to or bump anchor transactions with.
+//!
+//! Coins are held in BIP 84 (P2WPKH) addresses derived at `m/84'/<coin>'/0'/<change>/<index>`,
+//! which `KeysManager` never uses. With bitcoind as the chain source, the wallet learns about them
+//! by scanning every block we sync, starting from the block it was created at, so funds received
+//! before then (e.g. when restoring a seed) are not found. Esplora and Electrum servers index
+//! transactions by script instead, so with them the wallet fetches the whole history of its scripts.
+
+use crate::bitcoind_client::BitcoindClient;
+use crate::convert::RawTx;
+use crate::disk::FilesystemLogger;
+use crate::hex_utils;
+use crate::DynBroadcaster;
+use bitcoin::absolute::LockTime;
+use bitcoin::address::Address;
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, ExtendedPubKey};
+use bitcoin::blockdata::block::Header;
+use bitcoin::blockdata::script::ScriptBuf;
+use bitcoin::blockdata::transaction::{Sequence, Transaction, TxIn, TxOut};
+use bitcoin::consensus::encode;
+use bitcoin::hash_types::{BlockHash, Txid};
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::{self, All, Message, Secp256k1, SecretKey};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{Network, OutPoint, PublicKey, Witness};
+use lightning::chain::channelmonitor::ANTI_REORG_DELAY;
+use lightning::chain::transaction::TransactionData;
+use lightning::chain::Listen;
+use lightning::events::bump_transaction::{Utxo, WalletSource};
+use lightning::ln::ChannelId;
+use lightning::util::logger::Logger;
+use lightning::util::persist::KVStore;
+use lightning::util::ser::{Readable, Writeable};
+use lightning::{impl_writeable_tlv_based, log_error};
+use lightning_persister::fs_store::FilesystemStore;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+pub(crate) const ONCHAIN_WALLET_FNAME: &str = "onchain_wallet";
+pub(crate) const FUNDING_LOCKS_FNAME: &str = "funding_locks";
+
+/// How many unused addresses past the last used one we watch on each keychain.
+const GAP_LIMIT: u32 = 20;
+
+/// We don't create change outputs smaller than this, adding the amount to the fee instead.
+const CHANGE_DUST_LIMIT_SATS: u64 = 546;
+
+// Transaction weights, in weight units, for estimating fees before signing.
+/// Version, locktime, input and output counts and the segwit marker and flag.
+const BASE_TX_WEIGHT: u64 = 42;
+/// An outpoint, empty script_sig, sequence and a witness of a signature and compressed pubkey.
+const P2WPKH_INPUT_WEIGHT: u64 = 272;
+/// A P2WPKH change output.
+const CHANGE_OUTPUT_WEIGHT: u64 = 124;
+/// The witness of a P2WPKH input, as `Utxo::satisfaction_weight` counts it.
+const P2WPKH_SATISFACTION_WEIGHT: u64 = 4 /* empty script_sig */ + 1 /* witness items */ +
+	1 /* sig len */ + 73 /* sig */ + 1 /* pubkey len */ + 33 /* pubkey */;
+
+/// One of our outputs, which may since have been spent.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct WalletUtxo {
+	txout: TxOut,
+	change: bool,
+	index: u32,
+	/// The height of the block the output was created in, if it has confirmed.
+	confirmation_height: Option<u32>,
+	/// The transaction spending the output, once we've created or seen one.
+	spent_by: Option<Txid>,
+	/// The height of the block the spend confirmed in.
+	spent_height: Option<u32>,
+}
+
+impl_writeable_tlv_based!(WalletUtxo, {
+	(0, txout, required),
+	(2, change, required),
+	(4, index, required),
+	(6, confirmation_height, option),
+	(8, spent_by, option),
+	(10, spent_height, option),
+});
+
+/// Everything we persist about the wallet, the rest is derived from the seed.
+struct WalletState {
+	best_block_hash: BlockHash,
+	best_block_height: u32,
+	next_receive_index: u32,
+	next_change_index: u32,
+	utxos: HashMap<OutPoint, WalletUtxo>,
+}
+
+impl WalletState {
+	/// Notes that an address has been used, as addresses may be used out of order, so that we
+	/// look further ahead past it.
+	fn mark_used(&mut self, change: bool, index: u32) {
+		let next_index = match change {
+			false => &mut self.next_receive_index,
+			true => &mut self.next_change_index,
+		};
+		*next_index = (*next_index).max(index + 1);
+	}
+}
+
+impl_writeable_tlv_based!(WalletState, {
+	(0, best_block_hash, required),
+	(2, best_block_height, required),
+	(4, next_receive_index, required),
+	(6, next_change_index, required),
+	(8, utxos, required),
+});
+
+struct WalletInner {
+	state: WalletState,
+	/// The scripts we watch for, up to `GAP_LIMIT` past the next index of each keychain.
+	scripts: HashMap<ScriptBuf, (bool, u32)>,
+	/// How far each keychain's scripts have been derived into `scripts`.
+	derived_receive: u32,
+	derived_change: u32,
+}
+
+/// Our balance, in satoshis.
+pub(crate) struct Balance {
+	/// Unspent outputs which have confirmed.
+	pub(crate) confirmed_sats: u64,
+	/// Unspent outputs which haven't confirmed yet, e.g. change from our own transactions.
+	pub(crate) unconfirmed_sats: u64,
+}
+
+/// An unspent output of either wallet, as listed by `listunspent`.
+pub(crate) struct UnspentOutput {
+	pub(crate) outpoint: OutPoint,
+	pub(crate) address: Address,
+	pub(crate) amount_sats: u64,
+	/// Zero if the output hasn't confirmed yet.
+	pub(crate) confirmations: u32,
+}
+
+/// A BIP 84 wallet, which syncs as a [`Listen`]er alongside the `ChannelManager`, or from the
+/// history of its scripts with [`OnchainWallet::sync_history`].
+pub(crate) struct OnchainWallet {
+	secp_ctx: Secp256k1<All>,
+	network: Network,
+	master_fingerprint: bitcoin::bip32::Fingerprint,
+	account_path: DerivationPath,
+	account_key: ExtendedPrivKey,
+	inner: Mutex<WalletInner>,
+	broadcaster: Arc<DynBroadcaster>,
+	fs_store: Arc<FilesystemStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl OnchainWallet {
+	/// Loads the wallet from `fs_store`, or creates an empty one which starts watching the chain
+	/// at the given best block.
+	pub(crate) fn new(
+		keys_seed: &[u8; 32], network: Network, best_block_hash: BlockHash, best_block_height: u32,
+		broadcaster: Arc<DynBroadcaster>, fs_store: Arc<FilesystemStore>,
+		logger: Arc<FilesystemLogger>,
+	) -> Result<Self, String> {
+		let state = match fs_store.read("", "", ONCHAIN_WALLET_FNAME) {
+			Ok(bytes) => WalletState::read(&mut io::Cursor::new(bytes))
+				.map_err(|e| format!("failed to read the on-chain wallet: {:?}", e))?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => WalletState {
+				best_block_hash,
+				best_block_height,
+				next_receive_index: 0,
+				next_change_index: 0,
+				utxos: HashMap::new(),
+			},
+			Err(e) => return Err(format!("failed to read the on-chain wallet: {}", e)),
+		};
+
+		let secp_ctx = Secp256k1::new();
+		let master_key =
+			ExtendedPrivKey::new_master(network, keys_seed).map_err(|e| e.to_string())?;
+		let coin_type = if network == Network::Bitcoin { 0 } else { 1 };
+		let account_path = DerivationPath::from_str(&format!("m/84'/{}'/0'", coin_type)).unwrap();
+		let account_key =
+			master_key.derive_priv(&secp_ctx, &account_path).map_err(|e| e.to_string())?;
+		let wallet = Self {
+			master_fingerprint: master_key.fingerprint(&secp_ctx),
+			secp_ctx,
+			network,
+			account_path,
+			account_key,
+			inner: Mutex::new(WalletInner {
+				state,
+				scripts: HashMap::new(),
+				derived_receive: 0,
+				derived_change: 0,
+			}),
+			broadcaster,
+			fs_store,
+			logger,
+		};
+		wallet.derive_scripts(&mut wallet.inner.lock().unwrap());
+		Ok(wallet)
+	}
+
+	fn derive_key(&self, change: bool, index: u32) -> (SecretKey, PublicKey) {
+		let path = [
+			ChildNumber::from_normal_idx(change as u32).unwrap(),
+			ChildNumber::from_normal_idx(index).unwrap(),
+		];
+		let secret_key = self.account_key.derive_priv(&self.secp_ctx, &path).unwrap().private_key;
+		(secret_key, PublicKey::new(secret_key.public_key(&self.secp_ctx)))
+	}
+
+	fn script_pubkey(&self, change: bool, index: u32) -> ScriptBuf {
+		let (_, public_key) = self.derive_key(change, index);
+		ScriptBuf::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap())
+	}
+
+	/// Makes sure we watch `GAP_LIMIT` scripts past the next index of each keychain.
+	fn derive_scripts(&self, inner: &mut WalletInner) {
+		while inner.derived_receive < inner.state.next_receive_index + GAP_LIMIT {
+			let index = inner.derived_receive;
+			inner.scripts.insert(self.script_pubkey(false, index), (false, index));
+			inner.derived_receive += 1;
+		}
+		while inner.derived_change < inner.state.next_change_index + GAP_LIMIT {
+			let index = inner.derived_change;
+			inner.scripts.insert(self.script_pubkey(true, index), (true, index));
+			inner.derived_change += 1;
+		}
+	}
+
+	fn persist(&self, state: &WalletState) {
+		if let Err(e) = self.fs_store.write("", "", ONCHAIN_WALLET_FNAME, &state.encode()) {
+			log_error!(self.logger, "Failed to persist the on-chain wallet: {}", e);
+		}
+	}
+
+	/// The block we've synced the wallet up to.
+	pub(crate) fn best_block_hash(&self) -> BlockHash {
+		self.inner.lock().unwrap().state.best_block_hash
+	}
+
+	/// Returns a fresh receive address.
+	pub(crate) fn get_new_address(&self) -> Address {
+		let mut inner = self.inner.lock().unwrap();
+		let index = inner.state.next_receive_index;
+		inner.state.next_receive_index += 1;
+		self.derive_scripts(&mut inner);
+		self.persist(&inner.state);
+		let (_, public_key) = self.derive_key(false, index);
+		Address::p2wpkh(&public_key, self.network).unwrap()
+	}
+
+	fn get_change_script_locked(&self, inner: &mut WalletInner) -> ScriptBuf {
+		let index = inner.state.next_change_index;
+		inner.state.next_change_index += 1;
+		self.derive_scripts(inner);
+		self.script_pubkey(true, index)
+	}
+
+	/// Watch-only output descriptors for the receive and change keychains, e.g. to import into
+	/// another wallet.
+	pub(crate) fn descriptors(&self) -> [String; 2] {
+		let account_xpub = ExtendedPubKey::from_priv(&self.secp_ctx, &self.account_key);
+		let origin =
+			self.account_path.to_string().replacen('m', &self.master_fingerprint.to_string(), 1);
+		let descriptor = |change: u32| {
+			let descriptor = format!("wpkh([{}]{}/{}/*)", origin, account_xpub, change);
+			format!("{}#{}", descriptor, descriptor_checksum(&descriptor))
+		};
+		[descriptor(0), descriptor(1)]
+	}
+
+	pub(crate) fn balance(&self) -> Balance {
+		let inner = self.inner.lock().unwrap();
+		let mut balance = Balance { confirmed_sats: 0, unconfirmed_sats: 0 };
+		for utxo in inner.state.utxos.values().filter(|utxo| utxo.spent_by.is_none()) {
+			match utxo.confirmation_height {
+				Some(_) => balance.confirmed_sats += utxo.txout.value,
+				None => balance.unconfirmed_sats += utxo.txout.value,
+			}
+		}
+		balance
+	}
+
+	/// Our unspent outputs, including unconfirmed ones, ordered by outpoint.
+	pub(crate) fn list_unspent(&self) -> Vec<UnspentOutput> {
+		let inner = self.inner.lock().unwrap();
+		let best_block_height = inner.state.best_block_height;
+		let mut utxos: Vec<_> = inner
+			.state
+			.utxos
+			.iter()
+			.filter(|(_, utxo)| utxo.spent_by.is_none())
+			.map(|(outpoint, utxo)| UnspentOutput {
+				outpoint: *outpoint,
+				address: Address::from_script(&utxo.txout.script_pubkey, self.network).unwrap(),
+				amount_sats: utxo.txout.value,
+				confirmations: match utxo.confirmation_height {
+					Some(height) => best_block_height + 1 - height,
+					None => 0,
+				},
+			})
+			.collect();
+		utxos.sort_by_key(|utxo| (utxo.outpoint.txid, utxo.outpoint.vout));
+		utxos
+	}
+
+	/// Funds, signs and reserves the inputs of a transaction paying to `outputs`, spending only
+	/// confirmed outputs. Any change goes to a fresh change address.
+	pub(crate) fn create_transaction(
+		&self, outputs: Vec<TxOut>, feerate_sat_per_kw: u32,
+	) -> Result<Transaction, String> {
+		let mut inner = self.inner.lock().unwrap();
+		let mut candidates: Vec<_> = inner
+			.state
+			.utxos
+			.iter()
+			.filter(|(_, utxo)| utxo.spent_by.is_none() && utxo.confirmation_height.is_some())
+			.map(|(outpoint, utxo)| (*outpoint, utxo.txout.value))
+			.collect();
+		// Spend the largest outputs first, so we need as few inputs as possible.
+		candidates.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.txid.cmp(&b.0.txid)));
+
+		let target_sats = outputs
+			.iter()
+			.try_fold(0u64, |sum, output| sum.checked_add(output.value))
+			.ok_or_else(|| "the outputs' total amount overflows".to_string())?;
+		let outputs_weight: u64 = outputs.iter().map(txout_weight).sum();
+		let fee = |num_inputs: usize, with_change: bool| {
+			let weight = BASE_TX_WEIGHT
+				+ outputs_weight
+				+ num_inputs as u64 * P2WPKH_INPUT_WEIGHT
+				+ if with_change { CHANGE_OUTPUT_WEIGHT } else { 0 };
+			(weight * feerate_sat_per_kw as u64 + 999) / 1000
+		};
+
+		let mut inputs = Vec::new();
+		let mut input_sats = 0;
+		let mut change_sats = None;
+		for (outpoint, value) in candidates {
+			inputs.push(outpoint);
+			input_sats += value;
+			// An amount near u64::MAX can't be funded, even if adding the fee overflows.
+			match target_sats.checked_add(fee(inputs.len(), false)) {
+				Some(needed_sats) if input_sats >= needed_sats => {}
+				_ => continue,
+			}
+			let needed_sats = target_sats.saturating_add(fee(inputs.len(), true));
+			let change = input_sats.saturating_sub(needed_sats);
+			change_sats = Some(if change >= CHANGE_DUST_LIMIT_SATS { change } else { 0 });
+			break;
+		}
+		let change_sats = change_sats.ok_or_else(|| {
+			format!(
+				"insufficient confirmed funds: need {} sats plus fees, have {} sats",
+				target_sats, input_sats
+			)
+		})?;
+
+		let mut tx = Transaction {
+			version: 2,
+			// Discourage fee sniping by only allowing the transaction in the next block.
+			lock_time: LockTime::from_height(inner.state.best_block_height)
+				.unwrap_or(LockTime::ZERO),
+			input: inputs
+				.iter()
+				.map(|outpoint| TxIn {
+					previous_output: *outpoint,
+					script_sig: ScriptBuf::new(),
+					sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+					witness: Witness::new(),
+				})
+				.collect(),
+			output: outputs,
+		};
+		let change_output = if change_sats > 0 {
+			let script_pubkey = self.get_change_script_locked(&mut inner);
+			tx.output.push(TxOut { value: change_sats, script_pubkey });
+			Some(tx.output.len() - 1)
+		} else {
+			None
+		};
+		self.sign_inputs(&inner, &mut tx)?;
+
+		// Reserve the inputs and track the change, so they aren't spent twice before the transaction
+		// confirms.
+		let txid = tx.txid();
+		for outpoint in &inputs {
+			inner.state.utxos.get_mut(outpoint).unwrap().spent_by = Some(txid);
+		}
+		if let Some(vout) = change_output {
+			let txout = tx.output[vout].clone();
+			let (change, index) = inner.scripts[&txout.script_pubkey];
+			let outpoint = OutPoint { txid, vout: vout as u32 };
+			let utxo = WalletUtxo {
+				txout,
+				change,
+				index,
+				confirmation_height: None,
+				spent_by: None,
+				spent_height: None,
+			};
+			inner.state.utxos.insert(outpoint, utxo);
+		}
+		self.persist(&inner.state);
+		Ok(tx)
+	}
+
+	/// Creates a transaction paying `amount_sats` to `address` and broadcasts it.
+	pub(crate) fn send_to_address(
+		&self, address: &Address, amount_sats: u64, feerate_sat_per_kw: u32,
+	) -> Result<Txid, String> {
+		let output = TxOut { value: amount_sats, script_pubkey: address.script_pubkey() };
+		let tx = self.create_transaction(vec![output], feerate_sat_per_kw)?;
+		self.broadcaster.broadcast_transactions(&[&tx]);
+		Ok(tx.txid())
+	}
+
+	/// Releases the inputs reserved for a transaction we created which won't be broadcast after
+	/// all, and forgets its change.
+	pub(crate) fn release_transaction(&self, tx: &Transaction) {
+		let mut inner = self.inner.lock().unwrap();
+		let txid = tx.txid();
+		for input in tx.input.iter() {
+			if let Some(utxo) = inner.state.utxos.get_mut(&input.previous_output) {
+				if utxo.spent_by == Some(txid) && utxo.spent_height.is_none() {
+					utxo.spent_by = None;
+				}
+			}
+		}
+		inner
+			.state
+			.utxos
+			.retain(|outpoint, utxo| outpoint.txid != txid || utxo.confirmation_height.is_some());
+		self.persist(&inner.state);
+	}
+
+	/// The scripts whose history [`OnchainWallet::sync_history`] needs.
+	pub(crate) fn watched_scripts(&self) -> Vec<ScriptBuf> {
+		self.inner.lock().unwrap().scripts.keys().cloned().collect()
+	}
+
+	/// Syncs the wallet from `history`, every transaction paying to or spending from one of the
+	/// `watched_scripts` with the height of the block it confirmed in, as of the chain tip at
+	/// `tip_height`. Transactions we created which aren't in `history` (yet) keep their inputs
+	/// reserved, as when syncing from blocks.
+	///
+	/// Returns whether we watch more scripts since, whose history has to be fetched for another
+	/// sync.
+	pub(crate) fn sync_history(
+		&self, history: &[(Transaction, Option<u32>)], tip_hash: BlockHash, tip_height: u32,
+	) -> bool {
+		let mut inner = self.inner.lock().unwrap();
+		let inner = &mut *inner;
+		let watched_scripts = inner.scripts.len();
+		// Transactions which confirmed after the tip we know of count as unconfirmed for now.
+		let confirmed_height = |height: &Option<u32>| height.filter(|height| *height <= tip_height);
+
+		let mut utxos = HashMap::new();
+		for (tx, height) in history {
+			let txid = tx.txid();
+			for (vout, output) in tx.output.iter().enumerate() {
+				let (change, index) = match inner.scripts.get(&output.script_pubkey) {
+					Some(keychain_index) => *keychain_index,
+					None => continue,
+				};
+				let utxo = WalletUtxo {
+					txout: output.clone(),
+					change,
+					index,
+					confirmation_height: confirmed_height(height),
+					spent_by: None,
+					spent_height: None,
+				};
+				utxos.insert(OutPoint { txid, vout: vout as u32 }, utxo);
+				inner.state.mark_used(change, index);
+			}
+		}
+		for (tx, height) in history {
+			for input in tx.input.iter() {
+				if let Some(utxo) = utxos.get_mut(&input.previous_output) {
+					utxo.spent_by = Some(tx.txid());
+					utxo.spent_height = confirmed_height(height);
+				}
+			}
+		}
+
+		let seen_txids: HashSet<Txid> = history.iter().map(|(tx, _)| tx.txid()).collect();
+		for (outpoint, utxo) in inner.state.utxos.iter() {
+			let unseen_spend = utxo.spent_by.filter(|txid| !seen_txids.contains(txid));
+			if let (Some(spent_by), Some(new_utxo)) = (unseen_spend, utxos.get_mut(outpoint)) {
+				if new_utxo.spent_by.is_none() {
+					new_utxo.spent_by = Some(spent_by);
+				}
+			}
+			if !seen_txids.contains(&outpoint.txid) && utxo.confirmation_height.is_none() {
+				utxos.entry(*outpoint).or_insert_with(|| utxo.clone());
+			}
+		}
+
+		// Forget outputs whose spend is buried deeply enough that it won't be reorged out.
+		utxos.retain(|_, utxo| match utxo.spent_height {
+			Some(spent_height) => spent_height + ANTI_REORG_DELAY > tip_height,
+			None => true,
+		});
+		inner.state.utxos = utxos;
+		inner.state.best_block_hash = tip_hash;
+		inner.state.best_block_height = tip_height;
+		self.derive_scripts(inner);
+		self.persist(&inner.state);
+		inner.scripts.len() > watched_scripts
+	}
+
+	/// Gives up on an unconfirmed transaction we created, e.g. one which was never relayed, making
+	/// its inputs spendable again and forgetting its change. Should it confirm after all, we'll
+	/// learn about it from the block like about any other spend.
+	pub(crate) fn abandon_transaction(&self, txid: &Txid) -> Result<(), String> {
+		let mut inner = self.inner.lock().unwrap();
+		let inputs: Vec<&WalletUtxo> =
+			inner.state.utxos.values().filter(|utxo| utxo.spent_by == Some(*txid)).collect();
+		let outputs: Vec<&WalletUtxo> =
+			inner.state.utxos.iter().filter(|(o, _)| o.txid == *txid).map(|(_, u)| u).collect();
+		if inputs.is_empty() && outputs.is_empty() {
+			return Err(format!("transaction {} doesn't spend from the wallet", txid));
+		}
+		let confirmed = inputs.iter().any(|utxo| utxo.spent_height.is_some())
+			|| outputs.iter().any(|utxo| utxo.confirmation_height.is_some());
+		if confirmed {
+			return Err(format!("transaction {} has confirmed", txid));
+		}
+		if outputs.iter().any(|utxo| utxo.spent_by.is_some()) {
+			return Err(format!(
+				"the change of transaction {} is spent, abandon the spending transaction first",
+				txid
+			));
+		}
+		for utxo in inner.state.utxos.values_mut() {
+			if utxo.spent_by == Some(*txid) {
+				utxo.spent_by = None;
+			}
+		}
+		inner.state.utxos.retain(|outpoint, _| outpoint.txid != *txid);
+		self.persist(&inner.state);
+		Ok(())
+	}
+
+	/// Signs each input of `tx` spending one of our outputs, leaving the others untouched.
+	fn sign_inputs(&self, inner: &WalletInner, tx: &mut Transaction) -> Result<(), String> {
+		let mut witnesses = Vec::new();
+		let prevouts: Vec<OutPoint> = tx.input.iter().map(|input| input.previous_output).collect();
+		let mut sighash_cache = SighashCache::new(&*tx);
+		for (input_idx, prevout) in prevouts.iter().enumerate() {
+			let utxo = match inner.state.utxos.get(prevout) {
+				Some(utxo) => utxo,
+				None => continue,
+			};
+			let (secret_key, public_key) = self.derive_key(utxo.change, utxo.index);
+			let script_code = utxo.txout.script_pubkey.p2wpkh_script_code().unwrap();
+			let sighash = sighash_cache
+				.segwit_signature_hash(
+					input_idx,
+					&script_code,
+					utxo.txout.value,
+					EcdsaSighashType::All,
+				)
+				.map_err(|e| e.to_string())?;
+			let message = Message::from_slice(&sighash[..]).unwrap();
+			let mut signature =
+				self.secp_ctx.sign_ecdsa(&message, &secret_key).serialize_der().to_vec();
+			signature.push(EcdsaSighashType::All as u8);
+			witnesses.push((input_idx, Witness::from_slice(&[signature, public_key.to_bytes()])));
+		}
+		for (input_idx, witness) in witnesses {
+			tx.input[input_idx].witness = witness;
+		}
+		Ok(())
+	}
+}
+
+fn txout_weight(txout: &TxOut) -> u64 {
+	(encode::serialize(txout).len() * 4) as u64
+}
+
+impl Listen for OnchainWallet {
+	fn filtered_block_connected(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		let mut inner = self.inner.lock().unwrap();
+		let inner = &mut *inner;
+		for (_, tx) in txdata.iter() {
+			let txid = tx.txid();
+			for input in tx.input.iter() {
+				if let Some(utxo) = inner.state.utxos.get_mut(&input.previous_output) {
+					utxo.spent_by = Some(txid);
+					utxo.spent_height = Some(height);
+				}
+			}
+			for (vout, output) in tx.output.iter().enumerate() {
+				let (change, index) = match inner.scripts.get(&output.script_pubkey) {
+					Some(keychain_index) => *keychain_index,
+					None => continue,
+				};
+				let outpoint = OutPoint { txid, vout: vout as u32 };
+				let utxo = inner.state.utxos.entry(outpoint).or_insert_with(|| WalletUtxo {
+					txout: output.clone(),
+					change,
+					index,
+					confirmation_height: None,
+					spent_by: None,
+					spent_height: None,
+				});
+				utxo.confirmation_height = Some(height);
+				inner.state.mark_used(change, index);
+			}
+		}
+		self.derive_scripts(inner);
+
+		// Forget outputs whose spend is buried deeply enough that it won't be reorged out.
+		inner.state.utxos.retain(|_, utxo| match utxo.spent_height {
+			Some(spent_height) => spent_height + ANTI_REORG_DELAY > height,
+			None => true,
+		});
+		inner.state.best_block_hash = header.block_hash();
+		inner.state.best_block_height = height;
+		self.persist(&inner.state);
+	}
+
+	fn block_disconnected(&self, header: &Header, height: u32) {
+		let mut inner = self.inner.lock().unwrap();
+		for utxo in inner.state.utxos.values_mut() {
+			if utxo.confirmation_height == Some(height) {
+				utxo.confirmation_height = None;
+			}
+			if utxo.spent_height == Some(height) {
+				// The spend is most likely back in the mempool, so keep the output reserved.
+				utxo.spent_height = None;
+			}
+		}
+		inner.state.best_block_hash = header.prev_blockhash;
+		inner.state.best_block_height = height - 1;
+		self.persist(&inner.state);
+	}
+}
+
+impl WalletSource for OnchainWallet {
+	fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
+		let inner = self.inner.lock().unwrap();
+		Ok(inner
+			.state
+			.utxos
+			.iter()
+			.filter(|(_, utxo)| utxo.spent_by.is_none() && utxo.confirmation_height.is_some())
+			.map(|(outpoint, utxo)| Utxo {
+				outpoint: *outpoint,
+				output: utxo.txout.clone(),
+				satisfaction_weight: P2WPKH_SATISFACTION_WEIGHT,
+			})
+			.collect())
+	}
+
+	fn get_change_script(&self) -> Result<ScriptBuf, ()> {
+		let mut inner = self.inner.lock().unwrap();
+		let script = self.get_change_script_locked(&mut inner);
+		self.persist(&inner.state);
+		Ok(script)
+	}
+
+	fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> Result<Transaction, ()> {
+		let inner = self.inner.lock().unwrap();
+		let mut tx = psbt.extract_tx();
+		self.sign_inputs(&inner, &mut tx)
+			.map_err(|e| log_error!(self.logger, "Failed to sign a transaction: {}", e))?;
+		Ok(tx)
+	}
+}
+
+/// The on-chain wallet we fund channels from, sweep to and bump anchor transactions with.
+pub(crate) enum WalletBackend {
+	/// bitcoind's wallet, via RPC.
+	Bitcoind(Arc<BitcoindClient>),
+	/// Our own wallet, derived from the keys seed.
+	Internal(Arc<OnchainWallet>),
+}
+
+impl WalletBackend {
+	pub(crate) async fn get_new_address(&self) -> io::Result<Address> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => bitcoind_client.get_new_address().await,
+			WalletBackend::Internal(wallet) => Ok(wallet.get_new_address()),
+		}
+	}
+
+	/// Our balance, where bitcoind's also includes outputs it didn't create, as long as they're
+	/// spendable.
+	pub(crate) async fn balance(&self) -> io::Result<Balance> {
+		if let WalletBackend::Internal(wallet) = self {
+			return Ok(wallet.balance());
+		}
+		let mut balance = Balance { confirmed_sats: 0, unconfirmed_sats: 0 };
+		for utxo in self.list_unspent().await? {
+			match utxo.confirmations {
+				0 => balance.unconfirmed_sats += utxo.amount_sats,
+				_ => balance.confirmed_sats += utxo.amount_sats,
+			}
+		}
+		Ok(balance)
+	}
+
+	pub(crate) async fn list_unspent(&self) -> io::Result<Vec<UnspentOutput>> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => Ok(bitcoind_client
+				.list_unspent(0)
+				.await?
+				.0
+				.into_iter()
+				.map(|utxo| UnspentOutput {
+					outpoint: OutPoint { txid: utxo.txid, vout: utxo.vout },
+					address: utxo.address,
+					amount_sats: utxo.amount,
+					confirmations: utxo.confirmations,
+				})
+				.collect()),
+			WalletBackend::Internal(wallet) => Ok(wallet.list_unspent()),
+		}
+	}
+
+	/// Pays `amount_sats` to `address` and broadcasts the transaction.
+	pub(crate) async fn send_to_address(
+		&self, address: &Address, amount_sats: u64, feerate_sat_per_kw: u32,
+	) -> io::Result<Txid> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => {
+				let output = TxOut { value: amount_sats, script_pubkey: address.script_pubkey() };
+				let tx = self.create_transaction(vec![output], feerate_sat_per_kw).await?;
+				let raw_tx = RawTx(hex_utils::hex_str(&encode::serialize(&tx)));
+				let res = bitcoind_client.send_raw_transaction(raw_tx).await;
+				if res.is_err() {
+					// Don't leave the inputs locked for a transaction which went nowhere.
+					let _ = self.unlock_inputs(&tx).await;
+				}
+				res
+			}
+			WalletBackend::Internal(wallet) => wallet
+				.send_to_address(address, amount_sats, feerate_sat_per_kw)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+		}
+	}
+
+	/// Makes the inputs of a transaction from `create_transaction` spendable again, once it's
+	/// clear it won't be broadcast.
+	pub(crate) async fn unlock_inputs(&self, tx: &Transaction) -> io::Result<()> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => {
+				let outpoints: Vec<_> =
+					tx.input.iter().map(|input| input.previous_output).collect();
+				bitcoind_client.unlock_unspent(&outpoints).await
+			}
+			WalletBackend::Internal(wallet) => {
+				wallet.release_transaction(tx);
+				Ok(())
+			}
+		}
+	}
+
+	/// Gives up on an unconfirmed transaction which is stuck, so that its inputs can be spent again.
+	pub(crate) async fn abandon_transaction(&self, txid: &Txid) -> io::Result<()> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => {
+				bitcoind_client.abandon_transaction(txid).await
+			}
+			WalletBackend::Internal(wallet) => wallet
+				.abandon_transaction(txid)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+		}
+	}
+
+	/// Funds and signs a transaction paying to `outputs`, without broadcasting it.
+	pub(crate) async fn create_transaction(
+		&self, outputs: Vec<TxOut>, feerate_sat_per_kw: u32,
+	) -> io::Result<Transaction> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => {
+				let mut amounts = HashMap::with_capacity(outputs.len());
+				for output in outputs {
+					let address =
+						Address::from_script(&output.script_pubkey, bitcoind_client.network())
+							.map_err(|e| {
+								io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+							})?;
+					amounts.insert(address.to_string(), output.value as f64 / 100_000_000.0);
+				}
+				let raw_tx = bitcoind_client.create_raw_transaction(vec![amounts]).await?;
+
+				// Have bitcoind's wallet add inputs (and change) to pay for the outputs.
+				let funded_tx =
+					bitcoind_client.fund_raw_transaction(raw_tx, feerate_sat_per_kw).await?;
+
+				let signed_tx =
+					bitcoind_client.sign_raw_transaction_with_wallet(funded_tx.hex).await?;
+				if !signed_tx.complete {
+					return Err(io::Error::new(
+						io::ErrorKind::Other,
+						"bitcoind couldn't sign all inputs",
+					));
+				}
+				hex_utils::to_vec(&signed_tx.hex)
+					.and_then(|tx_bytes| encode::deserialize(&tx_bytes).ok())
+					.ok_or_else(|| {
+						io::Error::new(
+							io::ErrorKind::InvalidData,
+							"bitcoind returned an invalid transaction",
+						)
+					})
+			}
+			WalletBackend::Internal(wallet) => wallet
+				.create_transaction(outputs, feerate_sat_per_kw)
+				.map_err(|e| io::Error::new(io::ErrorKind::Other, e)),
+		}
+	}
+}
+
+struct FundingTransactions {
+	txs: HashMap<Txid, Transaction>,
+}
+
+impl_writeable_tlv_based!(FundingTransactions, {
+	(0, txs, required),
+});
+
+/// The funding transactions we've created whose inputs are locked in the wallet, until the
+/// channel is ready or LDK discards the funding. Persisted so that locks for channels which went
+/// away while we were offline can be released on startup.
+pub(crate) struct FundingLocks {
+	funding_txs: Mutex<FundingTransactions>,
+	fs_store: Arc<FilesystemStore>,
+	logger: Arc<FilesystemLogger>,
+}
+
+impl FundingLocks {
+	pub(crate) fn new(fs_store: Arc<FilesystemStore>, logger: Arc<FilesystemLogger>) -> Self {
+		let funding_txs = fs_store
+			.read("", "", FUNDING_LOCKS_FNAME)
+			.ok()
+			.and_then(|bytes| FundingTransactions::read(&mut io::Cursor::new(bytes)).ok())
+			.unwrap_or(FundingTransactions { txs: HashMap::new() });
+		Self { funding_txs: Mutex::new(funding_txs), fs_store, logger }
+	}
+
+	fn persist(&self, funding_txs: &FundingTransactions) {
+		if let Err(e) = self.fs_store.write("", "", FUNDING_LOCKS_FNAME, &funding_txs.encode()) {
+			log_error!(self.logger, "Failed to persist funding transaction locks: {}", e);
+		}
+	}
+
+	pub(crate) fn insert(&self, tx: &Transaction) {
+		let mut funding_txs = self.funding_txs.lock().unwrap();
+		funding_txs.txs.insert(tx.txid(), tx.clone());
+		self.persist(&funding_txs);
+	}
+
+	/// Stops tracking a funding transaction, returning it if we were.
+	pub(crate) fn remove(&self, txid: &Txid) -> Option<Transaction> {
+		let mut funding_txs = self.funding_txs.lock().unwrap();
+		let tx = funding_txs.txs.remove(txid);
+		if tx.is_some() {
+			self.persist(&funding_txs);
+		}
+		tx
+	}
+
+	pub(crate) fn pending(&self) -> Vec<Transaction> {
+		self.funding_txs.lock().unwrap().txs.values().cloned().collect()
+	}
+
+	/// Unlocks the inputs of a funding transaction which won't be broadcast, and stops tracking it.
+	///
+	/// Each channel of a failed batch discards the same transaction, so this does nothing if we're
+	/// no longer tracking it.
+	pub(crate) async fn release(&self, wallet: &WalletBackend, tx: &Transaction) {
+		if self.remove(&tx.txid()).is_none() {
+			return;
+		}
+		if let Err(e) = wallet.unlock_inputs(tx).await {
+			log_error!(
+				self.logger,
+				"Failed to unlock the inputs of funding transaction {}: {}",
+				tx.txid(),
+				e
+			);
+		}
+	}
+}
+
+/// A channel opened by `openchannels`, waiting on the rest of its batch.
+struct BatchChannel {
+	user_channel_id: u128,
+	counterparty_node_id: secp256k1::PublicKey,
+	temporary_channel_id: Option<ChannelId>,
+	funding_output: Option<TxOut>,
+}
+
+/// What to do with a channel whose funding output LDK just gave us.
+#[derive(Debug, PartialEq)]
+pub(crate) enum BatchFunding {
+	/// The channel isn't part of a batch, so it's funded on its own.
+	NotBatched,
+	/// Other channels in its batch are still being negotiated.
+	Pending,
+	/// Every channel in the batch is ready to be funded by one transaction paying `outputs`.
+	Ready { channels: Vec<(ChannelId, secp256k1::PublicKey)>, outputs: Vec<TxOut> },
+}
+
+/// Channels being opened together, which we fund with a single transaction once LDK has
+/// generated the funding outputs of all of them. Channels are identified by the
+/// `user_channel_id` we opened them with, as their temporary channel ids are only known once
+/// `create_channel` returns.
+///
+/// Batches aren't persisted: LDK forgets unfunded channels on restart anyway.
+pub(crate) struct FundingBatches {
+	batches: Mutex<Vec<Vec<BatchChannel>>>,
+}
+
+impl FundingBatches {
+	pub(crate) fn new() -> Self {
+		Self { batches: Mutex::new(Vec::new()) }
+	}
+
+	/// Starts tracking a batch. Must be called before any of its channels are created, so that
+	/// none of their `FundingGenerationReady` events are mistaken for a lone channel's.
+	pub(crate) fn register(&self, channels: &[(u128, secp256k1::PublicKey)]) {
+		let batch = channels
+			.iter()
+			.map(|(user_channel_id, counterparty_node_id)| BatchChannel {
+				user_channel_id: *user_channel_id,
+				counterparty_node_id: *counterparty_node_id,
+				temporary_channel_id: None,
+				funding_output: None,
+			})
+			.collect();
+		self.batches.lock().unwrap().push(batch);
+	}
+
+	/// Records the temporary channel id `create_channel` returned. Returns false if the batch has
+	/// been aborted in the meantime, in which case the channel should be closed.
+	pub(crate) fn channel_created(
+		&self, user_channel_id: u128, temporary_channel_id: ChannelId,
+	) -> bool {
+		let mut batches = self.batches.lock().unwrap();
+		match Self::find_channel(&mut batches, user_channel_id) {
+			Some(channel) => {
+				channel.temporary_channel_id = Some(temporary_channel_id);
+				true
+			}
+			None => false,
+		}
+	}
+
+	/// Records a channel's funding output, returning the whole batch once it is complete.
+	pub(crate) fn funding_ready(
+		&self, user_channel_id: u128, temporary_channel_id: ChannelId, funding_output: TxOut,
+	) -> BatchFunding {
+		let mut batches = self.batches.lock().unwrap();
+		match Self::find_channel(&mut batches, user_channel_id) {
+			Some(channel) => {
+				channel.temporary_channel_id = Some(temporary_channel_id);
+				channel.funding_output = Some(funding_output);
+			}
+			None => return BatchFunding::NotBatched,
+		}
+		let idx = Self::batch_index(&batches, user_channel_id).unwrap();
+		if batches[idx].iter().any(|channel| channel.funding_output.is_none()) {
+			return BatchFunding::Pending;
+		}
+		let (channels, outputs) = batches
+			.remove(idx)
+			.into_iter()
+			.map(|channel| {
+				(
+					(channel.temporary_channel_id.unwrap(), channel.counterparty_node_id),
+					channel.funding_output.unwrap(),
+				)
+			})
+			.unzip();
+		BatchFunding::Ready { channels, outputs }
+	}
+
+	/// Stops tracking the batch a channel belongs to, e.g. because the channel closed before it
+	/// could be funded. Returns the batch's other created channels, which will now never be funded
+	/// and should be closed.
+	pub(crate) fn abort(&self, user_channel_id: u128) -> Vec<(ChannelId, secp256k1::PublicKey)> {
+		let mut batches = self.batches.lock().unwrap();
+		let idx = match Self::batch_index(&batches, user_channel_id) {
+			Some(idx) => idx,
+			None => return Vec::new(),
+		};
+		batches
+			.remove(idx)
+			.into_iter()
+			.filter(|channel| channel.user_channel_id != user_channel_id)
+			.filter_map(|channel| {
+				channel.temporary_channel_id.map(|id| (id, channel.counterparty_node_id))
+			})
+			.collect()
+	}
+
+	fn batch_index(batches: &[Vec<BatchChannel>], user_channel_id: u128) -> Option<usize> {
+		batches.iter().position(|batch| {
+			batch.iter().any(|channel| channel.user_channel_id == user_channel_id)
+		})
+	}
+
+	fn find_channel(
+		batches: &mut [Vec<BatchChannel>], user_channel_id: u128,
+	) -> Option<&mut BatchChannel> {
+		batches
+			.iter_mut()
+			.flat_map(|batch| batch.iter_mut())
+			.find(|channel| channel.user_channel_id == user_channel_id)
+	}
+}
+
+impl Listen for WalletBackend {
+	fn filtered_block_connected(&self, header: &Header, txdata: &TransactionData, height: u32) {
+		if let WalletBackend::Internal(wallet) = self {
+			wallet.filtered_block_connected(header, txdata, height);
+		}
+	}
+
+	fn block_disconnected(&self, header: &Header, height: u32) {
+		if let WalletBackend::Internal(wallet) = self {
+			wallet.block_disconnected(header, height);
+		}
+	}
+}
+
+impl WalletSource for WalletBackend {
+	fn list_confirmed_utxos(&self) -> Result<Vec<Utxo>, ()> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => bitcoind_client.list_confirmed_utxos(),
+			WalletBackend::Internal(wallet) => wallet.list_confirmed_utxos(),
+		}
+	}
+
+	fn get_change_script(&self) -> Result<ScriptBuf, ()> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => bitcoind_client.get_change_script(),
+			WalletBackend::Internal(wallet) => wallet.get_change_script(),
+		}
+	}
+
+	fn sign_psbt(&self, psbt: PartiallySignedTransaction) -> Result<Transaction, ()> {
+		match self {
+			WalletBackend::Bitcoind(bitcoind_client) => bitcoind_client.sign_psbt(psbt),
+			WalletBackend::Internal(wallet) => wallet.sign_psbt(psbt),
+		}
+	}
+}
+
+/// Computes the BIP 380 checksum of an output descriptor.
+fn descriptor_checksum(descriptor: &str) -> String {
+	const INPUT_CHARSET: &str =
+		"0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+	const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+	const GENERATOR: [u64; 5] =
+		[0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+	let poly_mod = |c: u64, val: u64| {
+		let c0 = c >> 35;
+		let mut c = ((c & 0x7ffffffff) << 5) ^ val;
+		for (i, generator) in GENERATOR.iter().enumerate() {
+			if c0 & (1 << i) != 0 {
+				c ^= generator;
+			}
+		}
+		c
+	};
+
+	let (mut c, mut class, mut class_count) = (1, 0, 0);
+	for ch in descriptor.chars() {
+		let pos = INPUT_CHARSET.find(ch).expect("descriptors are ASCII") as u64;
+		c = poly_mod(c, pos & 31);
+		class = class * 3 + (pos >> 5);
+		class_count += 1;
+		if class_count == 3 {
+			c = poly_mod(c, class);
+			class = 0;
+			class_count = 0;
+		}
+	}
+	if class_count > 0 {
+		c = poly_mod(c, class);
+	}
+	for _ in 0..8 {
+		c = poly_mod(c, 0);
+	}
+	c ^= 1;
+	(0..8).map(|j| CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect()
+}
+
+#[cfg(test)]
+mod wallet_tests {
+	use super::*;
+	use bitcoin::blockdata::block::Version;
+	use bitcoin::hash_types::TxMerkleNode;
+	use bitcoin::hashes::Hash;
+	use bitcoin::pow::CompactTarget;
+	use bitcoin::secp256k1::ecdsa::Signature;
+	use lightning::chain::chaininterface::BroadcasterInterface;
+
+	struct TestBroadcaster(Mutex<Vec<Transaction>>);
+
+	impl BroadcasterInterface for TestBroadcaster {
+		fn broadcast_transactions(&self, txs: &[&Transaction]) {
+			self.0.lock().unwrap().extend(txs.iter().map(|tx| (*tx).clone()));
+		}
+	}
+
+	fn header(prev_blockhash: BlockHash, nonce: u32) -> Header {
+		Header {
+			version: Version::ONE,
+			prev_blockhash,
+			merkle_root: TxMerkleNode::all_zeros(),
+			time: 0,
+			bits: CompactTarget::from_consensus(0),
+			nonce,
+		}
+	}
+
+	fn paying_tx(script_pubkey: ScriptBuf, value: u64) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: LockTime::ZERO,
+			input: vec![TxIn::default()],
+			output: vec![TxOut { value, script_pubkey }],
+		}
+	}
+
+	struct TestWallet {
+		wallet: OnchainWallet,
+		broadcaster: Arc<TestBroadcaster>,
+		tip: Header,
+		height: u32,
+	}
+
+	impl TestWallet {
+		fn connect_block(&mut self, txs: &[&Transaction]) {
+			self.tip = header(self.tip.block_hash(), self.height + 1);
+			self.height += 1;
+			let txdata: Vec<_> = txs.iter().cloned().enumerate().collect();
+			self.wallet.filtered_block_connected(&self.tip, &txdata, self.height);
+		}
+	}
+
+	fn test_wallet(dir: &std::path::Path) -> TestWallet {
+		let fs_store = Arc::new(FilesystemStore::new(dir.to_path_buf()));
+		let logger = Arc::new(FilesystemLogger::new(dir.to_str().unwrap().to_string()));
+		let broadcaster = Arc::new(TestBroadcaster(Mutex::new(Vec::new())));
+		let tip = header(BlockHash::all_zeros(), 0);
+		let wallet = OnchainWallet::new(
+			&[42; 32],
+			Network::Regtest,
+			tip.block_hash(),
+			100,
+			Arc::clone(&broadcaster) as Arc<DynBroadcaster>,
+			fs_store,
+			logger,
+		)
+		.unwrap();
+		let height = wallet.inner.lock().unwrap().state.best_block_height;
+		TestWallet { wallet, broadcaster, tip, height }
+	}
+
+	#[test]
+	fn test_descriptor_checksum() {
+		assert_eq!(descriptor_checksum("raw(deadbeef)"), "89f8spxm");
+	}
+
+	#[test]
+	fn test_addresses() {
+		let dir = std::env::temp_dir().join(format!("ldk-wallet-addresses-{}", std::process::id()));
+		let test = test_wallet(&dir);
+		let first = test.wallet.get_new_address();
+		let second = test.wallet.get_new_address();
+		assert_ne!(first, second);
+		assert!(first.to_string().starts_with("bcrt1q"));
+		assert_eq!(first.script_pubkey(), test.wallet.script_pubkey(false, 0));
+		assert_ne!(test.wallet.script_pubkey(false, 0), test.wallet.script_pubkey(true, 0));
+
+		let [receive, change] = test.wallet.descriptors();
+		assert!(receive
+			.starts_with(&format!("wpkh([{}/84'/1'/0']tpub", test.wallet.master_fingerprint)));
+		assert!(receive.contains("/0/*)#"));
+		assert!(change.contains("/1/*)#"));
+
+		// We don't hand out the same address again after a restart.
+		let test = test_wallet(&dir);
+		let third = test.wallet.get_new_address();
+		assert_eq!(third.script_pubkey(), test.wallet.script_pubkey(false, 2));
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_sync_and_spend() {
+		let dir = std::env::temp_dir().join(format!("ldk-wallet-spend-{}", std::process::id()));
+		let mut test = test_wallet(&dir);
+		let address = test.wallet.get_new_address();
+		let funding_tx = paying_tx(address.script_pubkey(), 100_000);
+		// Outputs to addresses past the next one are found too, within the gap limit.
+		let gap_tx = paying_tx(test.wallet.script_pubkey(false, GAP_LIMIT - 1), 50_000);
+		test.connect_block(&[&funding_tx, &gap_tx]);
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (150_000, 0));
+		assert_eq!(test.wallet.list_unspent().len(), 2);
+		assert_eq!(test.wallet.inner.lock().unwrap().state.next_receive_index, GAP_LIMIT);
+
+		// Pay 120k sats at 1000 sat/KW, which needs both outputs and leaves change.
+		let destination = test.wallet.script_pubkey(false, 100);
+		let txid = test.wallet.send_to_address(
+			&Address::from_script(&destination, Network::Regtest).unwrap(),
+			120_000,
+			1000,
+		);
+		let txid = txid.unwrap();
+		let tx = test.broadcaster.0.lock().unwrap().pop().unwrap();
+		assert_eq!(tx.txid(), txid);
+		assert_eq!(tx.input.len(), 2);
+		assert_eq!(tx.output.len(), 2);
+		assert_eq!(tx.output[0], TxOut { value: 120_000, script_pubkey: destination });
+		let fee = 150_000 - 120_000 - tx.output[1].value;
+		// At 1000 sat/KW we pay a satoshi per weight unit.
+		let estimated_weight = BASE_TX_WEIGHT + 2 * P2WPKH_INPUT_WEIGHT + 2 * CHANGE_OUTPUT_WEIGHT;
+		assert_eq!(fee, estimated_weight);
+		assert!(tx.weight().to_wu() <= estimated_weight);
+
+		// Each input is signed by the key its output pays to.
+		let secp_ctx = Secp256k1::new();
+		let mut sighash_cache = SighashCache::new(&tx);
+		for (input_idx, prevout) in [&funding_tx, &gap_tx].iter().enumerate() {
+			let witness = &tx.input[input_idx].witness;
+			let public_key = PublicKey::from_slice(&witness.to_vec()[1]).unwrap();
+			let script_pubkey = ScriptBuf::new_v0_p2wpkh(&public_key.wpubkey_hash().unwrap());
+			assert_eq!(prevout.output[0].script_pubkey, script_pubkey);
+			let signature = witness.to_vec()[0].clone();
+			assert_eq!(*signature.last().unwrap(), EcdsaSighashType::All as u8);
+			let sighash = sighash_cache
+				.segwit_signature_hash(
+					input_idx,
+					&script_pubkey.p2wpkh_script_code().unwrap(),
+					prevout.output[0].value,
+					EcdsaSighashType::All,
+				)
+				.unwrap();
+			let message = Message::from_slice(&sighash[..]).unwrap();
+			let signature = Signature::from_der(&signature[..signature.len() - 1]).unwrap();
+			secp_ctx.verify_ecdsa(&message, &signature, &public_key.inner).unwrap();
+		}
+
+		// The inputs are reserved and the change shows up as unconfirmed.
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (0, tx.output[1].value));
+		assert!(test.wallet.create_transaction(vec![tx.output[0].clone()], 1000).is_err());
+
+		test.connect_block(&[&tx]);
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (tx.output[1].value, 0));
+		let tip = test.tip;
+		test.wallet.block_disconnected(&tip, test.height);
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (0, tx.output[1].value));
+		test.height -= 1;
+		test.tip = header(tip.prev_blockhash, 0);
+		test.connect_block(&[&tx]);
+
+		// Spent outputs are forgotten once their spend is buried, and the rest is persisted.
+		for _ in 0..ANTI_REORG_DELAY {
+			test.connect_block(&[]);
+		}
+		assert_eq!(test.wallet.inner.lock().unwrap().state.utxos.len(), 1);
+		let best_block_hash = test.wallet.best_block_hash();
+		let test = test_wallet(&dir);
+		assert_eq!(test.wallet.best_block_hash(), best_block_hash);
+		let utxos = test.wallet.list_unspent();
+		assert_eq!(utxos[0].outpoint, OutPoint { txid, vout: 1 });
+		assert_eq!(utxos[0].confirmations, ANTI_REORG_DELAY + 1);
+		assert_eq!(test.wallet.list_confirmed_utxos().unwrap().len(), 1);
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_sync_history() {
+		let dir = std::env::temp_dir().join(format!("ldk-wallet-history-{}", std::process::id()));
+		let test = test_wallet(&dir);
+		let tip_hash = test.tip.block_hash();
+		let received = paying_tx(test.wallet.script_pubkey(false, GAP_LIMIT - 1), 50_000);
+		// An address far past the ones we handed out is only found once we look ahead that far.
+		let far_script = test.wallet.script_pubkey(false, GAP_LIMIT + 5);
+		let received_unconfirmed = paying_tx(far_script.clone(), 30_000);
+		assert!(!test.wallet.watched_scripts().contains(&far_script));
+		let mut history = vec![(received.clone(), Some(101)), (received_unconfirmed, None)];
+		let mut syncs = 1;
+		while test.wallet.sync_history(&history, tip_hash, 101) {
+			syncs += 1;
+		}
+		assert_eq!(syncs, 3);
+		assert!(test.wallet.watched_scripts().contains(&far_script));
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (50_000, 30_000));
+
+		// Our own spend keeps its input reserved until the chain source sees it.
+		let output = TxOut { value: 20_000, script_pubkey: test.wallet.script_pubkey(false, 100) };
+		let tx = test.wallet.create_transaction(vec![output], 1000).unwrap();
+		let change_sats = tx.output[1].value;
+		assert!(!test.wallet.sync_history(&history, tip_hash, 101));
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (0, 30_000 + change_sats));
+
+		// Once the spend is buried deeply enough, the spent output is forgotten.
+		history.push((tx.clone(), Some(102)));
+		test.wallet.sync_history(&history, tip_hash, 102 + ANTI_REORG_DELAY);
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (change_sats, 30_000));
+		let spent_outpoint = OutPoint { txid: received.txid(), vout: 0 };
+		assert!(!test.wallet.inner.lock().unwrap().state.utxos.contains_key(&spent_outpoint));
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_release_transaction() {
+		let dir = std::env::temp_dir().join(format!("ldk-wallet-release-{}", std::process::id()));
+		let mut test = test_wallet(&dir);
+		let address = test.wallet.get_new_address();
+		let funding_tx = paying_tx(address.script_pubkey(), 100_000);
+		test.connect_block(&[&funding_tx]);
+
+		let output = TxOut { value: 40_000, script_pubkey: test.wallet.script_pubkey(false, 100) };
+		// Amounts which overflow once fees are added are simply unfundable.
+		let huge_output = TxOut { value: u64::MAX, ..output.clone() };
+		assert!(test.wallet.create_transaction(vec![huge_output.clone()], 1000).is_err());
+		assert!(test.wallet.create_transaction(vec![huge_output, output.clone()], 1000).is_err());
+
+		let tx = test.wallet.create_transaction(vec![output.clone()], 1000).unwrap();
+		assert_eq!(test.wallet.balance().confirmed_sats, 0);
+		assert!(test.wallet.create_transaction(vec![output.clone()], 1000).is_err());
+
+		// Once released, the input can be spent again and the change is forgotten.
+		test.wallet.release_transaction(&tx);
+		let balance = test.wallet.balance();
+		assert_eq!((balance.confirmed_sats, balance.unconfirmed_sats), (100_000, 0));
+		let stuck_tx = test.wallet.create_transaction(vec![output.clone()], 1000).unwrap();
+
+		// Abandoning a transaction does the same, but only works before it confirms.
+		test.wallet.abandon_transaction(&stuck_tx.txid()).unwrap();
+		assert!(test.wallet.abandon_transaction(&stuck_tx.txid()).is_err());
+		assert_eq!(test.wallet.balance().confirmed_sats, 100_000);
+		let tx = test.wallet.create_transaction(vec![output], 1000).unwrap();
+		test.connect_block(&[&tx]);
+		assert!(test.wallet.abandon_transaction(&tx.txid()).is_err());
+
+		let funding_locks =
+			FundingLocks::new(Arc::clone(&test.wallet.fs_store), Arc::clone(&test.wallet.logger));
+		funding_locks.insert(&tx);
+		let funding_locks =
+			FundingLocks::new(Arc::clone(&test.wallet.fs_store), Arc::clone(&test.wallet.logger));
+		assert_eq!(funding_locks.pending(), vec![tx.clone()]);
+		assert_eq!(funding_locks.remove(&tx.txid()), Some(tx.clone()));
+		assert_eq!(funding_locks.remove(&tx.txid()), None);
+		assert!(funding_locks.pending().is_empty());
+		std::fs::remove_dir_all(dir).unwrap();
+	}
+
+	#[test]
+	fn test_funding_batches() {
+		let secp_ctx = Secp256k1::new();
+		let node = |byte| {
+			secp256k1::PublicKey::from_secret_key(
+				&secp_ctx,
+				&SecretKey::from_slice(&[byte; 32]).unwrap(),
+			)
+		};
+		let output = |value| TxOut { value, script_pubkey: ScriptBuf::new() };
+		let batches = FundingBatches::new();
+
+		// Channels we didn't open as part of a batch are funded on their own.
+		assert_eq!(
+			batches.funding_ready(1, ChannelId([1; 32]), output(1_000)),
+			BatchFunding::NotBatched
+		);
+		assert!(!batches.channel_created(1, ChannelId([1; 32])));
+
+		// A batch is only funded once all of its channels are ready.
+		batches.register(&[(2, node(2)), (3, node(3))]);
+		assert!(batches.channel_created(2, ChannelId([2; 32])));
+		assert!(batches.channel_created(3, ChannelId([3; 32])));
+		assert_eq!(
+			batches.funding_ready(3, ChannelId([3; 32]), output(3_000)),
+			BatchFunding::Pending
+		);
+		assert_eq!(
+			batches.funding_ready(2, ChannelId([2; 32]), output(2_000)),
+			BatchFunding::Ready {
+				channels: vec![(ChannelId([2; 32]), node(2)), (ChannelId([3; 32]), node(3))],
+				outputs: vec![output(2_000), output(3_000)],
+			}
+		);
+		assert_eq!(
+			batches.funding_ready(2, ChannelId([2; 32]), output(2_000)),
+			BatchFunding::NotBatched
+		);
+
+		// When one channel of a batch fails, the others we created have to be closed.
+		batches.register(&[(4, node(4)), (5, node(5)), (6, node(6))]);
+		assert!(batches.channel_created(4, ChannelId([4; 32])));
+		assert_eq!(
+			batches.funding_ready(4, ChannelId([4; 32]), output(4_000)),
+			BatchFunding::Pending
+		);
+		assert!(batches.channel_created(5, ChannelId([5; 32])));
+		assert_eq!(batches.abort(5), vec![(ChannelId([4; 32]), node(4))]);
+		assert!(!batches.channel_created(6, ChannelId([6; 32])));
+		assert!(batches.abort(4).is_empty());
+	}
+}
@@ -0,0 +1,102 @@
+//! Block notifications from bitcoind's ZMQ interface (`zmqpubhashblock`), so we can react to new
+//! blocks right away instead of polling bitcoind's RPC interface every second.
+
+use crate::disk::FilesystemLogger;
+use lightning::util::logger::Logger;
+use lightning::{log_error, log_info};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use zeromq::{Socket, SocketRecv, SubSocket};
+
+/// How long we wait before reconnecting after losing the ZMQ connection.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How often we still poll bitcoind for a new chain tip when we get block notifications, in case
+/// a notification gets lost (ZMQ doesn't guarantee delivery).
+pub(crate) const FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Subscribes to the `hashblock` notifications published at `endpoint` (e.g.
+/// `tcp://127.0.0.1:28332`) in the background. The returned receiver is marked changed whenever a
+/// new block is announced.
+pub(crate) fn subscribe_blocks(
+	endpoint: String, logger: Arc<FilesystemLogger>,
+) -> watch::Receiver<()> {
+	let (sender, receiver) = watch::channel(());
+	tokio::spawn(async move {
+		loop {
+			if let Err(e) = receive_blocks(&endpoint, &sender, &logger).await {
+				log_error!(logger, "Lost ZMQ connection to {}: {}", endpoint, e);
+			}
+			if sender.is_closed() {
+				return;
+			}
+			tokio::time::sleep(RECONNECT_DELAY).await;
+		}
+	});
+	receiver
+}
+
+async fn receive_blocks(
+	endpoint: &str, sender: &watch::Sender<()>, logger: &FilesystemLogger,
+) -> zeromq::ZmqResult<()> {
+	let mut socket = SubSocket::new();
+	socket.connect(endpoint).await?;
+	socket.subscribe("hashblock").await?;
+	log_info!(logger, "Subscribed to bitcoind block notifications at {}", endpoint);
+	loop {
+		let message = socket.recv().await?;
+		// Messages are multipart: the topic, the block hash and a sequence number.
+		if is_hashblock(message.get(0).map(|topic| &topic[..])) && sender.send(()).is_err() {
+			// Nobody is listening anymore.
+			return Ok(());
+		}
+	}
+}
+
+fn is_hashblock(topic: Option<&[u8]>) -> bool {
+	topic == Some(b"hashblock")
+}
+
+/// Waits for the next block notification, or for `fallback` if we don't have notifications or
+/// didn't get one in time.
+pub(crate) async fn wait_for_block(
+	notifications: &mut Option<watch::Receiver<()>>, fallback: Duration,
+) {
+	match notifications {
+		Some(receiver) => {
+			let _ = tokio::time::timeout(fallback, receiver.changed()).await;
+		}
+		None => tokio::time::sleep(fallback).await,
+	}
+}
+
+#[cfg(test)]
+mod zmq_tests {
+	use super::*;
+
+	#[test]
+	fn test_is_hashblock() {
+		assert!(is_hashblock(Some(b"hashblock")));
+		assert!(!is_hashblock(Some(b"rawtx")));
+		assert!(!is_hashblock(None));
+	}
+
+	#[tokio::test]
+	async fn test_wait_for_block() {
+		let (sender, receiver) = watch::channel(());
+		let mut notifications = Some(receiver);
+		sender.send(()).unwrap();
+		// A pending notification returns right away rather than after the fallback.
+		tokio::time::timeout(
+			Duration::from_secs(5),
+			wait_for_block(&mut notifications, Duration::from_secs(60)),
+		)
+		.await
+		.unwrap();
+		// Without a notification, we wait for the fallback.
+		let start = std::time::Instant::now();
+		wait_for_block(&mut notifications, Duration::from_millis(50)).await;
+		assert!(start.elapsed() >= Duration::from_millis(50));
+	}
+}